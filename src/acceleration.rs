@@ -1,3 +1,6 @@
 pub mod aabb;
 pub mod bvh;
-pub mod grid;
\ No newline at end of file
+pub mod grid;
+pub mod kd_tree;
+pub mod octree;
+pub mod wide_bvh;
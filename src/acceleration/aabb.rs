@@ -19,6 +19,38 @@ impl AABB {
         Self { min, max }
     }
 
+    /// The smallest AABB containing all `points`
+    pub fn from_points(points: &[Point3]) -> Self {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for point in points {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(point[axis]);
+                max[axis] = max[axis].max(point[axis]);
+            }
+        }
+        Self::new(min, max)
+    }
+
+    /// An AABB that contains everything, used for unbounded objects
+    pub fn universe() -> Self {
+        Self::new(
+            Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        )
+    }
+
+    /// Makes sure no side is thinner than `delta`, to avoid degenerate boxes for flat objects
+    pub fn padded(mut self, delta: f64) -> Self {
+        for axis in 0..3 {
+            if self.max[axis] - self.min[axis] < delta {
+                self.min[axis] -= delta / 2.0;
+                self.max[axis] += delta / 2.0;
+            }
+        }
+        self
+    }
+
     fn axis_interval(&self, axis: u32) -> Interval {
         Interval::new(self.min[axis], self.max[axis])
     }
@@ -1,102 +1,405 @@
 use crate::acceleration::aabb::AABB;
 use crate::value::data::Data;
 use crate::hittable::{HitRecord, Hittable};
-use crate::hittable::hittable_list::objects_to_aabb;
 use crate::value::interval::Interval;
 use crate::value::ray::Ray;
-use crate::utils::rtweekend::AlgorithmOptions::{BvhNaive, BvhSahPlane};
+use crate::value::vec3::Point3;
+use crate::utils::rtweekend::AlgorithmOptions::{BvhHlbvh, BvhLbvh, BvhNaive, BvhOptimize, BvhSahBinned16, BvhSahBinned32, BvhSahPlane, BvhSbvh};
 use crate::utils::rtweekend::Options;
+use rayon::ThreadPoolBuilder;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tree::Tree;
+
+mod lbvh;
+mod sbvh;
+mod tree;
 
 /// BVH and AABB from course slides
+/// The nodes are stored depth first, see `BvhNode`
 pub struct Bvh {
     objects: Vec<Rc<dyn Hittable>>,
+    /// The index of every object in the objects given to `Bvh::new()`, used by `refit()`
+    indices: Vec<usize>,
     nodes: Vec<BvhNode>,
-    node_pointer: usize,
+    /// The SAH cost before `optimize()`
+    unoptimized_sah_cost: Option<f64>,
 }
 
+/// Nodes with fewer objects are split on the thread that splits their parent
+const PARALLEL_THRESHOLD: usize = 1024;
+
+/// Nodes at this depth are not split, so the traversal stack can have a fixed size
+pub const MAX_DEPTH: usize = 64;
+
+/// The part of the intermediate nodes that is reinserted in every pass of `optimize()`
+const REINSERT_FRACTION: f64 = 0.1;
+/// `optimize()` stops when a pass lowers the SAH cost by less than this fraction
+const MIN_IMPROVEMENT: f64 = 0.001;
+
 impl Bvh {
+    /// Builds the tree, subtrees are built in parallel on `options.threads` threads (or one per core)
+    /// The tree is the same for any number of threads
     pub fn new(objects: Vec<Rc<dyn Hittable>>, options: &Options) -> Self {
-        let nodes = vec![BvhNode::default(); objects.len() * 4];
-        let mut result = Self { objects, nodes, node_pointer: 0 };
-        let mut root = BvhNode::new_leaf(0, 0, result.objects.len(), &result.objects);
-        root.sub_divide(&mut result, options);
-        result.nodes[0] = root;
-        result
+        if objects.is_empty() { return Self { objects, indices: vec![], nodes: vec![], unoptimized_sah_cost: None }; }
+        assert!(objects.len() <= u32::MAX as usize / 2, "Too many objects for a BVH: {}", objects.len());
+
+        // The objects cannot be shared between threads, so the tree is built from their AABBs and centroids
+        // Spatial splits clip triangles, so they need the vertices
+        let spatial = options.options.contains(&BvhSbvh);
+        let mut references = objects.iter().enumerate()
+            .map(|(index, object)| Reference {
+                aabb: object.to_aabb(),
+                centroid: object.centroid(),
+                index,
+                triangle: object.as_triangle().filter(|_| spatial).map(|triangle| [triangle.a(), triangle.b(), triangle.c()]),
+            })
+            .collect::<Vec<_>>();
+        let budget = (objects.len() as f64 * sbvh::DUPLICATE_BUDGET) as usize;
+        let mut build = || {
+            if spatial {
+                return sbvh::build(references.clone(), budget);
+            }
+            if options.options.contains(&BvhLbvh) || options.options.contains(&BvhHlbvh) {
+                return lbvh::build(references.clone(), options.options.contains(&BvhHlbvh));
+            }
+            let root = BuildNode::new_leaf(0, 0, references.len(), &references);
+            let nodes = root.sub_divide(&mut references, options, 1);
+            (nodes, references.iter().map(|reference| reference.index).collect())
+        };
+        let (mut nodes, indices) = match options.threads {
+            Some(threads) => ThreadPoolBuilder::new().num_threads(threads).build()
+                .expect("Could not start the threads to build the BVH")
+                .install(build),
+            None => build(),
+        };
+        nodes.shrink_to_fit();
+        let objects = indices.iter().map(|&index| objects[index].clone()).collect();
+        let mut bvh = Self { objects, indices, nodes, unoptimized_sah_cost: None };
+        if options.options.contains(&BvhOptimize) {
+            let seconds = options.optimize_seconds.map(Duration::from_secs_f64);
+            bvh.optimize(options.optimize_iterations, seconds);
+        }
+        bvh
+    }
+
+    /// Lowers the SAH cost of the tree by taking out its most inefficient nodes and inserting them again where they
+    /// cost the least (Bittner et al. 2013). Every pass reinserts a part of the nodes, until a pass hardly improves
+    /// the tree or `iterations` passes or `time` have been used. The best tree is kept, so the tree never gets worse.
+    /// Returns the number of passes.
+    pub fn optimize(&mut self, iterations: Option<usize>, time: Option<Duration>) -> usize {
+        if self.nodes.len() < 3 {
+            return 0;
+        }
+        let start = Instant::now();
+        let initial_cost = self.sah_cost();
+        let mut tree = Tree::new(&self.nodes, self.boxes());
+        let objects = self.objects.iter().map(|object| object.to_aabb()).collect::<Vec<_>>();
+        tree.split_leaves(0, 1, &objects);
+        let mut best = (tree.sah_cost(), tree.clone());
+        let mut passes = 0;
+        while iterations.is_none_or(|iterations| passes < iterations) && time.is_none_or(|time| start.elapsed() < time) {
+            tree.reinsert(REINSERT_FRACTION);
+            passes += 1;
+            let cost = tree.sah_cost();
+            if cost >= best.0 {
+                break;
+            }
+            let improved = cost < best.0 * (1.0 - MIN_IMPROVEMENT);
+            best = (cost, tree.clone());
+            if !improved {
+                break;
+            }
+        }
+
+        let (nodes, objects, indices) = (self.nodes.clone(), self.objects.clone(), self.indices.clone());
+        self.set_tree(best.1, true);
+        // Leaves are collapsed again by a different rule than the builders use, which can cost more for a good tree
+        if self.sah_cost() > initial_cost {
+            (self.nodes, self.objects, self.indices) = (nodes, objects, indices);
+        }
+        self.unoptimized_sah_cost.get_or_insert(initial_cost);
+        passes
+    }
+
+    /// Replaces the nodes by the nodes of `tree`, the objects are put in the order of its leaves
+    fn set_tree(&mut self, tree: Tree, collapse: bool) {
+        let (nodes, order) = tree.into_nodes(collapse);
+        self.nodes = nodes;
+        self.objects = order.iter().map(|&index| self.objects[index].clone()).collect();
+        self.indices = order.iter().map(|&index| self.indices[index]).collect();
+    }
+
+    /// The SAH cost before `optimize()` changed the tree
+    pub fn unoptimized_sah_cost(&self) -> Option<f64> {
+        self.unoptimized_sah_cost
     }
 
-    pub fn root(&self) -> Option<&BvhNode> {
-        self.nodes.first()
+    /// The exact boxes of the nodes, computed from the objects
+    fn boxes(&self) -> Vec<AABB> {
+        // The children of a node come after it
+        let mut boxes = vec![AABB::default(); self.nodes.len()];
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            boxes[index] = if node.is_leaf() {
+                self.objects(node).iter().map(|object| object.to_aabb()).reduce(|aabb, other| aabb + other).unwrap_or_default()
+            } else {
+                &boxes[index + 1] + &boxes[node.right()]
+            };
+        }
+        boxes
+    }
+
+    /// The number of objects given to `Bvh::new()`
+    pub fn object_count(&self) -> usize {
+        self.indices.iter().max().map_or(0, |max| max + 1)
+    }
+
+    /// Updates the tree after the objects moved, `objects` are the objects given to `Bvh::new()` in their new place
+    /// The boxes of the nodes are recomputed from the leaves up, the structure of the tree stays the same. With `rotate`
+    /// the children and grandchildren of nodes are swapped where that lowers the SAH cost.
+    pub fn refit(&mut self, objects: &[Rc<dyn Hittable>], rotate: bool) {
+        assert_eq!(objects.len(), self.object_count(), "The BVH was built for other objects");
+        for (object, &index) in self.objects.iter_mut().zip(&self.indices) {
+            *object = objects[index].clone();
+        }
+
+        let boxes = self.boxes();
+        for (node, aabb) in self.nodes.iter_mut().zip(&boxes) {
+            *node = BvhNode::new(aabb, node.offset as usize, node.count());
+        }
+
+        if rotate && !self.nodes.is_empty() {
+            let mut tree = Tree::new(&self.nodes, boxes);
+            tree.rotate(0, 1);
+            self.set_tree(tree, false);
+        }
+    }
+
+    /// The nodes in depth first order, the root comes first
+    #[allow(dead_code)]
+    pub fn nodes(&self) -> &[BvhNode] {
+        &self.nodes
+    }
+
+    /// The objects in the order of the leaves, see `BvhNode::first()`
+    pub fn into_objects(self) -> Vec<Rc<dyn Hittable>> {
+        self.objects
+    }
+
+    pub fn objects(&self, node: &BvhNode) -> &[Rc<dyn Hittable>] {
+        &self.objects[node.first()..node.first() + node.count()]
+    }
+
+    /// The expected cost of tracing a ray through the tree, according to the surface area heuristic
+    /// The cost of each node is weighted by the chance that a ray through the root also passes through the node
+    pub fn sah_cost(&self) -> f64 {
+        let Some(root) = self.nodes.first() else { return 0.0 };
+        let root_area = root.aabb().surface_area();
+        self.nodes.iter().map(|node| {
+            let cost = if node.is_leaf() { node.count as f64 * INTERSECTION_COST } else { TRAVERSAL_COST };
+            node.aabb().surface_area() / root_area * cost
+        }).sum()
+    }
+
+    /// Finds the closest hit, the children of a node are visited nearest first
+    /// Nodes that still have to be visited are kept on a fixed size stack, with the distance to their AABB
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data, options: &Options) -> bool {
+        let Some(root) = self.nodes.first() else { return false };
+        let Some(root_distance) = root.hit_aabb(r, ray_t, rec, data, options) else { return false };
+
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+        let mut stack = [(0u32, 0.0); MAX_DEPTH];
+        stack[0] = (0, root_distance);
+        let mut size = 1;
+        while size > 0 {
+            size -= 1;
+            let (index, distance) = stack[size];
+            // Something closer was found after this node was pushed
+            if distance >= closest_so_far {
+                continue;
+            }
+            if hit_anything {
+                data.add_overlapping_aabb();
+            }
+            let mut index = index as usize;
+            loop {
+                data.add_traversal_step();
+                let node = &self.nodes[index];
+                if node.is_leaf() {
+                    for object in self.objects(node) {
+                        data.add_intersection_check();
+                        if object.hit(r, Interval::new(ray_t.min, closest_so_far), rec, data) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                        }
+                    }
+                    break;
+                }
+
+                let ray_t = Interval::new(ray_t.min, closest_so_far);
+                let (left, right) = (index + 1, node.right());
+                let left_distance = self.nodes[left].hit_aabb(r, ray_t, rec, data, options);
+                let right_distance = self.nodes[right].hit_aabb(r, ray_t, rec, data, options);
+                index = match (left_distance, right_distance) {
+                    (Some(left_distance), Some(right_distance)) => {
+                        let ((near, _), far) = if right_distance < left_distance {
+                            ((right, right_distance), (left, left_distance))
+                        } else {
+                            ((left, left_distance), (right, right_distance))
+                        };
+                        stack[size] = (far.0 as u32, far.1);
+                        size += 1;
+                        near
+                    }
+                    (Some(_), None) => left,
+                    (None, Some(_)) => right,
+                    (None, None) => break,
+                };
+            }
+        }
+        hit_anything
     }
 }
 
-#[derive(Clone)]
-// TODO fit this in one cache line?
+/// The cost of visiting a node relative to intersecting an object, used by `Bvh::sah_cost()`
+pub const TRAVERSAL_COST: f64 = 1.0;
+pub const INTERSECTION_COST: f64 = 1.0;
+
+/// A node of the tree in 32 bytes, so two nodes fit in a cache line
+/// The bounds are rounded outwards to f32. The left child of an intermediate node is the next node,
+/// `offset` is the index of the right child. For a leaf `offset` is the index of its first object.
+#[derive(Clone, Debug, PartialEq)]
+#[repr(C)]
 pub struct BvhNode {
-    pub aabb: AABB,
-    pub is_leaf: bool,
-    pub left: usize,
-    pub right: usize,
-    pub first: usize,
-    pub count: usize,
+    min: [f32; 3],
+    max: [f32; 3],
+    offset: u32,
+    /// The number of objects in a leaf, 0 for an intermediate node
+    count: u32,
 }
 
-impl Default for BvhNode {
-    fn default() -> Self {
+impl BvhNode {
+    fn new(aabb: &AABB, offset: usize, count: usize) -> Self {
         Self {
-            aabb: Default::default(),
-            is_leaf: true,
-            left: Default::default(),
-            right: Default::default(),
-            first: Default::default(),
-            count: Default::default(),
+            min: [0, 1, 2].map(|axis| round_down(aabb.min[axis])),
+            max: [0, 1, 2].map(|axis| round_up(aabb.max[axis])),
+            offset: offset as u32,
+            count: count as u32,
         }
     }
-}
 
-impl BvhNode {
-    /// Create a new leaf node
-    /// `first` is relative to the provided `objects` array
-    /// `offset + first` will be the index into the corresponding `Bvh.objects`
-    pub fn new_leaf(first: usize, offset: usize, count: usize, objects: &[Rc<dyn Hittable>]) -> Self {
-        let aabb = objects_to_aabb(&objects[first..(first + count)]);
-        Self { aabb, is_leaf: true, first: offset + first, count, left: 0, right: 0 }
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
     }
 
-    pub fn objects<'a>(&'a self, bvh: &'a Bvh) -> &'a [Rc<dyn Hittable>] {
-        if !self.is_leaf { panic!("Cannot get objects for intermediate node") }
-        &bvh.objects[self.first..(self.first + self.count)]
+    /// The index of the right child, the left child is the next node
+    pub fn right(&self) -> usize {
+        if self.is_leaf() { panic!("Cannot get child tree for leaf node") }
+        self.offset as usize
     }
 
-    pub fn objects_mut<'a>(&'a self, bvh: &'a mut Bvh) -> &'a mut [Rc<dyn Hittable>] {
-        if !self.is_leaf { panic!("Cannot get objects for intermediate node") }
-        &mut bvh.objects[self.first..(self.first + self.count)]
+    /// The index of the first object of a leaf
+    pub fn first(&self) -> usize {
+        if !self.is_leaf() { panic!("Cannot get objects for intermediate node") }
+        self.offset as usize
     }
 
-    pub fn left<'a>(&'a self, bvh: &'a Bvh) -> &'a BvhNode {
-        if self.is_leaf { panic!("Cannot get child tree for leaf node") }
-        &bvh.nodes[self.left]
+    pub fn count(&self) -> usize {
+        self.count as usize
     }
 
-    pub fn right<'a>(&'a self, bvh: &'a Bvh) -> &'a BvhNode {
-        if self.is_leaf { panic!("Cannot get child tree for leaf node") }
-        &bvh.nodes[self.right]
+    /// The bounds as they are stored
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        (self.min, self.max)
     }
 
-    fn get_split(&self, objects: &mut [Rc<dyn Hittable>], options: &Options) -> Option<(BvhNode, BvhNode)> {
+    pub fn aabb(&self) -> AABB {
+        let point = |e: [f32; 3]| Point3::new(e[0] as f64, e[1] as f64, e[2] as f64);
+        AABB::new(point(self.min), point(self.max))
+    }
+
+    /// The node moved by `offset` in the list of nodes
+    fn offset(mut self, offset: usize) -> Self {
+        if !self.is_leaf() {
+            self.offset += offset as u32;
+        }
+        self
+    }
+
+    /// The node with its objects moved by `offset` in the list of objects
+    fn offset_objects(mut self, offset: usize) -> Self {
+        if self.is_leaf() {
+            self.offset += offset as u32;
+        }
+        self
+    }
+
+    pub fn hit_aabb(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data, options: &Options) -> Option<f64> {
+        data.add_intersection_check();
+        self.aabb().hit(r, ray_t, rec, options).map(|i|i.0.min)
+    }
+}
+
+/// The largest f32 that is not larger than `value`
+fn round_down(value: f64) -> f32 {
+    let rounded = value as f32;
+    if rounded as f64 > value { rounded.next_down() } else { rounded }
+}
+
+/// The smallest f32 that is not smaller than `value`
+fn round_up(value: f64) -> f32 {
+    let rounded = value as f32;
+    if (rounded as f64) < value { rounded.next_up() } else { rounded }
+}
+
+/// An object while the tree is built, `index` is its index in the objects given to `Bvh::new()`
+/// With spatial splits a reference can be part of an object, then `aabb` is the box of that part
+#[derive(Clone)]
+struct Reference {
+    aabb: AABB,
+    centroid: Point3,
+    index: usize,
+    /// The vertices of a triangle, only used by spatial splits
+    triangle: Option<[Point3; 3]>,
+}
+
+/// A leaf while the tree is built
+struct BuildNode {
+    aabb: AABB,
+    first: usize,
+    count: usize,
+}
+
+impl BuildNode {
+    /// Create a new leaf node
+    /// `first` is relative to the provided `objects` array
+    /// `offset + first` will be the index into the corresponding `Bvh.objects`
+    fn new_leaf(first: usize, offset: usize, count: usize, objects: &[Reference]) -> Self {
+        Self { aabb: bounds(&objects[first..(first + count)]), first: offset + first, count }
+    }
+
+    fn get_split(&self, objects: &mut [Reference], options: &Options) -> Option<(BuildNode, BuildNode)> {
         if options.options.contains(&BvhNaive) {
             if objects.len() < 3 { return None; }
             // Sort objects on this axis
             objects.sort_by(|a, b| {
-                let a = a.centroid().x();
-                let b = b.centroid().x();
+                let a = a.centroid.x();
+                let b = b.centroid.x();
                 f64::total_cmp(&a, &b)
             });
             let split = self.count / 2;
-            let left_node = BvhNode::new_leaf(0, self.first, split, objects);
-            let right_node = BvhNode::new_leaf(split, self.first, self.count - split, objects);
+            let left_node = BuildNode::new_leaf(0, self.first, split, objects);
+            let right_node = BuildNode::new_leaf(split, self.first, self.count - split, objects);
             return Some((left_node, right_node));
         }
+        if options.options.contains(&BvhSahBinned16) {
+            return self.get_split_binned(objects, 16);
+        }
+        if options.options.contains(&BvhSahBinned32) {
+            return self.get_split_binned(objects, 32);
+        }
 
         let mut best_heuristic = self.aabb.surface_area() * self.count as f64;
         let mut best_split = None;
@@ -106,8 +409,8 @@ impl BvhNode {
             // Sort objects on this axis
             let mut axis_objects = objects.to_vec();
             axis_objects.sort_by(|a, b| {
-                let a = a.centroid()[axis];
-                let b = b.centroid()[axis];
+                let a = a.centroid[axis];
+                let b = b.centroid[axis];
                 f64::total_cmp(&a, &b)
             });
 
@@ -134,21 +437,86 @@ impl BvhNode {
             }
         }
         objects.sort_by(|a, b| {
-            let a = a.centroid()[best_axis];
-            let b = b.centroid()[best_axis];
+            let a = a.centroid[best_axis];
+            let b = b.centroid[best_axis];
             f64::total_cmp(&a, &b)
         });
         best_split
     }
 
+    /// Binned SAH: the centroids are put in `bins` bins along each axis, only the planes between the bins are checked
+    /// Sweeping over the bins from both sides gives the AABBs of both halves for every plane,
+    /// so building a node takes linear time instead of sorting and rebuilding leaves for every position
+    fn get_split_binned(&self, objects: &mut [Reference], bins: usize) -> Option<(BuildNode, BuildNode)> {
+        let centroids = objects.iter().map(|object| object.centroid).collect::<Vec<_>>();
+        let bounds = AABB::from_points(&centroids);
+        let bin = |centroid: &Point3, axis: u32| {
+            let extent = bounds.max[axis] - bounds.min[axis];
+            (((centroid[axis] - bounds.min[axis]) / extent * bins as f64) as usize).min(bins - 1)
+        };
+
+        let mut best_heuristic = self.aabb.surface_area() * self.count as f64;
+        let mut best_split = None;
+        for axis in 0..3 {
+            if bounds.max[axis] - bounds.min[axis] <= 0.0 {
+                continue;
+            }
+            let mut boxes: Vec<Option<AABB>> = vec![None; bins];
+            let mut counts = vec![0; bins];
+            for object in objects.iter() {
+                let index = bin(&object.centroid, axis);
+                boxes[index] = Some(merge(boxes[index].take(), object.aabb.clone()));
+                counts[index] += 1;
+            }
+
+            // The right half of plane `i` holds bins `i..`
+            let mut right = vec![(0.0, 0); bins];
+            let mut aabb = None;
+            let mut count = 0;
+            for i in (1..bins).rev() {
+                if let Some(bin_aabb) = &boxes[i] { aabb = Some(merge(aabb, bin_aabb.clone())); }
+                count += counts[i];
+                right[i] = (aabb.as_ref().map_or(0.0, AABB::surface_area), count);
+            }
+            let mut aabb = None;
+            let mut count = 0;
+            for i in 1..bins {
+                if let Some(bin_aabb) = &boxes[i - 1] { aabb = Some(merge(aabb, bin_aabb.clone())); }
+                count += counts[i - 1];
+                let (right_area, right_count) = right[i];
+                if count == 0 || right_count == 0 {
+                    continue;
+                }
+                let heuristic = aabb.as_ref().map_or(0.0, AABB::surface_area) * count as f64 + right_area * right_count as f64;
+                if heuristic < best_heuristic {
+                    best_heuristic = heuristic;
+                    best_split = Some((axis, i));
+                }
+            }
+        }
+
+        // Move the objects left of the plane to the front
+        let (axis, plane) = best_split?;
+        let mut split = 0;
+        for i in 0..objects.len() {
+            if bin(&objects[i].centroid, axis) < plane {
+                objects.swap(i, split);
+                split += 1;
+            }
+        }
+        let left_node = BuildNode::new_leaf(0, self.first, split, objects);
+        let right_node = BuildNode::new_leaf(split, self.first, self.count - split, objects);
+        Some((left_node, right_node))
+    }
+
     /// Surface Area Heuristic: a split if only worth it if:
     /// left.surface_area * left.objects.len() + right.surface_area * right.objects.len()
     ///    is less than self.surface_area * self.objects.len()
     /// Returns the new heuristic and the two nodes (or None if the split is not worth it)
-    fn check_split_sah(&self, split: usize, objects: &[Rc<dyn Hittable>], current_heuristic: f64)
-                       -> Option<(f64, BvhNode, BvhNode)> {
-        let left_node = BvhNode::new_leaf(0, self.first, split, objects);
-        let right_node = BvhNode::new_leaf(split, self.first, self.count - split, objects);
+    fn check_split_sah(&self, split: usize, objects: &[Reference], current_heuristic: f64)
+                       -> Option<(f64, BuildNode, BuildNode)> {
+        let left_node = BuildNode::new_leaf(0, self.first, split, objects);
+        let right_node = BuildNode::new_leaf(split, self.first, self.count - split, objects);
         let left_heuristic = left_node.aabb.surface_area()  * split as f64;
         let right_heuristic = right_node.aabb.surface_area()  * (self.count - split) as f64;
         let new_heuristic = left_heuristic + right_heuristic;
@@ -159,76 +527,54 @@ impl BvhNode {
         }
     }
 
-    pub fn sub_divide(&mut self, bvh: &mut Bvh, options: &Options) {
-        assert!(self.is_leaf, "We assume that nodes start out as leaves and are then subdivided");
-        let nodes = self.get_split(self.objects_mut(bvh), options);
-        if let Some((mut left_node, mut right_node)) = nodes {
-            // Make intermediate node
-            self.left = bvh.node_pointer + 1;
-            self.right = bvh.node_pointer + 2;
-            bvh.node_pointer += 2;
-
-            left_node.sub_divide(bvh, options);
-            bvh.nodes[self.left] = left_node;
-
-            right_node.sub_divide(bvh, options);
-            bvh.nodes[self.right] = right_node;
-
-            self.is_leaf = false;
-        }
+    /// Splits this leaf with the `objects` in it, returns the nodes of the subtree in depth first order
+    /// Child indices are relative to the returned nodes. Large subtrees are built in parallel, joining them in
+    /// this order gives the same tree as building them one after another.
+    fn sub_divide(self, objects: &mut [Reference], options: &Options, depth: usize) -> Vec<BvhNode> {
+        let split = if depth < MAX_DEPTH { self.get_split(objects, options) } else { None };
         // If no split found, keep this as a leaf.
-    }
+        let Some((left_node, right_node)) = split else { return vec![BvhNode::new(&self.aabb, self.first, self.count)] };
 
-    pub fn hit_aabb(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data, options: &Options) -> Option<f64> {
-        data.add_intersection_check();
-        self.aabb.hit(r, ray_t, rec, options).map(|i|i.0.min)
+        let parallel = objects.len() >= PARALLEL_THRESHOLD;
+        let (left_objects, right_objects) = objects.split_at_mut(left_node.count);
+        let left = || left_node.sub_divide(left_objects, options, depth + 1);
+        let right = || right_node.sub_divide(right_objects, options, depth + 1);
+        let (left_nodes, right_nodes) = if parallel { rayon::join(left, right) } else { (left(), right()) };
+
+        // Make intermediate node
+        let right_offset = 1 + left_nodes.len();
+        let mut nodes = Vec::with_capacity(right_offset + right_nodes.len());
+        nodes.push(BvhNode::new(&self.aabb, right_offset, 0));
+        nodes.extend(left_nodes.into_iter().map(|node| node.offset(1)));
+        nodes.extend(right_nodes.into_iter().map(|node| node.offset(right_offset)));
+        nodes
     }
+}
 
-    #[allow(clippy::collapsible_if)]
-    /// This method does NOT run self.hit_aabb(), please run that first to avoid unnecessary traversal
-    pub fn hit(&self, r: &Ray, ray_t: Interval, bvh: &Bvh, rec: &mut HitRecord, data: &mut Data, options: &Options) -> bool {
-        data.add_traversal_step();
-        
-        let mut hit_anything = false;
-        let mut closest_so_far = ray_t.max;
+/// The box around all references
+fn bounds(references: &[Reference]) -> AABB {
+    references.iter()
+        .map(|reference| reference.aabb.clone())
+        .reduce(|aabb, other| aabb + other)
+        .unwrap_or_default()
+}
 
-        if self.is_leaf {
-            for object in self.objects(bvh) {
-                data.add_intersection_check();
-                if object.hit(r, Interval::new(ray_t.min, closest_so_far), rec, data) {
-                    hit_anything = true;
-                    closest_so_far = rec.t;
-                }
-            }
-        } else {
-            let left = self.left(bvh);
-            let left_distance = left.hit_aabb(r, ray_t, rec, data, options);
-            let right = self.right(bvh);
-            let right_distance = right.hit_aabb(r, ray_t, rec, data, options);
-
-            if let (Some(left_distance), Some(right_distance)) = (left_distance, right_distance) {
-                let (close, far) =
-                    if left_distance >= right_distance { (right, left) } else { (left, right) };
-                let far_distance = left_distance.max(right_distance);
-                if close.hit(r, ray_t, bvh, rec, data, options) {
-                    hit_anything = true;
-                    closest_so_far = rec.t;
-                    // If the hitpoint is also in the far aabb, then also check that
-                    if far_distance < closest_so_far {
-                        data.add_overlapping_aabb();
-                        if far.hit(r, Interval::new(ray_t.min, closest_so_far), bvh, rec, data, options) {
-                            hit_anything = true;
-                        }
-                    }
-                } else if far.hit(r, ray_t, bvh, rec, data, options) {
-                    hit_anything = true;
-                }
-            } else if left_distance.is_some() && left.hit(r, ray_t, bvh, rec, data, options) ||
-                right_distance.is_some() && right.hit(r, ray_t, bvh, rec, data, options) {
-                hit_anything = true;
-            }
-        }
+/// Makes an intermediate node with the subtrees as children, for builders that return the nodes of a subtree with the
+/// objects of its leaves. The objects of the right subtree come after those of the left subtree.
+fn join(aabb: &AABB, (left_nodes, mut indices): (Vec<BvhNode>, Vec<usize>), (right_nodes, right_indices): (Vec<BvhNode>, Vec<usize>))
+        -> (Vec<BvhNode>, Vec<usize>) {
+    let right_offset = 1 + left_nodes.len();
+    let mut nodes = Vec::with_capacity(right_offset + right_nodes.len());
+    nodes.push(BvhNode::new(aabb, right_offset, 0));
+    nodes.extend(left_nodes.into_iter().map(|node| node.offset(1)));
+    nodes.extend(right_nodes.into_iter().map(|node| node.offset(right_offset).offset_objects(indices.len())));
+    indices.extend(right_indices);
+    (nodes, indices)
+}
 
-        hit_anything
+fn merge(aabb: Option<AABB>, other: AABB) -> AABB {
+    match aabb {
+        Some(aabb) => aabb + other,
+        None => other,
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,215 @@
+use super::{bounds, join, BuildNode, BvhNode, Reference, MAX_DEPTH, PARALLEL_THRESHOLD};
+use crate::acceleration::aabb::AABB;
+use crate::value::vec3::Point3;
+use rayon::slice::ParallelSliceMut;
+use std::ops::Range;
+
+/// The number of bits of a Morton code per axis, 63 bits in total
+const BITS_PER_AXIS: u32 = 21;
+/// Subtrees with at most this many objects become a leaf
+const MAX_LEAF_SIZE: usize = 4;
+/// HLBVH: objects with the same first bits of their Morton code form a cluster, the clusters are joined with SAH
+const CLUSTER_BITS: u32 = 12;
+/// HLBVH: below this depth the clusters are split at their median instead of by SAH, to keep the tree shallow
+const MAX_SAH_DEPTH: usize = MAX_DEPTH / 2;
+
+/// A child in the radix tree, the leaves are the sorted objects
+#[derive(Clone, Copy)]
+enum Child {
+    Leaf(usize),
+    Internal(usize),
+}
+
+/// The radix tree of sorted Morton codes (Karras 2012), internal node 0 is the root
+/// Every internal node is built on its own, so this takes linear time
+struct RadixTree {
+    children: Vec<(Child, Child)>,
+    /// The objects below every internal node
+    ranges: Vec<Range<usize>>,
+}
+
+/// Builds a linear BVH (LBVH): the objects are sorted by the Morton code of their centroid, which puts objects that are
+/// close together next to each other, and the radix tree of the codes is the hierarchy.
+/// With `hierarchical` (HLBVH) the clusters of objects that share the first bits of their code are joined by SAH,
+/// so the top of the tree is better than the Morton order.
+/// Returns the nodes in depth first order, and the objects of the leaves in order as indices into the objects
+pub(super) fn build(references: Vec<Reference>, hierarchical: bool) -> (Vec<BvhNode>, Vec<usize>) {
+    let centroids = references.iter().map(|reference| reference.centroid).collect::<Vec<_>>();
+    let centroid_bounds = AABB::from_points(&centroids);
+    let mut sorted = references.into_iter()
+        .map(|reference| (morton_code(&reference.centroid, &centroid_bounds), reference))
+        .collect::<Vec<_>>();
+    // Objects with the same code stay in their order, so the tree does not depend on the number of threads
+    sorted.par_sort_by_key(|(code, reference)| (*code, reference.index));
+    let (codes, references): (Vec<_>, Vec<_>) = sorted.into_iter().unzip();
+
+    if !hierarchical {
+        return sub_tree(&codes, &references, 1);
+    }
+    let cluster = |code: u64| code >> (3 * BITS_PER_AXIS - CLUSTER_BITS);
+    let mut ranges = vec![];
+    let mut first = 0;
+    for i in 1..=codes.len() {
+        if i == codes.len() || cluster(codes[i]) != cluster(codes[first]) {
+            ranges.push(first..i);
+            first = i;
+        }
+    }
+    let clusters = ranges.iter().enumerate().map(|(index, range)| {
+        let aabb = bounds(&references[range.clone()]);
+        Reference { centroid: (aabb.min + aabb.max) / 2.0, aabb, index, triangle: None }
+    }).collect();
+    top_tree(clusters, &ranges, &codes, &references, 1)
+}
+
+/// HLBVH: builds the top of the tree over the clusters with binned SAH, `index` of a cluster is its index in `ranges`
+/// Every cluster becomes a radix tree of the objects in its range
+fn top_tree(mut clusters: Vec<Reference>, ranges: &[Range<usize>], codes: &[u64], references: &[Reference], depth: usize)
+            -> (Vec<BvhNode>, Vec<usize>) {
+    let aabb = bounds(&clusters);
+    if let [cluster] = clusters.as_slice() {
+        let range = ranges[cluster.index].clone();
+        return sub_tree(&codes[range.clone()], &references[range], depth);
+    }
+
+    let node = BuildNode { aabb: aabb.clone(), first: 0, count: clusters.len() };
+    let split = if depth < MAX_SAH_DEPTH { node.get_split_binned(&mut clusters, 16) } else { None };
+    let split = match split {
+        Some((left, _)) => left.count,
+        None => {
+            // The clusters have to be split, so split them at the median of the longest axis
+            let extent = aabb.max - aabb.min;
+            let axis = (0..3).max_by(|&a, &b| extent[a].total_cmp(&extent[b])).unwrap_or(0);
+            clusters.sort_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            clusters.len() / 2
+        }
+    };
+    let right_clusters = clusters.split_off(split);
+    let left = top_tree(clusters, ranges, codes, references, depth + 1);
+    let right = top_tree(right_clusters, ranges, codes, references, depth + 1);
+    join(&aabb, left, right)
+}
+
+/// The BVH of sorted objects, made from their radix tree
+fn sub_tree(codes: &[u64], references: &[Reference], depth: usize) -> (Vec<BvhNode>, Vec<usize>) {
+    let root = if references.len() <= 1 { Child::Leaf(0) } else { Child::Internal(0) };
+    let tree = RadixTree::new(codes);
+    let (nodes, _) = emit(&tree, root, references, depth);
+    (nodes, references.iter().map(|reference| reference.index).collect())
+}
+
+/// The nodes below `child` in depth first order, small subtrees are collapsed into a leaf
+/// Returns the nodes and the box around them
+fn emit(tree: &RadixTree, child: Child, references: &[Reference], depth: usize) -> (Vec<BvhNode>, AABB) {
+    let range = match child {
+        Child::Leaf(i) => i..i + 1,
+        Child::Internal(i) => tree.ranges[i].clone(),
+    };
+    let Child::Internal(i) = child else { return leaf(range, references) };
+    if range.len() <= MAX_LEAF_SIZE || depth >= MAX_DEPTH {
+        return leaf(range, references);
+    }
+
+    let (left, right) = tree.children[i];
+    let left = || emit(tree, left, references, depth + 1);
+    let right = || emit(tree, right, references, depth + 1);
+    let ((left_nodes, left_aabb), (right_nodes, right_aabb)) =
+        if range.len() >= PARALLEL_THRESHOLD { rayon::join(left, right) } else { (left(), right()) };
+
+    let aabb = &left_aabb + &right_aabb;
+    let right_offset = 1 + left_nodes.len();
+    let mut nodes = Vec::with_capacity(right_offset + right_nodes.len());
+    nodes.push(BvhNode::new(&aabb, right_offset, 0));
+    nodes.extend(left_nodes.into_iter().map(|node| node.offset(1)));
+    nodes.extend(right_nodes.into_iter().map(|node| node.offset(right_offset)));
+    (nodes, aabb)
+}
+
+fn leaf(range: Range<usize>, references: &[Reference]) -> (Vec<BvhNode>, AABB) {
+    let aabb = bounds(&references[range.clone()]);
+    (vec![BvhNode::new(&aabb, range.start, range.len())], aabb)
+}
+
+impl RadixTree {
+    fn new(codes: &[u64]) -> Self {
+        let n = codes.len();
+        // The length of the common prefix of the keys `i` and `j`, the index breaks ties between equal codes
+        let delta = |i: usize, j: isize| -> i32 {
+            if j < 0 || j as usize >= n {
+                return -1;
+            }
+            let j = j as usize;
+            if codes[i] == codes[j] {
+                64 + (i ^ j).leading_zeros() as i32
+            } else {
+                (codes[i] ^ codes[j]).leading_zeros() as i32
+            }
+        };
+
+        let (children, ranges) = (0..n.saturating_sub(1)).map(|i| {
+            let at = |steps: usize, direction: isize| i as isize + steps as isize * direction;
+            // The direction of the range of this node, towards the neighbour with the longer common prefix
+            let direction = if delta(i, at(1, 1)) > delta(i, at(1, -1)) { 1 } else { -1 };
+            let delta_min = delta(i, at(1, -direction));
+
+            // The other end of the range, first an upper bound then a binary search
+            let mut max_length = 2;
+            while delta(i, at(max_length, direction)) > delta_min {
+                max_length *= 2;
+            }
+            let mut length = 0;
+            let mut step = max_length / 2;
+            while step >= 1 {
+                if delta(i, at(length + step, direction)) > delta_min {
+                    length += step;
+                }
+                step /= 2;
+            }
+            let j = at(length, direction) as usize;
+
+            // The split is where the common prefix gets shorter
+            let delta_node = delta(i, j as isize);
+            let mut split = 0;
+            let mut step = length;
+            loop {
+                step = step.div_ceil(2);
+                if delta(i, at(split + step, direction)) > delta_node {
+                    split += step;
+                }
+                if step <= 1 {
+                    break;
+                }
+            }
+            let gamma = (at(split, direction) + direction.min(0)) as usize;
+
+            let (first, last) = (i.min(j), i.max(j));
+            let left = if first == gamma { Child::Leaf(gamma) } else { Child::Internal(gamma) };
+            let right = if last == gamma + 1 { Child::Leaf(gamma + 1) } else { Child::Internal(gamma + 1) };
+            ((left, right), first..last + 1)
+        }).unzip();
+        Self { children, ranges }
+    }
+}
+
+/// The Morton code of a point in `bounds`: the bits of the coordinates interleaved, so points that are close together
+/// have codes that are close together
+fn morton_code(point: &Point3, bounds: &AABB) -> u64 {
+    let scale = (1u64 << BITS_PER_AXIS) as f64;
+    let coordinate = |axis: u32| {
+        let extent = bounds.max[axis] - bounds.min[axis];
+        let relative = if extent > 0.0 { (point[axis] - bounds.min[axis]) / extent } else { 0.0 };
+        ((relative * scale) as u64).min((1 << BITS_PER_AXIS) - 1)
+    };
+    spread(coordinate(0)) << 2 | spread(coordinate(1)) << 1 | spread(coordinate(2))
+}
+
+/// Puts two zero bits between each of the lowest 21 bits
+fn spread(value: u64) -> u64 {
+    let mut x = value & 0x1fffff;
+    x = (x | x << 32) & 0x1f00000000ffff;
+    x = (x | x << 16) & 0x1f0000ff0000ff;
+    x = (x | x << 8) & 0x100f00f00f00f00f;
+    x = (x | x << 4) & 0x10c30c30c30c30c3;
+    x = (x | x << 2) & 0x1249249249249249;
+    x
+}
@@ -0,0 +1,227 @@
+use super::{bounds, join, merge, BuildNode, BvhNode, Reference, MAX_DEPTH, PARALLEL_THRESHOLD};
+use crate::acceleration::aabb::AABB;
+use crate::value::vec3::Point3;
+
+/// The number of extra references that spatial splits may add, relative to the number of objects
+pub const DUPLICATE_BUDGET: f64 = 0.3;
+/// The object split is binned like `BvhSahBinned16`
+const OBJECT_BINS: usize = 16;
+/// The number of bins of a spatial split, the planes between them are checked
+const SPATIAL_BINS: usize = 32;
+/// Spatial splits are only checked if the children of the object split overlap at least this much,
+/// relative to the surface area of the root, as they only help where the objects overlap
+const OVERLAP_THRESHOLD: f64 = 1e-5;
+
+/// A spatial split at `position` on `axis`, with the box and number of references of both halves
+struct SpatialSplit {
+    heuristic: f64,
+    axis: u32,
+    position: f64,
+    left: (AABB, usize),
+    right: (AABB, usize),
+}
+
+/// Builds a tree with spatial splits (Stich et al. 2009), an object can be in more than one leaf
+/// Returns the nodes in depth first order, and the objects of the leaves in order as indices into the objects
+pub(super) fn build(references: Vec<Reference>, budget: usize) -> (Vec<BvhNode>, Vec<usize>) {
+    let root_area = bounds(&references).surface_area();
+    sub_divide(references, budget, root_area, 1)
+}
+
+/// Splits the node with `references` in it, like `BuildNode::sub_divide()`
+/// `budget` is the number of references this subtree may add, it is shared by the children by their size
+fn sub_divide(mut references: Vec<Reference>, budget: usize, root_area: f64, depth: usize) -> (Vec<BvhNode>, Vec<usize>) {
+    let aabb = bounds(&references);
+    let count = references.len();
+    let leaf = |references: Vec<Reference>| {
+        (vec![BvhNode::new(&aabb, 0, count)], references.iter().map(|reference| reference.index).collect())
+    };
+    if depth >= MAX_DEPTH || count < 2 {
+        return leaf(references);
+    }
+
+    // The best object split, it moves the references left of it to the front
+    let node = BuildNode { aabb: aabb.clone(), first: 0, count };
+    let object_split = node.get_split_binned(&mut references, OBJECT_BINS);
+    let mut heuristic = aabb.surface_area() * count as f64;
+    let mut overlap = f64::INFINITY;
+    if let Some((left, right)) = &object_split {
+        heuristic = left.aabb.surface_area() * left.count as f64 + right.aabb.surface_area() * right.count as f64;
+        overlap = intersection(&left.aabb, &right.aabb).map_or(0.0, |aabb| aabb.surface_area());
+    }
+    let spatial_split = if budget > 0 && overlap / root_area > OVERLAP_THRESHOLD {
+        spatial_split(&references, &aabb, budget).filter(|split| split.heuristic < heuristic)
+    } else {
+        None
+    };
+
+    let (left, right, duplicates) = match (spatial_split, object_split) {
+        (Some(split), _) => split_references(references, &split),
+        (None, Some((left, _))) => {
+            let right = references.split_off(left.count);
+            (references, right, 0)
+        }
+        (None, None) => return leaf(references),
+    };
+    if left.is_empty() || right.is_empty() {
+        return leaf(left.into_iter().chain(right).collect());
+    }
+
+    let budget = budget.saturating_sub(duplicates);
+    let left_budget = budget * left.len() / (left.len() + right.len());
+    let parallel = count >= PARALLEL_THRESHOLD;
+    let left = || sub_divide(left, left_budget, root_area, depth + 1);
+    let right = || sub_divide(right, budget - left_budget, root_area, depth + 1);
+    let (left, right) = if parallel { rayon::join(left, right) } else { (left(), right()) };
+    join(&aabb, left, right)
+}
+
+/// Puts the references in `SPATIAL_BINS` bins along each axis, a reference is clipped to every bin it overlaps
+/// A reference is counted where it enters and where it exits, so the planes between the bins can be checked with
+/// sweeps from both sides like `BuildNode::get_split_binned()`. Planes that split more than `budget` references are
+/// skipped.
+fn spatial_split(references: &[Reference], aabb: &AABB, budget: usize) -> Option<SpatialSplit> {
+    let mut best: Option<SpatialSplit> = None;
+    for axis in 0..3 {
+        let extent = aabb.max[axis] - aabb.min[axis];
+        if extent <= 0.0 {
+            continue;
+        }
+        let width = extent / SPATIAL_BINS as f64;
+        let plane = |i: usize| aabb.min[axis] + i as f64 * width;
+        let bin = |value: f64| (((value - aabb.min[axis]) / width) as usize).min(SPATIAL_BINS - 1);
+
+        let mut boxes: Vec<Option<AABB>> = vec![None; SPATIAL_BINS];
+        let mut entries = [0; SPATIAL_BINS];
+        let mut exits = [0; SPATIAL_BINS];
+        for reference in references {
+            let (first, last) = (bin(reference.aabb.min[axis]), bin(reference.aabb.max[axis]));
+            entries[first] += 1;
+            exits[last] += 1;
+            let mut rest = Some(reference.clone());
+            for (i, bin_aabb) in boxes.iter_mut().enumerate().take(last + 1).skip(first) {
+                let Some(reference) = rest.take() else { break };
+                let (left, right) = if i < last { split(&reference, axis, plane(i + 1)) } else { (Some(reference), None) };
+                if let Some(left) = left {
+                    *bin_aabb = Some(merge(bin_aabb.take(), left.aabb));
+                }
+                rest = right;
+            }
+        }
+
+        // The right half of plane `i` holds bins `i..`
+        let mut right = vec![(None, 0); SPATIAL_BINS];
+        let mut bounds = None;
+        let mut count = 0;
+        for i in (1..SPATIAL_BINS).rev() {
+            if let Some(bin_aabb) = &boxes[i] { bounds = Some(merge(bounds, bin_aabb.clone())); }
+            count += exits[i];
+            right[i] = (bounds.clone(), count);
+        }
+        let mut bounds = None;
+        let mut count = 0;
+        for i in 1..SPATIAL_BINS {
+            if let Some(bin_aabb) = &boxes[i - 1] { bounds = Some(merge(bounds, bin_aabb.clone())); }
+            count += entries[i - 1];
+            let (Some(left_aabb), (Some(right_aabb), right_count)) = (&bounds, &right[i]) else { continue };
+            // The references in both halves are split
+            if count + right_count - references.len() > budget {
+                continue;
+            }
+            let heuristic = left_aabb.surface_area() * count as f64 + right_aabb.surface_area() * *right_count as f64;
+            if best.as_ref().is_none_or(|best| heuristic < best.heuristic) {
+                best = Some(SpatialSplit {
+                    heuristic, axis, position: plane(i), left: (left_aabb.clone(), count), right: (right_aabb.clone(), *right_count),
+                });
+            }
+        }
+    }
+    best
+}
+
+/// Divides the references over both halves of a spatial split, returns both halves and the number of duplicates
+/// A reference that straddles the plane is split in two, unless putting all of it in one half is cheaper
+fn split_references(references: Vec<Reference>, spatial_split: &SpatialSplit) -> (Vec<Reference>, Vec<Reference>, usize) {
+    let (axis, position) = (spatial_split.axis, spatial_split.position);
+    let ((left_aabb, left_count), (right_aabb, right_count)) = (&spatial_split.left, &spatial_split.right);
+    let (left_count, right_count) = (*left_count as f64, *right_count as f64);
+    let (left_area, right_area) = (left_aabb.surface_area(), right_aabb.surface_area());
+    let (mut left, mut right) = (vec![], vec![]);
+    let mut duplicates = 0;
+    for reference in references {
+        if reference.aabb.max[axis] <= position {
+            left.push(reference);
+            continue;
+        }
+        if reference.aabb.min[axis] >= position {
+            right.push(reference);
+            continue;
+        }
+        match split(&reference, axis, position) {
+            (Some(left_part), Some(right_part)) => {
+                // Reference unsplitting, the boxes of the halves are those of the bins
+                let split_heuristic = left_area * left_count + right_area * right_count;
+                let left_heuristic = (left_aabb + &reference.aabb).surface_area() * left_count + right_area * (right_count - 1.0);
+                let right_heuristic = left_area * (left_count - 1.0) + (right_aabb + &reference.aabb).surface_area() * right_count;
+                if left_heuristic < split_heuristic && left_heuristic <= right_heuristic {
+                    left.push(reference);
+                } else if right_heuristic < split_heuristic {
+                    right.push(reference);
+                } else {
+                    left.push(left_part);
+                    right.push(right_part);
+                    duplicates += 1;
+                }
+            }
+            (Some(part), None) => left.push(part),
+            (None, Some(part)) => right.push(part),
+            (None, None) => left.push(reference),
+        }
+    }
+    (left, right, duplicates)
+}
+
+/// Splits a reference at `position` on `axis`, triangles are clipped so their halves get tight boxes
+/// Returns `None` for a half without any of the reference in it
+fn split(reference: &Reference, axis: u32, position: f64) -> (Option<Reference>, Option<Reference>) {
+    let (mut left, mut right) = (vec![], vec![]);
+    match &reference.triangle {
+        Some(vertices) => {
+            for (i, &a) in vertices.iter().enumerate() {
+                let b = vertices[(i + 1) % 3];
+                if a[axis] <= position { left.push(a); }
+                if a[axis] >= position { right.push(a); }
+                // The edge crosses the plane
+                if (a[axis] < position && position < b[axis]) || (b[axis] < position && position < a[axis]) {
+                    let t = (position - a[axis]) / (b[axis] - a[axis]);
+                    let mut point = a + t * (b - a);
+                    point[axis] = position;
+                    left.push(point);
+                    right.push(point);
+                }
+            }
+        }
+        None => {
+            let (mut max, mut min) = (reference.aabb.max, reference.aabb.min);
+            max[axis] = position;
+            min[axis] = position;
+            left.extend([reference.aabb.min, max]);
+            right.extend([min, reference.aabb.max]);
+        }
+    }
+    let part = |points: Vec<Point3>| {
+        if points.is_empty() { return None; }
+        // The reference may already be clipped by an earlier split
+        let aabb = intersection(&AABB::from_points(&points), &reference.aabb)?;
+        let centroid = (aabb.min + aabb.max) / 2.0;
+        Some(Reference { aabb, centroid, ..reference.clone() })
+    };
+    (part(left), part(right))
+}
+
+/// The box where both boxes overlap, if they do
+fn intersection(a: &AABB, b: &AABB) -> Option<AABB> {
+    let min = Point3::new(a.min.x().max(b.min.x()), a.min.y().max(b.min.y()), a.min.z().max(b.min.z()));
+    let max = Point3::new(a.max.x().min(b.max.x()), a.max.y().min(b.max.y()), a.max.z().min(b.max.z()));
+    (0..3).all(|axis| min[axis] <= max[axis]).then(|| AABB::new(min, max))
+}
@@ -0,0 +1,313 @@
+use super::{BvhNode, INTERSECTION_COST, MAX_DEPTH, TRAVERSAL_COST};
+use crate::acceleration::aabb::AABB;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Subtrees with more objects are not collapsed into a leaf by `into_nodes()`
+const MAX_LEAF_SIZE: usize = 8;
+
+/// A BVH with the children of every node stored in the node, so subtrees can be moved around
+/// The depth first order of `BvhNode`s only allows changing the boxes
+#[derive(Clone)]
+pub(super) struct Tree {
+    nodes: Vec<TreeNode>,
+    root: usize,
+}
+
+#[derive(Clone)]
+struct TreeNode {
+    aabb: AABB,
+    parent: Option<usize>,
+    /// The left and right child of an intermediate node
+    children: Option<[usize; 2]>,
+    /// The objects of a leaf, like `BvhNode::first()` and `BvhNode::count()`
+    first: usize,
+    count: usize,
+    /// The number of levels of the subtree, 1 for a leaf
+    height: usize,
+}
+
+impl Tree {
+    /// The tree of `nodes` in depth first order, with the exact `boxes` of the nodes, node 0 is the root
+    pub(super) fn new(nodes: &[BvhNode], boxes: Vec<AABB>) -> Self {
+        let mut nodes = nodes.iter().zip(boxes).enumerate().map(|(index, (node, aabb))| {
+            if node.is_leaf() {
+                TreeNode { aabb, parent: None, children: None, first: node.first(), count: node.count(), height: 1 }
+            } else {
+                TreeNode { aabb, parent: None, children: Some([index + 1, node.right()]), first: 0, count: 0, height: 0 }
+            }
+        }).collect::<Vec<_>>();
+        for index in (0..nodes.len()).rev() {
+            if let Some([left, right]) = nodes[index].children {
+                nodes[index].height = 1 + nodes[left].height.max(nodes[right].height);
+                nodes[left].parent = Some(index);
+                nodes[right].parent = Some(index);
+            }
+        }
+        Self { nodes, root: 0 }
+    }
+
+    /// The nodes in depth first order, and for every object of the new leaves the index of the object in the old ones
+    /// The objects are renumbered so the objects below a node are next to each other. With `collapse` subtrees
+    /// become a leaf if that has a lower SAH cost.
+    pub(super) fn into_nodes(self, collapse: bool) -> (Vec<BvhNode>, Vec<usize>) {
+        let mut collapsed = vec![false; self.nodes.len()];
+        if collapse {
+            self.collapse(self.root, &mut collapsed);
+        }
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        let mut order = vec![];
+        self.emit(self.root, &collapsed, &mut nodes, &mut order);
+        (nodes, order)
+    }
+
+    fn emit(&self, index: usize, collapsed: &[bool], nodes: &mut Vec<BvhNode>, order: &mut Vec<usize>) {
+        let node = &self.nodes[index];
+        let position = nodes.len();
+        match node.children {
+            Some([left, right]) if !collapsed[index] => {
+                nodes.push(BvhNode::new(&node.aabb, 0, 0));
+                self.emit(left, collapsed, nodes, order);
+                nodes[position] = BvhNode::new(&node.aabb, nodes.len(), 0);
+                self.emit(right, collapsed, nodes, order);
+            }
+            _ => {
+                let first = order.len();
+                self.objects_below(index, order);
+                nodes.push(BvhNode::new(&node.aabb, first, order.len() - first));
+            }
+        }
+    }
+
+    fn objects_below(&self, index: usize, order: &mut Vec<usize>) {
+        let node = &self.nodes[index];
+        match node.children {
+            Some(children) => children.into_iter().for_each(|child| self.objects_below(child, order)),
+            None => order.extend(node.first..node.first + node.count),
+        }
+    }
+
+    /// Marks the subtrees that are cheaper as a leaf, returns the SAH cost of the subtree (not divided by the area of
+    /// the root) and its number of objects
+    fn collapse(&self, index: usize, collapsed: &mut [bool]) -> (f64, usize) {
+        let node = &self.nodes[index];
+        let area = node.aabb.surface_area();
+        let Some([left, right]) = node.children else { return (area * node.count as f64 * INTERSECTION_COST, node.count) };
+        let (left_cost, left_count) = self.collapse(left, collapsed);
+        let (right_cost, right_count) = self.collapse(right, collapsed);
+        let (cost, count) = (area * TRAVERSAL_COST + left_cost + right_cost, left_count + right_count);
+        let leaf_cost = area * count as f64 * INTERSECTION_COST;
+        if count <= MAX_LEAF_SIZE && leaf_cost <= cost {
+            collapsed[index] = true;
+            return (leaf_cost, count);
+        }
+        (cost, count)
+    }
+
+    /// Tree rotations (Kopta et al. 2012) from the leaves up: swaps a child of a node with a child of its other child,
+    /// if that makes the other child smaller. `depth` is the level of `index`, the tree does not get deeper than
+    /// `MAX_DEPTH`.
+    pub(super) fn rotate(&mut self, index: usize, depth: usize) {
+        let Some(children) = self.nodes[index].children else { return };
+        for child in children {
+            self.rotate(child, depth + 1);
+        }
+
+        // Swap `children[side]` with a grandchild below `children[1 - side]`
+        let mut best: Option<(f64, usize, usize)> = None;
+        for side in 0..2 {
+            let (child, other) = (children[side], children[1 - side]);
+            let Some(grandchildren) = self.nodes[other].children else { continue };
+            for position in 0..2 {
+                let (grandchild, sibling) = (grandchildren[position], grandchildren[1 - position]);
+                // `other` gets `child` and `sibling` as children, `grandchild` moves up
+                let area = (&self.nodes[child].aabb + &self.nodes[sibling].aabb).surface_area();
+                let gain = self.nodes[other].aabb.surface_area() - area;
+                let other_height = 1 + self.nodes[child].height.max(self.nodes[sibling].height);
+                let height = 1 + other_height.max(self.nodes[grandchild].height);
+                if gain > 0.0 && depth + height - 1 <= MAX_DEPTH && best.is_none_or(|(best, _, _)| gain > best) {
+                    best = Some((gain, side, position));
+                }
+            }
+        }
+
+        let Some((_, side, position)) = best else { return };
+        let (child, other) = (children[side], children[1 - side]);
+        let mut grandchildren = self.nodes[other].children.unwrap();
+        let grandchild = grandchildren[position];
+        grandchildren[position] = child;
+        let mut children = children;
+        children[side] = grandchild;
+        self.nodes[other].children = Some(grandchildren);
+        self.nodes[index].children = Some(children);
+        self.nodes[child].parent = Some(other);
+        self.nodes[grandchild].parent = Some(index);
+        self.update(other);
+        self.update(index);
+    }
+
+    /// The SAH cost like `Bvh::sah_cost()`
+    pub(super) fn sah_cost(&self) -> f64 {
+        let root_area = self.nodes[self.root].aabb.surface_area();
+        let mut cost = 0.0;
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match node.children {
+                Some(children) => {
+                    cost += node.aabb.surface_area() * TRAVERSAL_COST;
+                    stack.extend(children);
+                }
+                None => cost += node.aabb.surface_area() * node.count as f64 * INTERSECTION_COST,
+            }
+        }
+        cost / root_area
+    }
+
+    /// Splits the leaves below `index` into leaves of one object, so `reinsert()` can move every object on its own
+    /// `objects` are the boxes of the objects, `depth` is the level of `index`. Leaves at `MAX_DEPTH` are not split.
+    pub(super) fn split_leaves(&mut self, index: usize, depth: usize, objects: &[AABB]) {
+        let children = match self.nodes[index].children {
+            Some(children) => children,
+            None => {
+                let (first, count) = (self.nodes[index].first, self.nodes[index].count);
+                if count < 2 || depth >= MAX_DEPTH {
+                    return;
+                }
+                let half = count / 2;
+                let children = [(first, half), (first + half, count - half)].map(|(first, count)| {
+                    let aabb = objects[first..first + count].iter().cloned().reduce(|aabb, other| aabb + other).unwrap_or_default();
+                    self.nodes.push(TreeNode { aabb, parent: Some(index), children: None, first, count, height: 1 });
+                    self.nodes.len() - 1
+                });
+                self.nodes[index].children = Some(children);
+                children
+            }
+        };
+        for child in children {
+            self.split_leaves(child, depth + 1, objects);
+        }
+        self.update(index);
+    }
+
+    /// Insertion-based optimisation (Bittner et al. 2013): takes the intermediate nodes that are the most inefficient
+    /// out of the tree and inserts their children again where they cost the least. Returns the number of removed nodes.
+    /// A node is inefficient if it is large and its children are much smaller than it.
+    pub(super) fn reinsert(&mut self, fraction: f64) -> usize {
+        let mut candidates = (0..self.nodes.len())
+            .filter(|&index| index != self.root && self.nodes[index].children.is_some())
+            .map(|index| {
+                let [left, right] = self.nodes[index].children.unwrap();
+                let area = self.nodes[index].aabb.surface_area();
+                let (left, right) = (self.nodes[left].aabb.surface_area(), self.nodes[right].aabb.surface_area());
+                let inefficiency = area * (area / (left + right).max(f64::MIN_POSITIVE)) * (area / left.min(right).max(f64::MIN_POSITIVE));
+                (inefficiency, index)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let count = ((candidates.len() as f64 * fraction).ceil() as usize).min(candidates.len());
+        for &(_, index) in &candidates[..count] {
+            // An earlier reinsertion can make a candidate the root
+            if index == self.root {
+                continue;
+            }
+            // The node and its parent are taken out, and used again as the parents of the children
+            let parent = self.remove(index);
+            let mut children = self.nodes[index].children.unwrap();
+            children.sort_by(|&a, &b| self.nodes[b].aabb.surface_area().total_cmp(&self.nodes[a].aabb.surface_area()));
+            for (child, free) in children.into_iter().zip([parent, index]) {
+                let position = self.find_position(child);
+                self.insert(child, position, free);
+            }
+        }
+        count
+    }
+
+    /// Takes the subtree at `index` out of the tree, its sibling takes the place of its parent
+    /// Returns the parent, which is no longer used
+    fn remove(&mut self, index: usize) -> usize {
+        let parent = self.nodes[index].parent.expect("Cannot remove the root");
+        let [left, right] = self.nodes[parent].children.unwrap();
+        let sibling = if left == index { right } else { left };
+        let grandparent = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grandparent;
+        match grandparent {
+            None => self.root = sibling,
+            Some(grandparent) => {
+                self.replace_child(grandparent, parent, sibling);
+                self.update_up(grandparent);
+            }
+        }
+        self.nodes[index].parent = None;
+        parent
+    }
+
+    /// Puts the subtree at `index` next to the node at `position`, with the unused node `parent` as their parent
+    fn insert(&mut self, index: usize, position: usize, parent: usize) {
+        let grandparent = self.nodes[position].parent;
+        self.nodes[parent].parent = grandparent;
+        self.nodes[parent].children = Some([position, index]);
+        self.nodes[position].parent = Some(parent);
+        self.nodes[index].parent = Some(parent);
+        match grandparent {
+            None => self.root = parent,
+            Some(grandparent) => self.replace_child(grandparent, position, parent),
+        }
+        self.update_up(parent);
+    }
+
+    /// Branch and bound search for the node where inserting the subtree at `index` adds the least surface area
+    /// The area it adds to the ancestors of a node is a lower bound for the area it adds below that node
+    fn find_position(&self, index: usize) -> usize {
+        let aabb = &self.nodes[index].aabb;
+        let area = aabb.surface_area();
+        let height = self.nodes[index].height;
+        let mut best = (f64::INFINITY, self.root);
+        // The areas are not negative, so their bits sort like the areas
+        let mut queue = BinaryHeap::from([(Reverse(0.0f64.to_bits()), self.root, 1)]);
+        while let Some((Reverse(induced), position, depth)) = queue.pop() {
+            let induced = f64::from_bits(induced);
+            if induced + area >= best.0 {
+                break;
+            }
+            let node = &self.nodes[position];
+            let merged = (&node.aabb + aabb).surface_area();
+            let cost = induced + merged;
+            // The new parent is at `depth`, its deepest leaf must not get deeper than `MAX_DEPTH`
+            if cost < best.0 && depth + node.height.max(height) <= MAX_DEPTH {
+                best = (cost, position);
+            }
+            if let Some(children) = node.children {
+                let induced = cost - node.aabb.surface_area();
+                if induced + area < best.0 {
+                    for child in children {
+                        queue.push((Reverse(induced.to_bits()), child, depth + 1));
+                    }
+                }
+            }
+        }
+        best.1
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let children = self.nodes[parent].children.as_mut().unwrap();
+        let side = if children[0] == old { 0 } else { 1 };
+        children[side] = new;
+    }
+
+    /// Updates the boxes and heights from `index` up to the root
+    fn update_up(&mut self, index: usize) {
+        let mut index = Some(index);
+        while let Some(current) = index {
+            self.update(current);
+            index = self.nodes[current].parent;
+        }
+    }
+
+    /// Recomputes the box and height of an intermediate node from its children
+    fn update(&mut self, index: usize) {
+        let [left, right] = self.nodes[index].children.unwrap();
+        self.nodes[index].aabb = &self.nodes[left].aabb + &self.nodes[right].aabb;
+        self.nodes[index].height = 1 + self.nodes[left].height.max(self.nodes[right].height);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::acceleration::aabb::AABB;
+use crate::acceleration::bvh::{INTERSECTION_COST, TRAVERSAL_COST};
+use crate::hittable::{HitRecord, Hittable};
+use crate::utils::rtweekend::Options;
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::ray::Ray;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// A kd-tree built with the SAH in O(n log n), following Wald and Havran, "On building fast kd-trees for ray tracing,
+/// and on doing that in O(N log N)" (2006)
+/// The cells of the tree do not overlap, an object that crosses a split plane is in both children.
+/// The nodes are stored depth first, the left child of a split is the next node.
+pub struct KdTree {
+    objects: Vec<Rc<dyn Hittable>>,
+    /// The objects of the leaves, as indices into `objects`, see `KdNode::Leaf`
+    indices: Vec<u32>,
+    nodes: Vec<KdNode>,
+    aabb: AABB,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KdNode {
+    /// Splits the cell at `position` on `axis`, `right` is the index of the right child
+    Split { axis: u32, position: f64, right: u32 },
+    /// The objects `first..first + count` of `KdTree::indices`
+    Leaf { first: u32, count: u32 },
+}
+
+/// The cost of a split that cuts off an empty cell is lowered by this part, unless `--empty-bonus` is given
+pub const EMPTY_BONUS: f64 = 0.2;
+
+/// Nodes at this depth are not split, so the traversal stack can have a fixed size
+pub const MAX_DEPTH: usize = 64;
+
+/// The bounds of the tree are made at least this thick, so rays can enter a flat scene
+const MIN_THICKNESS: f64 = 1e-4;
+
+/// The order of events at the same position, so the sweep sees the objects that end there first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum EventKind {
+    End,
+    Planar,
+    Start,
+}
+
+/// Where the box of an object starts or ends on an axis, or where it lies if it is flat on that axis
+#[derive(Clone, Copy, Debug)]
+struct Event {
+    position: f64,
+    kind: EventKind,
+    object: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Both,
+    Left,
+    Right,
+}
+
+struct Split {
+    axis: u32,
+    position: f64,
+    /// Objects that lie in the split plane go to the left child
+    planar_left: bool,
+    cost: f64,
+}
+
+/// The state of a build, the nodes are added depth first
+struct Builder<'a> {
+    boxes: &'a [AABB],
+    /// The side of the split plane of every object, only valid for the objects of the node being split
+    sides: Vec<Side>,
+    nodes: Vec<KdNode>,
+    indices: Vec<u32>,
+    empty_bonus: f64,
+    max_depth: usize,
+}
+
+impl KdTree {
+    /// Builds the tree, the best split of a cell is found by sweeping over the sorted starts and ends of the boxes of
+    /// its objects. The events are sorted once, the children keep the order of their parent.
+    pub fn new(objects: Vec<Rc<dyn Hittable>>, options: &Options) -> Self {
+        if objects.is_empty() {
+            return Self { objects, indices: vec![], nodes: vec![], aabb: AABB::default() };
+        }
+        assert!(objects.len() <= u32::MAX as usize, "Too many objects for a kd-tree: {}", objects.len());
+
+        let boxes = objects.iter().map(|object| object.to_aabb()).collect::<Vec<_>>();
+        let aabb = boxes.iter().cloned().reduce(|aabb, other| aabb + other).unwrap_or_default().padded(MIN_THICKNESS);
+        let events = [0, 1, 2].map(|axis| {
+            let mut events = Vec::with_capacity(2 * boxes.len());
+            for (object, aabb) in boxes.iter().enumerate() {
+                push_events(aabb, object as u32, axis, &mut events);
+            }
+            events.sort_unstable_by(compare);
+            events
+        });
+
+        // The usual limit of Havran's thesis, deeper trees mostly duplicate objects
+        let max_depth = ((8.0 + 1.3 * (objects.len() as f64).log2()) as usize).min(MAX_DEPTH);
+        let mut builder = Builder {
+            boxes: &boxes,
+            sides: vec![Side::Both; boxes.len()],
+            nodes: vec![],
+            indices: vec![],
+            empty_bonus: options.empty_bonus.unwrap_or(EMPTY_BONUS),
+            max_depth,
+        };
+        builder.build(events, (0..objects.len() as u32).collect(), aabb.clone(), 0);
+        let (mut nodes, mut indices) = (builder.nodes, builder.indices);
+        nodes.shrink_to_fit();
+        indices.shrink_to_fit();
+        Self { objects, indices, nodes, aabb }
+    }
+
+    /// The nodes in depth first order, the root comes first
+    pub fn nodes(&self) -> &[KdNode] {
+        &self.nodes
+    }
+
+    /// The objects of a leaf
+    #[allow(dead_code)]
+    pub fn objects(&self, node: &KdNode) -> impl Iterator<Item = &Rc<dyn Hittable>> {
+        let KdNode::Leaf { first, count } = *node else { panic!("Cannot get objects for a split") };
+        self.indices[first as usize..(first + count) as usize].iter().map(|&index| &self.objects[index as usize])
+    }
+
+    /// The cell of the root, the box around all objects
+    #[allow(dead_code)]
+    pub fn aabb(&self) -> &AABB {
+        &self.aabb
+    }
+
+    /// The number of objects in all leaves, objects that cross a split plane are counted more than once
+    pub fn references(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// The expected cost of tracing a ray through the tree, like `Bvh::sah_cost()`
+    /// The cost of each node is weighted by the chance that a ray through the root also passes through its cell
+    pub fn sah_cost(&self) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.0;
+        }
+        let mut cost = 0.0;
+        let mut stack = vec![(0, self.aabb.clone())];
+        while let Some((index, cell)) = stack.pop() {
+            match self.nodes[index] {
+                KdNode::Split { axis, position, right } => {
+                    cost += cell.surface_area() * TRAVERSAL_COST;
+                    let (left_cell, right_cell) = split_cell(&cell, axis, position);
+                    stack.push((index + 1, left_cell));
+                    stack.push((right as usize, right_cell));
+                }
+                KdNode::Leaf { count, .. } => cost += cell.surface_area() * count as f64 * INTERSECTION_COST,
+            }
+        }
+        cost / self.aabb.surface_area()
+    }
+
+    /// Finds the closest hit, the cells are visited front to back
+    /// A hit inside the current cell is closer than anything in the cells after it, so the traversal stops there.
+    /// The far children that still have to be visited are kept on a fixed size stack, with the part of the ray in them.
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let Some((mut t_min, mut t_max)) = self.aabb.enter_and_exit(r, ray_t) else { return false };
+
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+        let mut stack = [(0u32, 0.0, 0.0); MAX_DEPTH];
+        let mut size = 0;
+        let mut index = 0;
+        loop {
+            data.add_traversal_step();
+            match self.nodes[index] {
+                KdNode::Split { axis, position, right } => {
+                    let (origin, direction) = (r.origin()[axis], r.direction()[axis]);
+                    let left_first = origin < position || origin == position && direction <= 0.0;
+                    let (near, far) = if left_first { (index + 1, right as usize) } else { (right as usize, index + 1) };
+                    let t = (position - origin) / direction;
+                    // The ray does not cross the plane inside the cell (or runs parallel to it, which gives NaN)
+                    if t.is_nan() || t <= 0.0 || t > t_max {
+                        index = near;
+                    } else if t < t_min {
+                        index = far;
+                    } else {
+                        stack[size] = (far as u32, t, t_max);
+                        size += 1;
+                        index = near;
+                        t_max = t;
+                    }
+                    continue;
+                }
+                KdNode::Leaf { first, count } => {
+                    for &object in &self.indices[first as usize..(first + count) as usize] {
+                        data.add_intersection_check();
+                        if self.objects[object as usize].hit(r, Interval::new(ray_t.min, closest_so_far), rec, data) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                        }
+                    }
+                    if hit_anything && closest_so_far <= t_max {
+                        return true;
+                    }
+                }
+            }
+
+            // Cells that start after the closest hit cannot contain a closer one
+            loop {
+                if size == 0 {
+                    return hit_anything;
+                }
+                size -= 1;
+                let next;
+                (next, t_min, t_max) = stack[size];
+                index = next as usize;
+                if t_min < closest_so_far {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Builder<'_> {
+    /// Adds the subtree of a cell with `objects`, their events on each axis are sorted and clipped to the cell
+    fn build(&mut self, events: [Vec<Event>; 3], objects: Vec<u32>, cell: AABB, depth: usize) {
+        let split = if depth < self.max_depth { self.find_split(&events, objects.len(), &cell) } else { None };
+        // Splitting has to be cheaper than intersecting all objects
+        let Some(split) = split.filter(|split| split.cost < objects.len() as f64 * INTERSECTION_COST) else {
+            self.nodes.push(KdNode::Leaf { first: self.indices.len() as u32, count: objects.len() as u32 });
+            self.indices.extend(objects);
+            return;
+        };
+
+        self.classify(&events[split.axis as usize], &objects, &split);
+        let (left_cell, right_cell) = split_cell(&cell, split.axis, split.position);
+        let on = |side: Side| objects.iter().copied().filter(|&object| self.sides[object as usize] != side).collect::<Vec<_>>();
+        let (left_objects, right_objects) = (on(Side::Right), on(Side::Left));
+        let (left_events, right_events) = self.split_events(events, &objects, &left_cell, &right_cell);
+        drop(objects);
+
+        let index = self.nodes.len();
+        self.nodes.push(KdNode::Leaf { first: 0, count: 0 });
+        self.build(left_events, left_objects, left_cell, depth + 1);
+        let right = self.nodes.len() as u32;
+        self.nodes[index] = KdNode::Split { axis: split.axis, position: split.position, right };
+        self.build(right_events, right_objects, right_cell, depth + 1);
+    }
+
+    /// Sweeps over the events of every axis, keeping track of the number of objects left of, in and right of the plane
+    fn find_split(&self, events: &[Vec<Event>; 3], count: usize, cell: &AABB) -> Option<Split> {
+        let mut best: Option<Split> = None;
+        for (axis, events) in (0..3).zip(events) {
+            let (mut left, mut right) = (0, count);
+            let mut i = 0;
+            while i < events.len() {
+                let position = events[i].position;
+                let mut counts = [0; 3];
+                for kind in [EventKind::End, EventKind::Planar, EventKind::Start] {
+                    while i < events.len() && events[i].position == position && events[i].kind == kind {
+                        counts[kind as usize] += 1;
+                        i += 1;
+                    }
+                }
+                let [ends, planar, starts] = counts;
+                right -= ends + planar;
+                if let Some(split) = self.sah(cell, axis, position, left, right, planar) {
+                    if best.as_ref().is_none_or(|best| split.cost < best.cost) {
+                        best = Some(split);
+                    }
+                }
+                left += starts + planar;
+            }
+        }
+        best
+    }
+
+    /// The cost of splitting `cell` at `position`, relative to intersecting one object. Objects in the plane go to the
+    /// side where they cost the least.
+    fn sah(&self, cell: &AABB, axis: u32, position: f64, left: usize, right: usize, planar: usize) -> Option<Split> {
+        // A plane on the border of the cell would make a child that is the same as its parent
+        if position <= cell.min[axis] || position >= cell.max[axis] {
+            return None;
+        }
+        let (left_cell, right_cell) = split_cell(cell, axis, position);
+        let area = cell.surface_area();
+        let (left_chance, right_chance) = (left_cell.surface_area() / area, right_cell.surface_area() / area);
+        let cost = |left: usize, right: usize| {
+            let cost = TRAVERSAL_COST + INTERSECTION_COST * (left_chance * left as f64 + right_chance * right as f64);
+            if left == 0 || right == 0 { cost * (1.0 - self.empty_bonus) } else { cost }
+        };
+        let (left_cost, right_cost) = (cost(left + planar, right), cost(left, right + planar));
+        Some(Split { axis, position, planar_left: left_cost <= right_cost, cost: left_cost.min(right_cost) })
+    }
+
+    /// Sets the side of every object from its events on the axis of the split
+    fn classify(&mut self, events: &[Event], objects: &[u32], split: &Split) {
+        for &object in objects {
+            self.sides[object as usize] = Side::Both;
+        }
+        for event in events {
+            let side = &mut self.sides[event.object as usize];
+            match event.kind {
+                EventKind::End if event.position <= split.position => *side = Side::Left,
+                EventKind::Start if event.position >= split.position => *side = Side::Right,
+                EventKind::Planar => {
+                    let left = event.position < split.position || event.position == split.position && split.planar_left;
+                    *side = if left { Side::Left } else { Side::Right };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The sorted events of the children: the events of objects on one side keep their order, the objects in both
+    /// children get new events for their box clipped to each child, which are sorted and merged in
+    fn split_events(&self, events: [Vec<Event>; 3], objects: &[u32], left_cell: &AABB, right_cell: &AABB)
+                    -> ([Vec<Event>; 3], [Vec<Event>; 3]) {
+        let both = objects.iter().copied().filter(|&object| self.sides[object as usize] == Side::Both).collect::<Vec<_>>();
+        let clipped = |cell: &AABB| both.iter().map(|&object| (object, clip(&self.boxes[object as usize], cell))).collect::<Vec<_>>();
+        let (left_clipped, right_clipped) = (clipped(left_cell), clipped(right_cell));
+
+        let mut axes = events.into_iter().zip(0..3).map(|(events, axis)| {
+            let mut left = Vec::with_capacity(events.len());
+            let mut right = Vec::with_capacity(events.len());
+            for event in events {
+                match self.sides[event.object as usize] {
+                    Side::Left => left.push(event),
+                    Side::Right => right.push(event),
+                    Side::Both => {}
+                }
+            }
+            let new_events = |clipped: &[(u32, AABB)]| {
+                let mut events = Vec::with_capacity(2 * clipped.len());
+                for (object, aabb) in clipped {
+                    push_events(aabb, *object, axis, &mut events);
+                }
+                events.sort_unstable_by(compare);
+                events
+            };
+            (merge(left, new_events(&left_clipped)), merge(right, new_events(&right_clipped)))
+        });
+        let [x, y, z] = [axes.next().unwrap(), axes.next().unwrap(), axes.next().unwrap()];
+        ([x.0, y.0, z.0], [x.1, y.1, z.1])
+    }
+}
+
+/// The events of `aabb` on `axis`, one for a box that is flat on that axis
+fn push_events(aabb: &AABB, object: u32, axis: u32, events: &mut Vec<Event>) {
+    if aabb.min[axis] == aabb.max[axis] {
+        events.push(Event { position: aabb.min[axis], kind: EventKind::Planar, object });
+    } else {
+        events.push(Event { position: aabb.min[axis], kind: EventKind::Start, object });
+        events.push(Event { position: aabb.max[axis], kind: EventKind::End, object });
+    }
+}
+
+fn compare(a: &Event, b: &Event) -> Ordering {
+    a.position.total_cmp(&b.position).then(a.kind.cmp(&b.kind))
+}
+
+/// Merges two sorted lists of events
+fn merge(a: Vec<Event>, b: Vec<Event>) -> Vec<Event> {
+    if b.is_empty() {
+        return a;
+    }
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+    while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+        let next = if compare(x, y) == Ordering::Greater { b.next() } else { a.next() };
+        merged.extend(next);
+    }
+    merged.extend(a);
+    merged.extend(b);
+    merged
+}
+
+/// The part of `aabb` inside `cell`
+fn clip(aabb: &AABB, cell: &AABB) -> AABB {
+    let mut clipped = aabb.clone();
+    for axis in 0..3 {
+        clipped.min[axis] = aabb.min[axis].max(cell.min[axis]);
+        clipped.max[axis] = aabb.max[axis].min(cell.max[axis]);
+    }
+    clipped
+}
+
+fn split_cell(cell: &AABB, axis: u32, position: f64) -> (AABB, AABB) {
+    let (mut left, mut right) = (cell.clone(), cell.clone());
+    left.max[axis] = position;
+    right.min[axis] = position;
+    (left, right)
+}
@@ -0,0 +1,264 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, Hittable};
+use crate::utils::rtweekend::Options;
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use std::rc::Rc;
+
+/// An adaptive octree: a cell is split into 8 equal children until it holds at most `max_objects` objects or is at
+/// `max_depth`, so empty space is covered by a few large cells instead of many grid boxes.
+/// The children of a cell are stored next to each other, child `4x + 2y + z` is on the high side of the axes with a 1.
+pub struct Octree {
+    objects: Vec<Rc<dyn Hittable>>,
+    /// The objects of the leaves, as indices into `objects`, see `OctreeNode::Leaf`
+    indices: Vec<u32>,
+    nodes: Vec<OctreeNode>,
+    /// The cell of the root, a cube around all objects
+    aabb: AABB,
+    stats: OctreeStats,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OctreeNode {
+    /// The 8 children are at `first..first + 8`
+    Cell { first: u32 },
+    /// The objects `first..first + count` of `Octree::indices`
+    Leaf { first: u32, count: u32 },
+}
+
+/// The cells of an octree, printed when it is built
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OctreeStats {
+    pub cells: usize,
+    pub leaves: usize,
+    pub empty_leaves: usize,
+    /// The depth of the deepest leaf, the root is at depth 0
+    pub depth: usize,
+    /// The number of objects in all leaves, objects in more than one cell are counted for every cell
+    pub references: usize,
+    /// The most objects in one leaf
+    pub max_objects: usize,
+}
+
+/// Cells with more objects are split, unless `--octree-objects` is given
+pub const MAX_OBJECTS: usize = 8;
+/// Cells at this depth are not split, unless `--octree-depth` is given
+pub const MAX_DEPTH: usize = 10;
+
+/// The root is made at least this large, so rays can enter a flat scene
+const MIN_SIZE: f64 = 1e-4;
+/// Direction components closer to zero are replaced by this, so the parameters of every plane are finite
+const MIN_DIRECTION: f64 = 1e-12;
+/// `first_child()` and `next_child()` return this when the ray leaves the cell
+const EXIT: usize = 8;
+
+/// The part of the ray in a cell: the parameters where it crosses the lower and upper plane of the cell on each axis
+#[derive(Clone, Copy)]
+struct Span {
+    t0: [f64; 3],
+    t1: [f64; 3],
+}
+
+/// The closest hit so far during a traversal
+struct Closest {
+    hit_anything: bool,
+    t: f64,
+}
+
+impl Octree {
+    pub fn new(objects: Vec<Rc<dyn Hittable>>, options: &Options) -> Self {
+        if objects.is_empty() {
+            return Self { objects, indices: vec![], nodes: vec![], aabb: AABB::default(), stats: OctreeStats::default() };
+        }
+        assert!(objects.len() <= u32::MAX as usize, "Too many objects for an octree: {}", objects.len());
+
+        let boxes = objects.iter().map(|object| object.to_aabb()).collect::<Vec<_>>();
+        let bounds = boxes.iter().cloned().reduce(|aabb, other| aabb + other).unwrap_or_default();
+        let extent = bounds.max - bounds.min;
+        let half = extent.x().max(extent.y()).max(extent.z()).max(MIN_SIZE) / 2.0;
+        let center = (bounds.min + bounds.max) / 2.0;
+        let aabb = AABB::new(center - Vec3::new(half, half, half), center + Vec3::new(half, half, half));
+
+        let mut octree = Self { objects, indices: vec![], nodes: vec![OctreeNode::Leaf { first: 0, count: 0 }], aabb, stats: OctreeStats::default() };
+        let max_objects = options.octree_objects.unwrap_or(MAX_OBJECTS);
+        let max_depth = options.octree_depth.unwrap_or(MAX_DEPTH);
+        octree.nodes[0] = octree.build(&boxes, (0..boxes.len() as u32).collect(), octree.aabb.clone(), 0, max_objects, max_depth);
+        octree.nodes.shrink_to_fit();
+        octree.indices.shrink_to_fit();
+        octree
+    }
+
+    /// Splits a cell with the `objects` that overlap it, the nodes of its children are added to `self.nodes`
+    fn build(&mut self, boxes: &[AABB], objects: Vec<u32>, cell: AABB, depth: usize, max_objects: usize,
+             max_depth: usize) -> OctreeNode {
+        self.stats.cells += 1;
+        let children = (objects.len() > max_objects && depth < max_depth).then(|| {
+            (0..8).map(|child| {
+                let cell = child_cell(&cell, child);
+                let objects = objects.iter().copied().filter(|&object| overlaps(&cell, &boxes[object as usize])).collect::<Vec<_>>();
+                (cell, objects)
+            }).collect::<Vec<_>>()
+        });
+        // Splitting does not help if every child has all objects, e.g. many objects that meet in one point
+        let children = children.filter(|children| children.iter().any(|(_, child)| child.len() < objects.len()));
+        let Some(children) = children else {
+            self.stats.leaves += 1;
+            self.stats.empty_leaves += objects.is_empty() as usize;
+            self.stats.depth = self.stats.depth.max(depth);
+            self.stats.references += objects.len();
+            self.stats.max_objects = self.stats.max_objects.max(objects.len());
+            let first = self.indices.len() as u32;
+            self.indices.extend(&objects);
+            return OctreeNode::Leaf { first, count: objects.len() as u32 };
+        };
+        drop(objects);
+
+        let first = self.nodes.len();
+        self.nodes.extend([OctreeNode::Leaf { first: 0, count: 0 }; 8]);
+        for (child, (cell, objects)) in children.into_iter().enumerate() {
+            self.nodes[first + child] = self.build(boxes, objects, cell, depth + 1, max_objects, max_depth);
+        }
+        OctreeNode::Cell { first: first as u32 }
+    }
+
+    /// The nodes, the root comes first
+    #[allow(dead_code)]
+    pub fn nodes(&self) -> &[OctreeNode] {
+        &self.nodes
+    }
+
+    /// The objects of a leaf
+    #[allow(dead_code)]
+    pub fn objects(&self, node: &OctreeNode) -> impl Iterator<Item = &Rc<dyn Hittable>> {
+        let OctreeNode::Leaf { first, count } = *node else { panic!("Cannot get objects for a cell that is split") };
+        self.indices[first as usize..(first + count) as usize].iter().map(|&index| &self.objects[index as usize])
+    }
+
+    /// The cell of the root
+    #[allow(dead_code)]
+    pub fn aabb(&self) -> &AABB {
+        &self.aabb
+    }
+
+    pub fn stats(&self) -> &OctreeStats {
+        &self.stats
+    }
+
+    /// Finds the closest hit with the parametric traversal of Revelles et al., "An efficient parametric algorithm for
+    /// octree traversal" (2000). The children of a cell are visited in the order the ray enters them, found from the
+    /// parameters where the ray crosses the planes of the cell and its middle planes. The traversal stops at the first
+    /// leaf that contains a hit.
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        // The ray is mirrored in the middle of the root so every direction is positive, `mirror` has the bits of the
+        // mirrored axes, so mirrored child `i` is child `i ^ mirror`
+        let mut origin = *r.origin();
+        let mut direction = *r.direction();
+        let mut mirror = 0;
+        for axis in 0..3 {
+            if direction[axis] < 0.0 {
+                origin[axis] = self.aabb.min[axis] + self.aabb.max[axis] - origin[axis];
+                direction[axis] = -direction[axis];
+                mirror |= 4 >> axis;
+            }
+            direction[axis] = direction[axis].max(MIN_DIRECTION);
+        }
+        let parameter = |plane: Point3, axis: u32| (plane[axis] - origin[axis]) / direction[axis];
+        let span = Span {
+            t0: [0, 1, 2].map(|axis| parameter(self.aabb.min, axis)),
+            t1: [0, 1, 2].map(|axis| parameter(self.aabb.max, axis)),
+        };
+        if span.entry() >= span.exit() {
+            return false;
+        }
+        let mut closest = Closest { hit_anything: false, t: ray_t.max };
+        self.traverse(0, span, mirror, r, ray_t, rec, data, &mut closest);
+        closest.hit_anything
+    }
+
+    /// Visits the node at `index` if the ray passes through it before the closest hit
+    /// Returns true when a hit is found inside the cell, so the cells after it do not have to be visited
+    #[allow(clippy::too_many_arguments)]
+    fn traverse(&self, index: usize, span: Span, mirror: usize, r: &Ray, ray_t: Interval, rec: &mut HitRecord,
+                data: &mut Data, closest: &mut Closest) -> bool {
+        if span.exit() < ray_t.min || span.entry() > closest.t {
+            return false;
+        }
+        data.add_traversal_step();
+        let first = match self.nodes[index] {
+            OctreeNode::Leaf { first, count } => {
+                for &object in &self.indices[first as usize..(first + count) as usize] {
+                    data.add_intersection_check();
+                    if self.objects[object as usize].hit(r, Interval::new(ray_t.min, closest.t), rec, data) {
+                        closest.hit_anything = true;
+                        closest.t = rec.t;
+                    }
+                }
+                return closest.hit_anything && closest.t <= span.exit();
+            }
+            OctreeNode::Cell { first } => first as usize,
+        };
+
+        let Span { t0, t1 } = span;
+        let tm = [0, 1, 2].map(|axis| (t0[axis] + t1[axis]) / 2.0);
+        let mut child = first_child(&t0, &tm);
+        while child < EXIT {
+            // Every bit of the child picks the lower or upper half of the cell on that axis
+            let half = |axis: usize| {
+                if child & (4 >> axis) == 0 { (t0[axis], tm[axis]) } else { (tm[axis], t1[axis]) }
+            };
+            let halves = [half(0), half(1), half(2)];
+            let child_span = Span { t0: halves.map(|(t0, _)| t0), t1: halves.map(|(_, t1)| t1) };
+            if self.traverse(first + (child ^ mirror), child_span, mirror, r, ray_t, rec, data, closest) {
+                return true;
+            }
+            child = next_child(child, &child_span.t1);
+        }
+        false
+    }
+}
+
+impl Span {
+    fn entry(&self) -> f64 {
+        self.t0[0].max(self.t0[1]).max(self.t0[2])
+    }
+
+    fn exit(&self) -> f64 {
+        self.t1[0].min(self.t1[1]).min(self.t1[2])
+    }
+}
+
+/// The child the ray enters first: the ray enters the cell through the plane of its largest `t0`, and is on the upper
+/// half of another axis if it crosses the middle plane of that axis before it enters
+fn first_child(t0: &[f64; 3], tm: &[f64; 3]) -> usize {
+    let entry_axis = (0..3).rev().max_by(|&a, &b| t0[a].total_cmp(&t0[b])).unwrap_or(0);
+    (0..3).filter(|&axis| axis != entry_axis && tm[axis] < t0[entry_axis]).map(|axis| 4 >> axis).sum()
+}
+
+/// The child the ray enters after leaving `child` through the plane of its smallest `t1`, `EXIT` if it leaves the cell
+fn next_child(child: usize, t1: &[f64; 3]) -> usize {
+    let exit_axis = (0..3).min_by(|&a, &b| t1[a].total_cmp(&t1[b])).unwrap_or(0);
+    let bit = 4 >> exit_axis;
+    if child & bit == 0 { child | bit } else { EXIT }
+}
+
+/// Child `4x + 2y + z` of `cell`
+fn child_cell(cell: &AABB, child: usize) -> AABB {
+    let center = (cell.min + cell.max) / 2.0;
+    let (mut min, mut max) = (cell.min, center);
+    for axis in 0..3 {
+        if child & (4 >> axis) != 0 {
+            min[axis] = center[axis];
+            max[axis] = cell.max[axis];
+        }
+    }
+    AABB::new(min, max)
+}
+
+fn overlaps(cell: &AABB, aabb: &AABB) -> bool {
+    (0..3).all(|axis| cell.min[axis] <= aabb.max[axis] && aabb.min[axis] <= cell.max[axis])
+}
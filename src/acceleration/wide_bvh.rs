@@ -0,0 +1,238 @@
+use crate::acceleration::aabb::AABB;
+use crate::acceleration::bvh::{Bvh, INTERSECTION_COST, MAX_DEPTH, TRAVERSAL_COST};
+use crate::hittable::{HitRecord, Hittable};
+use crate::utils::rtweekend::Options;
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::ray::Ray;
+use crate::value::vec3::Point3;
+use std::rc::Rc;
+
+/// A BVH where every node has up to `N` children, made by collapsing a binary `Bvh`
+/// The boxes of all children of a node are tested at once, with one lane per child
+pub struct WideBvh<const N: usize> {
+    objects: Vec<Rc<dyn Hittable>>,
+    nodes: Vec<WideNode<N>>,
+}
+
+/// Every level of the binary tree adds at most one level, and every level pushes at most `N - 1` children
+const STACK_SIZE: usize = MAX_DEPTH * 8;
+
+impl<const N: usize> WideBvh<N> {
+    /// Builds a binary BVH with `options`, then collapses it into nodes with `N` children
+    pub fn new(objects: Vec<Rc<dyn Hittable>>, options: &Options) -> Self {
+        assert!((2..=8).contains(&N), "Wide BVH nodes should have 2 to 8 children, not {N}");
+        let bvh = Bvh::new(objects, options);
+        let mut nodes = vec![];
+        if !bvh.nodes().is_empty() {
+            collapse(&bvh, 0, &mut nodes);
+        }
+        nodes.shrink_to_fit();
+        Self { objects: bvh.into_objects(), nodes }
+    }
+
+    /// The nodes in depth first order, the root comes first
+    #[allow(dead_code)]
+    pub fn nodes(&self) -> &[WideNode<N>] {
+        &self.nodes
+    }
+
+    /// The objects of a leaf child
+    #[allow(dead_code)]
+    pub fn objects(&self, node: &WideNode<N>, lane: usize) -> &[Rc<dyn Hittable>] {
+        if !node.is_leaf(lane) { panic!("Cannot get objects for intermediate child") }
+        &self.objects[node.child[lane] as usize..(node.child[lane] + node.count[lane]) as usize]
+    }
+
+    /// The expected cost of tracing a ray through the tree, like `Bvh::sah_cost()`
+    /// Testing the boxes of all children of a node costs as much as visiting one binary node
+    pub fn sah_cost(&self) -> f64 {
+        let Some(root) = self.nodes.first() else { return 0.0 };
+        let root_area = root.aabb().surface_area();
+        self.nodes.iter().map(|node| {
+            let leaves = (0..N).filter(|&lane| node.count[lane] > 0)
+                .map(|lane| node.child_aabb(lane).surface_area() * node.count[lane] as f64 * INTERSECTION_COST)
+                .sum::<f64>();
+            (node.aabb().surface_area() * TRAVERSAL_COST + leaves) / root_area
+        }).sum()
+    }
+
+    /// Finds the closest hit, the children of a node are visited nearest first
+    /// Testing the boxes of all children of a node counts as a single intersection check
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data, options: &Options) -> bool {
+        if self.nodes.is_empty() { return false }
+        let ray = LaneRay::new(r);
+
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+        // The children that still have to be visited, as (child, count, distance) like in `WideNode`
+        let mut stack = [(0u32, 0u32, 0.0); STACK_SIZE];
+        stack[0] = (0, 0, ray_t.min);
+        let mut size = 1;
+        while size > 0 {
+            size -= 1;
+            let (child, count, distance) = stack[size];
+            // Something closer was found after this child was pushed
+            if distance >= closest_so_far {
+                continue;
+            }
+            if hit_anything {
+                data.add_overlapping_aabb();
+            }
+            if count > 0 {
+                for object in &self.objects[child as usize..(child + count) as usize] {
+                    data.add_intersection_check();
+                    if object.hit(r, Interval::new(ray_t.min, closest_so_far), rec, data) {
+                        hit_anything = true;
+                        closest_so_far = rec.t;
+                    }
+                }
+                continue;
+            }
+
+            data.add_traversal_step();
+            data.add_intersection_check();
+            let node = &self.nodes[child as usize];
+            let distances = node.hit_lanes(&ray, Interval::new(ray_t.min, closest_so_far));
+            // The children that are hit, sorted from far to near
+            let mut hits = [0; N];
+            let mut count = 0;
+            for lane in (0..N).filter(|&lane| distances[lane].is_some()) {
+                let mut position = count;
+                while position > 0 && distances[hits[position - 1]] < distances[lane] {
+                    hits[position] = hits[position - 1];
+                    position -= 1;
+                }
+                hits[position] = lane;
+                count += 1;
+            }
+            // Push the farthest child first, so the nearest is visited next
+            for &lane in &hits[..count] {
+                if options.draw_boxes {
+                    node.child_aabb(lane).hit(r, Interval::new(ray_t.min, closest_so_far), rec, options);
+                }
+                stack[size] = (node.child[lane], node.count[lane], distances[lane].unwrap());
+                size += 1;
+            }
+        }
+        hit_anything
+    }
+}
+
+/// A node with the boxes of its children stored per axis, so each child is a lane
+/// Unused lanes have an empty box, which is never hit
+#[derive(Clone, Debug, PartialEq)]
+pub struct WideNode<const N: usize> {
+    min: [[f32; N]; 3],
+    max: [[f32; N]; 3],
+    /// The index of the node of an intermediate child, or the first object of a leaf
+    child: [u32; N],
+    /// The number of objects of a leaf, 0 for an intermediate child or an unused lane
+    count: [u32; N],
+}
+
+impl<const N: usize> WideNode<N> {
+    fn empty() -> Self {
+        Self { min: [[f32::INFINITY; N]; 3], max: [[f32::NEG_INFINITY; N]; 3], child: [0; N], count: [0; N] }
+    }
+
+    /// The number of children, the used lanes come first
+    pub fn children(&self) -> usize {
+        (0..N).take_while(|&lane| self.min[0][lane] <= self.max[0][lane]).count()
+    }
+
+    pub fn is_leaf(&self, lane: usize) -> bool {
+        self.count[lane] > 0
+    }
+
+    /// The index of the node of an intermediate child
+    #[allow(dead_code)]
+    pub fn child(&self, lane: usize) -> usize {
+        if self.is_leaf(lane) { panic!("Cannot get child node for leaf") }
+        self.child[lane] as usize
+    }
+
+    pub fn child_aabb(&self, lane: usize) -> AABB {
+        let point = |e: &[[f32; N]; 3]| Point3::new(e[0][lane] as f64, e[1][lane] as f64, e[2][lane] as f64);
+        AABB::new(point(&self.min), point(&self.max))
+    }
+
+    /// The box around all children
+    pub fn aabb(&self) -> AABB {
+        (0..self.children()).map(|lane| self.child_aabb(lane))
+            .reduce(|aabb, other| aabb + other)
+            .unwrap_or_default()
+    }
+
+    /// The slab test of all lanes at once, returns the distance to every child box that is hit
+    /// The loops over the lanes have no branches, so they are compiled to SIMD instructions
+    fn hit_lanes(&self, ray: &LaneRay, ray_t: Interval) -> [Option<f64>; N] {
+        let mut near = [ray_t.min; N];
+        let mut far = [ray_t.max; N];
+        for axis in 0..3 {
+            // The near side of the boxes faces the origin of the ray
+            let (near_side, far_side) = if ray.negative[axis] { (&self.max, &self.min) } else { (&self.min, &self.max) };
+            for lane in 0..N {
+                let t0 = (near_side[axis][lane] as f64 - ray.origin[axis]) * ray.inverse[axis];
+                let t1 = (far_side[axis][lane] as f64 - ray.origin[axis]) * ray.inverse[axis];
+                near[lane] = near[lane].max(t0);
+                far[lane] = far[lane].min(t1);
+            }
+        }
+        std::array::from_fn(|lane| (near[lane] <= far[lane]).then_some(near[lane]))
+    }
+}
+
+/// A ray prepared for `WideNode::hit_lanes()`
+struct LaneRay {
+    origin: [f64; 3],
+    inverse: [f64; 3],
+    negative: [bool; 3],
+}
+
+impl LaneRay {
+    fn new(r: &Ray) -> Self {
+        let inverse = [0, 1, 2].map(|axis| 1.0 / r.direction()[axis]);
+        Self {
+            origin: [0, 1, 2].map(|axis| r.origin()[axis]),
+            inverse,
+            negative: inverse.map(|inverse| inverse.is_sign_negative()),
+        }
+    }
+}
+
+/// Collapses the binary subtree at `index` into a node and its descendants, returns the index of the node
+/// A node starts with the children of the binary node, and keeps replacing its intermediate child with the largest
+/// surface area by the children of that child. A ray hits that child the most often, so removing it lowers the SAH
+/// cost the most.
+fn collapse<const N: usize>(bvh: &Bvh, index: usize, nodes: &mut Vec<WideNode<N>>) -> usize {
+    let binary = bvh.nodes();
+    let children = |index: usize| [index + 1, binary[index].right()];
+    let mut lanes = if binary[index].is_leaf() { vec![index] } else { children(index).to_vec() };
+    while lanes.len() < N {
+        let largest = lanes.iter().enumerate()
+            .filter(|&(_, &child)| !binary[child].is_leaf())
+            .max_by(|(_, &a), (_, &b)| binary[a].aabb().surface_area().total_cmp(&binary[b].aabb().surface_area()));
+        let Some((lane, &child)) = largest else { break };
+        lanes.splice(lane..=lane, children(child));
+    }
+
+    let position = nodes.len();
+    nodes.push(WideNode::empty());
+    let mut node = WideNode::empty();
+    for (lane, &child) in lanes.iter().enumerate() {
+        let (min, max) = binary[child].bounds();
+        for axis in 0..3 {
+            node.min[axis][lane] = min[axis];
+            node.max[axis][lane] = max[axis];
+        }
+        if binary[child].is_leaf() {
+            node.child[lane] = binary[child].first() as u32;
+            node.count[lane] = binary[child].count() as u32;
+        } else {
+            node.child[lane] = collapse(bvh, child, nodes) as u32;
+        }
+    }
+    nodes[position] = node;
+    position
+}
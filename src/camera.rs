@@ -5,11 +5,52 @@ use crate::hittable::hittable_list::HittableList;
 use crate::value::interval::Interval;
 use crate::value::ray::Ray;
 use crate::utils::rtweekend::{degrees_to_radians, random_double};
+use crate::value::material::MaterialType;
+use crate::value::transform::Transform;
 use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 use indicatif::{ProgressBar, ProgressStyle};
 use std::io;
 use std::io::Write;
 
+/// The camera as stored in a scene, settings that are not given keep their defaults
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraSettings {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    #[serde(default = "default_v_up")]
+    pub v_up: Vec3,
+    /// Vertical field of view in degrees
+    pub vfov: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub defocus_angle: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_dist: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub samples_per_pixel: Option<u32>,
+}
+
+impl CameraSettings {
+    /// The camera moved by `transform`, the focus distance is not scaled
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        Self {
+            look_from: transform.point(self.look_from),
+            look_at: transform.point(self.look_at),
+            v_up: transform.vector(self.v_up).unit(),
+            ..self.clone()
+        }
+    }
+}
+
+fn default_v_up() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
 #[derive(Default)]
 pub struct Camera {
     pub aspect_ratio: f64,
@@ -50,6 +91,18 @@ impl Camera {
         }
     }
 
+    pub fn apply(&mut self, settings: &CameraSettings) {
+        self.look_from = settings.look_from;
+        self.look_at = settings.look_at;
+        self.v_up = settings.v_up;
+        self.vfov = settings.vfov;
+        if let Some(aspect_ratio) = settings.aspect_ratio { self.aspect_ratio = aspect_ratio; }
+        if let Some(defocus_angle) = settings.defocus_angle { self.defocus_angle = defocus_angle; }
+        if let Some(focus_dist) = settings.focus_dist { self.focus_dist = focus_dist; }
+        if let Some(image_width) = settings.image_width { self.image_width = image_width; }
+        if let Some(samples_per_pixel) = settings.samples_per_pixel { self.samples_per_pixel = samples_per_pixel; }
+    }
+
     pub fn render(&mut self, world: &HittableList, writer: &mut impl Write, data: &mut Data) -> io::Result<()> {
         self.initialise();
 
@@ -150,7 +203,7 @@ impl Camera {
 
 
 /// Determine the ray colour for the ray tracing algorithm
-fn ray_color(r: &Ray, depth: u32, world: &dyn Hittable, data: &mut Data) -> Color {
+fn ray_color(r: &Ray, depth: u32, world: &HittableList, data: &mut Data) -> Color {
     // Stop gathering light if the ray bounce limit is exceeded
     if depth == 0 {
         return Color::default();
@@ -167,10 +220,18 @@ fn ray_color(r: &Ray, depth: u32, world: &dyn Hittable, data: &mut Data) -> Colo
         let mut attenuation = Color::default();
 
         if let Some(mat) = &rec.mat {
+            let emitted = mat.emitted(&rec);
             if mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
                 data.add_scatter_ray();
-                return attenuation * ray_color(&scattered, depth - 1, world, data);
+                // Lights without a surface can only be found by sampling them
+                let direct = if mat.get_type() == MaterialType::Lambertian {
+                    direct_light(&rec, attenuation, world, data)
+                } else {
+                    Color::default()
+                };
+                return emitted + direct + attenuation * ray_color(&scattered, depth - 1, world, data);
             }
+            return emitted;
         }
 
         Color::default()
@@ -185,6 +246,26 @@ fn ray_color(r: &Ray, depth: u32, world: &dyn Hittable, data: &mut Data) -> Colo
     }
 }
 
+/// The light from the scene lights reflected by a diffuse surface, using shadow rays
+fn direct_light(rec: &HitRecord, albedo: Color, world: &HittableList, data: &mut Data) -> Color {
+    let mut color = Color::default();
+    for light in &world.lights {
+        let Some((to_light, distance, incoming)) = light.sample(rec.p) else {
+            continue;
+        };
+        let cos_theta = rec.normal.dot(&to_light);
+        if cos_theta <= 0.0 {
+            continue;
+        }
+        let shadow_ray = Ray::new(rec.p, to_light);
+        if world.hit(&shadow_ray, Interval::new(0.001, distance), &mut HitRecord::default(), data) {
+            continue;
+        }
+        color += cos_theta / PI * albedo * incoming;
+    }
+    color
+}
+
 /// Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
 fn sample_square() -> Vec3 {
     Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
@@ -1,6 +1,8 @@
 use std::rc::Rc;
 use crate::acceleration::aabb::AABB;
 use crate::value::data::Data;
+use crate::hittable::triangle::Triangle;
+use crate::value::color::Color;
 use crate::value::interval::Interval;
 use crate::value::material::{Material, MaterialType};
 use crate::value::ray::Ray;
@@ -9,6 +11,17 @@ use crate::value::vec3::{Point3, Vec3};
 pub mod sphere;
 pub mod hittable_list;
 pub mod triangle;
+pub mod quad;
+pub mod disk;
+pub mod cuboid;
+pub mod plane;
+pub mod instance;
+pub mod cylinder;
+pub mod cone;
+pub mod paraboloid;
+pub mod torus;
+pub mod csg;
+pub mod sdf;
 
 #[derive(Default, Clone)]
 pub struct HitRecord {
@@ -16,11 +29,35 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub mat: Option<Rc<dyn Material>>,
     pub t: f64,
+    /// Surface coordinates of the hit point
+    pub u: f64,
+    pub v: f64,
+    /// Interpolated vertex color, multiplied with the albedo of the material
+    pub color: Option<Color>,
     pub front_face: bool,
     pub hits_aabb_edge: bool,
 }
 
 impl HitRecord {
+    /// Creates a hit record for a hit at distance `t` along the ray
+    pub fn at(r: &Ray, t: f64, outward_normal: Vec3, (u, v): (f64, f64), mat: &Rc<dyn Material>) -> Self {
+        let mut rec = Self { p: r.at(t), t, u, v, mat: Some(Rc::clone(mat)), ..Default::default() };
+        rec.set_face_normal(r, outward_normal);
+        rec
+    }
+
+    /// Copies the result of a hit into this record, keeping flags that were set during traversal
+    pub fn update(&mut self, hit: HitRecord) {
+        let hits_aabb_edge = self.hits_aabb_edge;
+        *self = hit;
+        self.hits_aabb_edge |= hits_aabb_edge;
+    }
+
+    /// The normal pointing out of the object, regardless of the side that was hit
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
     /// Sets the hit record normal vector.
     /// Note: the parameter `outward_normal` is assumed to have unit length.
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
@@ -38,8 +75,61 @@ pub trait Hittable {
     fn centroid(&self) -> Point3;
     
     fn surface_area(&self) -> f64;
-    
+
+    /// Unbounded objects (like infinite planes) cannot be put in an acceleration structure,
+    /// they are checked separately by the `HittableList`
+    fn is_bounded(&self) -> bool {
+        true
+    }
+
+    /// All parts of the (infinite) line through the ray that are inside this object, sorted along the ray.
+    /// This is used for constructive solid geometry, so only closed objects support it.
+    fn hit_spans(&self, _r: &Ray, _data: &mut Data) -> Option<Vec<HitSpan>> {
+        None
+    }
+
     fn material_type(&self) -> Option<MaterialType> {
         None
     }
+
+    /// The object as a triangle, so meshes can be stored as vertex and index buffers
+    fn as_triangle(&self) -> Option<&Triangle> {
+        None
+    }
+}
+
+/// A part of a ray that is inside an object, from where the ray enters to where it exits it
+/// Either side can be at an infinite distance, for unbounded objects or when the ray starts inside
+#[derive(Clone)]
+pub struct HitSpan {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+/// Turns all points where a ray crosses the surface of a closed object into spans,
+/// using the direction of the crossing (`front_face`) to determine if the ray enters or exits
+pub fn spans_from_crossings(mut crossings: Vec<HitRecord>) -> Vec<HitSpan> {
+    crossings.sort_by(|a, b| a.t.total_cmp(&b.t));
+    let infinity = |t: f64| HitRecord { t, ..Default::default() };
+
+    let mut spans = Vec::new();
+    let mut depth = 0;
+    let mut enter = None;
+    for crossing in crossings {
+        if crossing.front_face {
+            if depth == 0 { enter = Some(crossing); }
+            depth += 1;
+        } else if depth <= 1 {
+            // The first crossing is an exit if the ray starts inside (or before the start of the mesh)
+            let enter = enter.take().unwrap_or_else(|| infinity(f64::NEG_INFINITY));
+            spans.push(HitSpan { enter, exit: crossing });
+            depth = 0;
+        } else {
+            depth -= 1;
+        }
+    }
+    if let Some(enter) = enter {
+        spans.push(HitSpan { enter, exit: infinity(f64::INFINITY) });
+    }
+    spans
 }
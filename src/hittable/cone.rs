@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{spans_from_crossings, HitRecord, HitSpan, Hittable};
+use crate::utils::polynomial::solve_quadratic;
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A cone around the y axis, with its base on the xz plane at the origin and its apex at y = height
+/// Use an `Instance` to place it somewhere else in the scene
+#[derive(Serialize, Deserialize)]
+pub struct Cone {
+    radius: f64,
+    height: f64,
+    #[serde(default = "default_capped")]
+    capped: bool,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+fn default_capped() -> bool { true }
+
+impl Cone {
+    pub fn new(radius: f64, height: f64, capped: bool, mat: Rc<dyn Material>) -> Self {
+        Self { radius: f64::max(0.0, radius), height: f64::max(0.0, height), capped, mat }
+    }
+
+    /// Calls `visit` with the distance, outward normal and (u, v) coordinates
+    /// of every point where the (infinite) ray crosses the surface
+    fn surface_hits(&self, r: &Ray, mut visit: impl FnMut(f64, Vec3, (f64, f64))) {
+        let (o, d) = (r.origin(), r.direction());
+
+        // Side: x^2 + z^2 = (k (height - y))^2 with k = radius / height
+        let k_squared = (self.radius / self.height).powi(2);
+        let y0 = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k_squared * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z() + k_squared * y0 * d.y());
+        let c = o.x() * o.x() + o.z() * o.z() - k_squared * y0 * y0;
+        for &t in solve_quadratic(a, b, c).as_slice() {
+            let p = r.at(t);
+            if (0.0..=self.height).contains(&p.y()) {
+                let normal = Vec3::new(p.x(), k_squared * (self.height - p.y()), p.z());
+                // The normal is not defined at the apex
+                let normal = if normal.near_zero() { Vec3::new(0.0, 1.0, 0.0) } else { normal.unit() };
+                let phi = (-p.z()).atan2(p.x()) + PI;
+                visit(t, normal, (phi / (2.0 * PI), p.y() / self.height));
+            }
+        }
+
+        // Base: y = 0
+        if self.capped && d.y().abs() > 1e-12 {
+            let t = -o.y() / d.y();
+            let p = r.at(t);
+            if p.x() * p.x() + p.z() * p.z() <= self.radius * self.radius {
+                let u = 0.5 + p.x() / (2.0 * self.radius);
+                let v = 0.5 + p.z() / (2.0 * self.radius);
+                visit(t, Vec3::new(0.0, -1.0, 0.0), (u, v));
+            }
+        }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        let mut closest: Option<(f64, Vec3, (f64, f64))> = None;
+        self.surface_hits(r, |t, outward_normal, uv| {
+            if ray_t.surrounds(t) && closest.is_none_or(|(closest_t, ..)| t < closest_t) {
+                closest = Some((t, outward_normal, uv));
+            }
+        });
+
+        if let Some((t, outward_normal, uv)) = closest {
+            rec.update(HitRecord::at(r, t, outward_normal, uv, &self.mat));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn to_aabb(&self) -> AABB {
+        AABB::new(Point3::new(-self.radius, 0.0, -self.radius), Point3::new(self.radius, self.height, self.radius))
+    }
+
+    fn centroid(&self) -> Point3 {
+        Point3::new(0.0, self.height / 4.0, 0.0)
+    }
+
+    fn surface_area(&self) -> f64 {
+        let side = PI * self.radius * (self.radius * self.radius + self.height * self.height).sqrt();
+        if self.capped { side + PI * self.radius * self.radius } else { side }
+    }
+
+    fn hit_spans(&self, r: &Ray, _data: &mut Data) -> Option<Vec<HitSpan>> {
+        if !self.capped {
+            return None;
+        }
+        let mut crossings = Vec::new();
+        self.surface_hits(r, |t, outward_normal, uv| {
+            crossings.push(HitRecord::at(r, t, outward_normal, uv, &self.mat));
+        });
+        Some(spans_from_crossings(crossings))
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, HitSpan, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::MaterialType;
+use crate::value::ray::Ray;
+use crate::value::vec3::Point3;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// `a` minus `b`
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_a || inside_b,
+            CsgOperation::Intersection => inside_a && inside_b,
+            CsgOperation::Difference => inside_a && !inside_b,
+        }
+    }
+}
+
+/// Constructive solid geometry: combines two closed objects
+/// Both objects have to support `Hittable::hit_spans()`, which is also used for the intersection
+#[derive(Serialize, Deserialize)]
+pub struct Csg {
+    operation: CsgOperation,
+    a: Rc<dyn Hittable>,
+    b: Rc<dyn Hittable>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, a: Rc<dyn Hittable>, b: Rc<dyn Hittable>) -> Self {
+        Self { operation, a, b }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        let Some(spans) = self.hit_spans(r, data) else {
+            return false;
+        };
+
+        // The first boundary of the combined spans inside the interval is the hit
+        let hit = spans.into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|boundary| ray_t.surrounds(boundary.t));
+        if let Some(hit) = hit {
+            rec.update(hit);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn to_aabb(&self) -> AABB {
+        match self.operation {
+            CsgOperation::Union => self.a.to_aabb() + self.b.to_aabb(),
+            CsgOperation::Intersection => {
+                let (a, b) = (self.a.to_aabb(), self.b.to_aabb());
+                AABB::new(
+                    Point3::new(a.min.x().max(b.min.x()), a.min.y().max(b.min.y()), a.min.z().max(b.min.z())),
+                    Point3::new(a.max.x().min(b.max.x()), a.max.y().min(b.max.y()), a.max.z().min(b.max.z())),
+                )
+            }
+            CsgOperation::Difference => self.a.to_aabb(),
+        }
+    }
+
+    fn centroid(&self) -> Point3 {
+        let aabb = self.to_aabb();
+        0.5 * (aabb.min + aabb.max)
+    }
+
+    fn surface_area(&self) -> f64 {
+        // Upper bound, the exact area would need the intersection curve of both surfaces
+        match self.operation {
+            CsgOperation::Union | CsgOperation::Difference => self.a.surface_area() + self.b.surface_area(),
+            CsgOperation::Intersection => self.a.surface_area().min(self.b.surface_area()),
+        }
+    }
+
+    fn is_bounded(&self) -> bool {
+        match self.operation {
+            CsgOperation::Union => self.a.is_bounded() && self.b.is_bounded(),
+            CsgOperation::Intersection => self.a.is_bounded() || self.b.is_bounded(),
+            CsgOperation::Difference => self.a.is_bounded(),
+        }
+    }
+
+    /// Combines the spans of both objects by walking over all their boundaries in order
+    fn hit_spans(&self, r: &Ray, data: &mut Data) -> Option<Vec<HitSpan>> {
+        let spans_a = self.a.hit_spans(r, data)?;
+        let spans_b = self.b.hit_spans(r, data)?;
+
+        let mut boundaries = Vec::with_capacity(2 * (spans_a.len() + spans_b.len()));
+        for (spans, from_a) in [(spans_a, true), (spans_b, false)] {
+            for span in spans {
+                boundaries.push((span.enter, from_a, true));
+                boundaries.push((span.exit, from_a, false));
+            }
+        }
+        boundaries.sort_by(|(a, ..), (b, ..)| a.t.total_cmp(&b.t));
+
+        let mut result = Vec::new();
+        let (mut inside_a, mut inside_b) = (false, false);
+        let mut enter: Option<HitRecord> = None;
+        for (mut boundary, from_a, entering) in boundaries {
+            let was_inside = self.operation.inside(inside_a, inside_b);
+            if from_a { inside_a = entering } else { inside_b = entering }
+            let is_inside = self.operation.inside(inside_a, inside_b);
+            if was_inside == is_inside {
+                continue;
+            }
+
+            // The surface of `b` is inside out when it is subtracted
+            if !from_a && self.operation == CsgOperation::Difference && boundary.t.is_finite() {
+                let outward_normal = -boundary.outward_normal();
+                boundary.set_face_normal(r, outward_normal);
+            }
+
+            if is_inside {
+                enter = Some(boundary);
+            } else if let Some(enter) = enter.take() {
+                result.push(HitSpan { enter, exit: boundary });
+            }
+        }
+        Some(result)
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        self.a.material_type()
+    }
+}
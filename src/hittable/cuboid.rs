@@ -0,0 +1,135 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, HitSpan, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A box with six rectangular sides, the local `axes` can be rotated to get an oriented box.
+/// Called `Box` in scene files.
+#[derive(Serialize, Deserialize)]
+pub struct Cuboid {
+    center: Point3,
+    half_extents: Vec3,
+    #[serde(default = "world_axes")]
+    axes: [Vec3; 3],
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+fn world_axes() -> [Vec3; 3] {
+    [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)]
+}
+
+impl Cuboid {
+    /// Create an axis-aligned box with opposite corners `a` and `b`
+    pub fn new(a: Point3, b: Point3, mat: Rc<dyn Material>) -> Self {
+        let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        Self { center: 0.5 * (min + max), half_extents: 0.5 * (max - min), axes: world_axes(), mat }
+    }
+
+    /// Create an oriented box, the local z axis is the cross product of `x_axis` and `y_axis`
+    pub fn oriented(center: Point3, half_extents: Vec3, x_axis: Vec3, y_axis: Vec3, mat: Rc<dyn Material>) -> Self {
+        let x_axis = x_axis.unit();
+        // Make sure the axes are orthogonal
+        let z_axis = x_axis.cross(&y_axis).unit();
+        let y_axis = z_axis.cross(&x_axis);
+        Self { center, half_extents, axes: [x_axis, y_axis, z_axis], mat }
+    }
+
+    /// Slab method in the local frame of the box
+    /// Returns the entry and exit distances along the (infinite) ray, with the local axis of the face
+    fn slabs(&self, r: &Ray) -> Option<((f64, usize), (f64, usize))> {
+        let offset = *r.origin() - self.center;
+        let mut enter = (f64::NEG_INFINITY, 0);
+        let mut exit = (f64::INFINITY, 0);
+        for (axis, local_axis) in self.axes.iter().enumerate() {
+            let origin = offset.dot(local_axis);
+            let direction = r.direction().dot(local_axis);
+            let half = self.half_extents[axis as u32];
+
+            if direction.abs() < 1e-12 {
+                // Parallel to this slab, so the origin must be inside it
+                if origin.abs() > half { return None; }
+                continue;
+            }
+
+            let t0 = (-half - origin) / direction;
+            let t1 = (half - origin) / direction;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if near > enter.0 { enter = (near, axis); }
+            if far < exit.0 { exit = (far, axis); }
+            if exit.0 < enter.0 { return None; }
+        }
+        Some((enter, exit))
+    }
+
+    fn record(&self, r: &Ray, t: f64, axis: usize) -> HitRecord {
+        // The normal points along the local axis of the face that was hit
+        let local_p = r.at(t) - self.center;
+        let side = local_p.dot(&self.axes[axis]).signum();
+
+        // Use the other two local coordinates on the face
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let u = 0.5 + local_p.dot(&self.axes[a]) / (2.0 * self.half_extents[a as u32]);
+        let v = 0.5 + local_p.dot(&self.axes[b]) / (2.0 * self.half_extents[b as u32]);
+        HitRecord::at(r, t, side * self.axes[axis], (u, v), &self.mat)
+    }
+}
+
+#[typetag::serde(name = "Box")]
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        let Some(((t_enter, enter_axis), (t_exit, exit_axis))) = self.slabs(r) else {
+            return false;
+        };
+
+        // Use the entry point, or the exit point if the ray starts inside the box
+        let (t, axis) = if ray_t.surrounds(t_enter) {
+            (t_enter, enter_axis)
+        } else if ray_t.surrounds(t_exit) {
+            (t_exit, exit_axis)
+        } else {
+            return false;
+        };
+
+        rec.update(self.record(r, t, axis));
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        let mut extent = Vec3::default();
+        for (axis, local_axis) in self.axes.iter().enumerate() {
+            for world_axis in 0..3 {
+                extent[world_axis] += local_axis[world_axis].abs() * self.half_extents[axis as u32];
+            }
+        }
+        AABB::new(self.center - extent, self.center + extent).padded(1e-4)
+    }
+
+    fn centroid(&self) -> Point3 {
+        self.center
+    }
+
+    fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.half_extents.x(), self.half_extents.y(), self.half_extents.z());
+        8.0 * (x * y + x * z + y * z)
+    }
+
+    fn hit_spans(&self, r: &Ray, _data: &mut Data) -> Option<Vec<HitSpan>> {
+        let spans = self.slabs(r)
+            .map(|((t_enter, enter_axis), (t_exit, exit_axis))| HitSpan {
+                enter: self.record(r, t_enter, enter_axis),
+                exit: self.record(r, t_exit, exit_axis),
+            });
+        Some(spans.into_iter().collect())
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
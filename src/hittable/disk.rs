@@ -0,0 +1,85 @@
+use std::f64::consts::PI;
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A flat circle around `center`, facing `normal`
+#[derive(Serialize, Deserialize)]
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    radius: f64,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, mat: Rc<dyn Material>) -> Self {
+        Self { center, normal: normal.unit(), radius: f64::max(0.0, radius), mat }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        // Check if the ray and plane are parallel
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = self.normal.dot(&(self.center - *r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+
+        // Check if the plane intersection is inside the circle
+        let p = r.at(t);
+        let offset = p - self.center;
+        let distance_squared = offset.length_squared();
+        if distance_squared > self.radius * self.radius {
+            return false;
+        }
+
+        // Polar coordinates: u is the angle, v the distance to the center
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let phi = offset.dot(&bitangent).atan2(offset.dot(&tangent)) + PI;
+
+        rec.t = t;
+        rec.p = p;
+        rec.u = phi / (2.0 * PI);
+        rec.v = distance_squared.sqrt() / self.radius;
+        rec.set_face_normal(r, self.normal);
+        rec.color = None;
+        rec.mat = Some(Rc::clone(&self.mat));
+
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        // The extent of a circle along an axis depends on how much it is tilted towards that axis
+        let mut extent = Vec3::default();
+        for axis in 0..3 {
+            extent[axis] = self.radius * (1.0 - self.normal[axis] * self.normal[axis]).max(0.0).sqrt();
+        }
+        AABB::new(self.center - extent, self.center + extent).padded(1e-4)
+    }
+
+    fn centroid(&self) -> Point3 {
+        self.center
+    }
+
+    fn surface_area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
@@ -1,46 +1,197 @@
 use crate::acceleration::aabb::AABB;
+use crate::camera::CameraSettings;
+use crate::value::light::Light;
+use crate::utils::mesh::{load_mesh, ImportOptions, MeshReference};
+use crate::utils::parser::ParseError;
+use crate::utils::scene_file::{self, SCENE_VERSION};
+use crate::value::material::{shared, MaterialTable};
 use crate::acceleration::bvh::Bvh;
 use crate::value::data::Data;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{spans_from_crossings, HitRecord, HitSpan, Hittable};
 use crate::value::interval::Interval;
 use crate::value::ray::Ray;
-use crate::utils::rtweekend::{IntersectionAlgorithm, Options};
-use serde::{Deserialize, Serialize};
+use crate::utils::rtweekend::{FileFormat, IntersectionAlgorithm, Options};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use crate::acceleration::grid::Grid;
+use crate::acceleration::kd_tree::KdTree;
+use crate::acceleration::octree::Octree;
+use crate::acceleration::wide_bvh::WideBvh;
+use crate::hittable::instance::Instance;
+use crate::value::transform::Transform;
 use crate::value::vec3::{Point3, Vec3};
 use std::time::Instant;
 
-#[derive(Default, Serialize, Deserialize)]
+/// Included scene files can include other files, up to this depth
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// A scene, or a group of objects in a scene
+/// The materials of the objects are written once, in `materials`, the objects refer to them by index
+#[derive(Default)]
 pub struct HittableList {
     pub objects: Vec<Rc<dyn Hittable>>,
-    #[serde(skip)]
+    pub camera: Option<CameraSettings>,
+    pub lights: Vec<Light>,
+    /// Materials that objects and meshes can refer to by name or index
+    pub materials: MaterialTable,
+    /// Mesh files that are added to the objects by `load_meshes()`
+    pub meshes: Vec<MeshReference>,
+    /// Scene files that are added to this scene by `load()`, relative to this file
+    pub include: Vec<PathBuf>,
     pub algorithm: IntersectionAlgorithm,
-    #[serde(skip)]
     pub options: Options,
-    #[serde(skip)]
     bvh: Option<Bvh>,
-    #[serde(skip)]
+    bvh4: Option<WideBvh<4>>,
+    bvh8: Option<WideBvh<8>>,
     grid: Option<Grid>,
+    kd_tree: Option<KdTree>,
+    octree: Option<Octree>,
+    /// Objects that cannot be put in the acceleration structure, see `Hittable::is_bounded()`
+    unbounded: Vec<Rc<dyn Hittable>>,
+}
+
+/// The fields of a scene file besides the materials
+#[derive(Deserialize)]
+struct SceneFile {
+    #[serde(default)]
+    objects: Vec<Rc<dyn Hittable>>,
+    #[serde(default)]
+    camera: Option<CameraSettings>,
+    #[serde(default)]
+    lights: Vec<Light>,
+    #[serde(default)]
+    meshes: Vec<MeshReference>,
+    #[serde(default)]
+    include: Vec<PathBuf>,
 }
 
 impl HittableList {
     #[allow(dead_code)]
     pub fn new(object: Rc<dyn Hittable>) -> Self {
-        Self { objects: vec![object], algorithm: Default::default(), options: Default::default(), bvh: None, grid: None }
+        Self { objects: vec![object], ..Default::default() }
+    }
+
+    /// Loads a scene file together with the scene files it includes and the mesh files it uses
+    /// The materials of included files can be used as well
+    pub fn load(path: &Path) -> Result<Self, ParseError> {
+        Self::load_included(path, 0)
+    }
+
+    fn load_included(path: &Path, depth: usize) -> Result<Self, ParseError> {
+        let mut scene: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        // Included files are loaded first, so their materials are known when the objects are read
+        let includes = match scene.as_object_mut().and_then(|scene| scene.remove("include")) {
+            Some(includes) => Vec::<PathBuf>::deserialize(includes)?,
+            None => vec![],
+        };
+        let mut included = vec![];
+        let mut inherited = MaterialTable::default();
+        for include in includes {
+            if depth >= MAX_INCLUDE_DEPTH {
+                return Err(ParseError::Parse(format!("Scene files are included more than {MAX_INCLUDE_DEPTH} levels deep")));
+            }
+            let world = Self::load_included(&directory.join(include), depth + 1)?;
+            for (name, material) in world.materials.clone() {
+                inherited.insert(name, material);
+            }
+            included.push(world);
+        }
+
+        let mut world = Self::from_scene(scene, inherited)?;
+        for scene in included {
+            world.merge(scene);
+        }
+        world.load_meshes(directory)?;
+        Ok(world)
+    }
+
+    /// Reads a scene, the materials of the scene come first, followed by the `inherited` materials with other names
+    /// Scene files of an older version are migrated, a list inside a scene has the version of the scene
+    fn from_scene(mut scene: serde_json::Value, inherited: MaterialTable) -> Result<Self, serde_json::Error> {
+        if !shared::is_active() {
+            scene_file::migrate(&mut scene).map_err(de::Error::custom)?;
+        }
+        let mut materials = match scene.as_object_mut().and_then(|scene| scene.remove("materials")) {
+            Some(materials) => MaterialTable::deserialize(materials)?,
+            None => MaterialTable::default(),
+        };
+        for (name, material) in inherited {
+            materials.insert(name, material);
+        }
+        let (scene, materials) = shared::with_table(materials, || SceneFile::deserialize(scene));
+        let scene = scene?;
+        Ok(Self {
+            objects: scene.objects,
+            camera: scene.camera,
+            lights: scene.lights,
+            materials,
+            meshes: scene.meshes,
+            include: scene.include,
+            ..Default::default()
+        })
+    }
+
+    /// Adds an included scene, the camera of this scene takes precedence
+    fn merge(&mut self, included: HittableList) {
+        self.objects.extend(included.objects);
+        self.lights.extend(included.lights);
+        self.camera = self.camera.take().or(included.camera);
+    }
+
+    /// Adds the triangles, lights and camera of the mesh files to the scene, paths are relative to `directory`
+    pub fn load_meshes(&mut self, directory: &Path) -> Result<(), ParseError> {
+        for mesh in &self.meshes {
+            let path = directory.join(&mesh.path);
+            let format = mesh.format.or(FileFormat::from_path(&path))
+                .ok_or(ParseError::Parse(format!("Unknown format of mesh {path:?}")))?;
+            let material = mesh.material.as_ref()
+                .map(|material| self.materials.resolve(material))
+                .transpose()
+                .map_err(ParseError::Parse)?;
+            let import = ImportOptions { transform: mesh.transform.clone(), ..mesh.import.clone() };
+            let loaded = load_mesh(&path, format, material, &import)?;
+            self.objects.extend(loaded.objects);
+            self.lights.extend(loaded.lights);
+            self.camera = self.camera.take().or(loaded.camera);
+        }
+        Ok(())
     }
 
     pub fn init(&mut self) {
+        let (bounded, unbounded) = self.objects.iter().cloned()
+            .partition::<Vec<_>, _>(|object| object.is_bounded());
+        self.unbounded = unbounded;
+
         match self.algorithm {
             IntersectionAlgorithm::BVH => {
                 let t = Instant::now();
-                self.bvh = Some(Bvh::new(self.objects.clone(), &self.options));
-                eprintln!("BVH constructed in {:3.2?}", t.elapsed())
+                let bvh = Bvh::new(bounded, &self.options);
+                eprintln!("BVH constructed in {:3.2?}", t.elapsed());
+                if let Some(cost) = bvh.unoptimized_sah_cost() {
+                    eprintln!("BVH optimized from SAH cost {cost:.2} to {:.2}", bvh.sah_cost());
+                }
+                self.bvh = Some(bvh);
+            }
+            IntersectionAlgorithm::BVH4 => {
+                let t = Instant::now();
+                self.bvh4 = Some(WideBvh::new(bounded, &self.options));
+                eprintln!("BVH4 constructed in {:3.2?}", t.elapsed())
+            }
+            IntersectionAlgorithm::BVH8 => {
+                let t = Instant::now();
+                self.bvh8 = Some(WideBvh::new(bounded, &self.options));
+                eprintln!("BVH8 constructed in {:3.2?}", t.elapsed())
             }
             IntersectionAlgorithm::Grid => {
                 let t = Instant::now();
                 let size = self.options.grid_size;
-                self.grid = Some(Grid::new(self.objects.clone(), Vec3::new(size, size, size), Point3::new(-100.0, -100.0, -100.0), Point3::new(100.0, 100.0, 100.0), Point3::new(200.0, 200.0, 200.0)));
+                self.grid = Some(Grid::new(bounded, Vec3::new(size, size, size), Point3::new(-100.0, -100.0, -100.0), Point3::new(100.0, 100.0, 100.0), Point3::new(200.0, 200.0, 200.0)));
                 if let Some(grid) = &self.grid {
                     for box_ in &grid.boxes {
                         if box_.objects.len() <= 1 { continue }
@@ -48,16 +199,134 @@ impl HittableList {
                 }
                 eprintln!("Grid constructed in {:3.2?}", t.elapsed())
             }
+            IntersectionAlgorithm::KdTree => {
+                let t = Instant::now();
+                let kd_tree = KdTree::new(bounded, &self.options);
+                eprintln!("kd-tree constructed in {:3.2?} ({} nodes, {} references)", t.elapsed(), kd_tree.nodes().len(),
+                          kd_tree.references());
+                self.kd_tree = Some(kd_tree);
+            }
+            IntersectionAlgorithm::Octree => {
+                let t = Instant::now();
+                let octree = Octree::new(bounded, &self.options);
+                let stats = octree.stats();
+                eprintln!("Octree constructed in {:3.2?} ({} cells, {} leaves of which {} empty, depth {}, {} references, at most {} per leaf)",
+                          t.elapsed(), stats.cells, stats.leaves, stats.empty_leaves, stats.depth, stats.references, stats.max_objects);
+                self.octree = Some(octree);
+            }
             _ => {}
         }
     }
 
+    /// Replaces the object at `index`, e.g. with the object in its place in the next frame of an animation
+    /// Call `update()` afterwards to update the acceleration structure
+    pub fn set_object(&mut self, index: usize, object: Rc<dyn Hittable>) {
+        self.objects[index] = object;
+    }
+
+    /// Moves the object at `index` by `transform`, triangles are transformed and other objects put in an `Instance`
+    /// Call `update()` afterwards to update the acceleration structure
+    pub fn transform_object(&mut self, index: usize, transform: &Transform) {
+        let object = &self.objects[index];
+        self.objects[index] = match object.as_triangle() {
+            Some(triangle) => Rc::new(triangle.transformed(transform)),
+            None => Rc::new(Instance::new(object.clone(), transform.clone())),
+        };
+    }
+
+    /// Updates the acceleration structure after objects moved, for the next frame of an animation
+    /// The BVH is refitted instead of rebuilt, optionally with rotations, see `Bvh::refit()`. It is rebuilt when
+    /// objects were added or removed, or became bounded or unbounded, and other structures are always rebuilt.
+    /// The time it takes is added to the frames in `data`.
+    pub fn update(&mut self, rotate: bool, data: &mut Data) {
+        let start = Instant::now();
+        let (bounded, unbounded) = self.objects.iter().cloned()
+            .partition::<Vec<_>, _>(|object| object.is_bounded());
+        match &mut self.bvh {
+            Some(bvh) if bvh.object_count() == bounded.len() => {
+                self.unbounded = unbounded;
+                bvh.refit(&bounded, rotate);
+            }
+            _ => self.init(),
+        }
+        data.add_frame(start.elapsed().as_secs_f64());
+    }
+
+    /// The SAH cost of the BVH before `-o bvh-optimize`, once it is built by `init()`
+    pub fn unoptimized_sah_cost(&self) -> Option<f64> {
+        self.bvh.as_ref().and_then(Bvh::unoptimized_sah_cost)
+    }
+
+    /// The SAH cost of the BVH or kd-tree, once it is built by `init()`
+    pub fn sah_cost(&self) -> Option<f64> {
+        self.bvh.as_ref().map(Bvh::sah_cost)
+            .or(self.bvh4.as_ref().map(WideBvh::sah_cost))
+            .or(self.bvh8.as_ref().map(WideBvh::sah_cost))
+            .or(self.kd_tree.as_ref().map(KdTree::sah_cost))
+    }
+
     #[allow(dead_code)]
     pub fn clear(&mut self) { self.objects.clear(); }
 
     pub fn add(&mut self, object: Rc<dyn Hittable>) {
         self.objects.push(object);
     }
+
+    /// Check the unbounded objects, which are not in the acceleration structure
+    /// Returns the distance to the closest hit, if any
+    fn hit_unbounded(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> Option<f64> {
+        let mut closest_so_far = None;
+        for object in &self.unbounded {
+            data.add_intersection_check();
+            if object.hit(r, Interval::new(ray_t.min, closest_so_far.unwrap_or(ray_t.max)), rec, data) {
+                closest_so_far = Some(rec.t);
+            }
+        }
+        closest_so_far
+    }
+}
+
+impl Serialize for HittableList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // The objects are written first to collect their materials, a list inside a scene uses the table of the scene
+        let nested = shared::is_active();
+        let (objects, materials) = if nested {
+            (serde_json::to_value(&self.objects), None)
+        } else {
+            let (objects, materials) = shared::with_table(self.materials.clone(), || serde_json::to_value(&self.objects));
+            (objects, Some(materials).filter(|materials| !materials.is_empty()))
+        };
+        let objects = objects.map_err(ser::Error::custom)?;
+
+        let mut state = serializer.serialize_struct("HittableList", 7)?;
+        if nested { state.skip_field("version")? } else { state.serialize_field("version", &SCENE_VERSION)? }
+        match &materials {
+            Some(materials) => state.serialize_field("materials", materials)?,
+            None => state.skip_field("materials")?,
+        }
+        state.serialize_field("objects", &objects)?;
+        match &self.camera {
+            Some(camera) => state.serialize_field("camera", camera)?,
+            None => state.skip_field("camera")?,
+        }
+        if self.lights.is_empty() { state.skip_field("lights")? } else { state.serialize_field("lights", &self.lights)? }
+        if self.meshes.is_empty() { state.skip_field("meshes")? } else { state.serialize_field("meshes", &self.meshes)? }
+        if self.include.is_empty() { state.skip_field("include")? } else { state.serialize_field("include", &self.include)? }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for HittableList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // The materials have to be known before the objects are read, but can come after them in the file
+        let scene = serde_json::Value::deserialize(deserializer)?;
+        let mut world = Self::from_scene(scene, shared::current().unwrap_or_default()).map_err(de::Error::custom)?;
+        // A list inside a scene is not initialised, so its objects are checked one by one
+        if shared::is_active() {
+            world.algorithm = IntersectionAlgorithm::Naive;
+        }
+        Ok(world)
+    }
 }
 
 #[typetag::serde]
@@ -80,24 +349,50 @@ impl Hittable for HittableList {
             }
             IntersectionAlgorithm::BVH => {
                 if let Some(bvh) = &self.bvh {
-                    if let Some(root) = bvh.root() {
-                        let root_hit = root.hit_aabb(r, ray_t, rec, data, &self.options).is_some();
-                        if root_hit && root.hit(r, ray_t, bvh, rec, data, &self.options) {
-                            return true;
-                        }
-                    }
-                    false
+                    let unbounded_hit = self.hit_unbounded(r, ray_t, rec, data);
+                    let ray_t = Interval::new(ray_t.min, unbounded_hit.unwrap_or(ray_t.max));
+                    bvh.hit(r, ray_t, rec, data, &self.options) || unbounded_hit.is_some()
                 } else {
                     panic!("Please run HittableList.init() first")
                 }
             }
+            IntersectionAlgorithm::BVH4 | IntersectionAlgorithm::BVH8 => {
+                let unbounded_hit = self.hit_unbounded(r, ray_t, rec, data);
+                let ray_t = Interval::new(ray_t.min, unbounded_hit.unwrap_or(ray_t.max));
+                let hit = match (&self.bvh4, &self.bvh8) {
+                    (Some(bvh), _) => bvh.hit(r, ray_t, rec, data, &self.options),
+                    (_, Some(bvh)) => bvh.hit(r, ray_t, rec, data, &self.options),
+                    _ => panic!("Please run HittableList.init() first"),
+                };
+                hit || unbounded_hit.is_some()
+            }
             IntersectionAlgorithm::Grid => {
                 if let Some(grid) = &self.grid {
-                    grid.hit(r, ray_t, rec, data, &self.options)
+                    let unbounded_hit = self.hit_unbounded(r, ray_t, rec, data);
+                    let ray_t = Interval::new(ray_t.min, unbounded_hit.unwrap_or(ray_t.max));
+                    grid.hit(r, ray_t, rec, data, &self.options) || unbounded_hit.is_some()
                 } else {
                     panic!("Please run Grid::new() first")
                 }
             }
+            IntersectionAlgorithm::KdTree => {
+                if let Some(kd_tree) = &self.kd_tree {
+                    let unbounded_hit = self.hit_unbounded(r, ray_t, rec, data);
+                    let ray_t = Interval::new(ray_t.min, unbounded_hit.unwrap_or(ray_t.max));
+                    kd_tree.hit(r, ray_t, rec, data) || unbounded_hit.is_some()
+                } else {
+                    panic!("Please run HittableList.init() first")
+                }
+            }
+            IntersectionAlgorithm::Octree => {
+                if let Some(octree) = &self.octree {
+                    let unbounded_hit = self.hit_unbounded(r, ray_t, rec, data);
+                    let ray_t = Interval::new(ray_t.min, unbounded_hit.unwrap_or(ray_t.max));
+                    octree.hit(r, ray_t, rec, data) || unbounded_hit.is_some()
+                } else {
+                    panic!("Please run HittableList.init() first")
+                }
+            }
         }
     }
 
@@ -118,6 +413,25 @@ impl Hittable for HittableList {
     fn surface_area(&self) -> f64 {
         objects_surface_area(&self.objects)
     }
+
+    /// Treats the list as a closed mesh, so all crossings of the objects are combined
+    fn hit_spans(&self, r: &Ray, data: &mut Data) -> Option<Vec<HitSpan>> {
+        let mut crossings = Vec::new();
+        for object in &self.objects {
+            data.add_intersection_check();
+            if let Some(spans) = object.hit_spans(r, data) {
+                crossings.extend(spans.into_iter()
+                    .flat_map(|span| [span.enter, span.exit])
+                    .filter(|rec| rec.t.is_finite()));
+            } else {
+                let mut rec = HitRecord::default();
+                if object.hit(r, Interval::universe(), &mut rec, data) && rec.t.is_finite() {
+                    crossings.push(rec);
+                }
+            }
+        }
+        Some(spans_from_crossings(crossings))
+    }
 }
 
 pub fn objects_to_aabb(objects: &[Rc<dyn Hittable>]) -> AABB {
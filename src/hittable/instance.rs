@@ -0,0 +1,84 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, HitSpan, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::MaterialType;
+use crate::value::ray::Ray;
+use crate::value::transform::Transform;
+use crate::value::vec3::Point3;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// Places an object that is defined in local space into the world using a transform
+/// The same object can be shared between multiple instances
+#[derive(Serialize, Deserialize)]
+pub struct Instance {
+    object: Rc<dyn Hittable>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Rc<dyn Hittable>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    /// Move a hit of the local ray back to world space
+    fn to_world(&self, r: &Ray, rec: &mut HitRecord) {
+        rec.p = self.transform.point(rec.p);
+        let outward_normal = self.transform.normal(rec.outward_normal()).unit();
+        rec.set_face_normal(r, outward_normal);
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        // Move the ray to local space, the direction is not normalised so `t` stays the same
+        let local_ray = self.transform.inverse().ray(r);
+        if !self.object.hit(&local_ray, ray_t, rec, data) {
+            return false;
+        }
+
+        self.to_world(r, rec);
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        let aabb = self.object.to_aabb();
+        let corners = (0..8).map(|i| {
+            self.transform.point(Point3::new(
+                if i & 1 == 0 { aabb.min.x() } else { aabb.max.x() },
+                if i & 2 == 0 { aabb.min.y() } else { aabb.max.y() },
+                if i & 4 == 0 { aabb.min.z() } else { aabb.max.z() },
+            ))
+        }).collect::<Vec<_>>();
+        AABB::from_points(&corners)
+    }
+
+    fn centroid(&self) -> Point3 {
+        self.transform.point(self.object.centroid())
+    }
+
+    fn surface_area(&self) -> f64 {
+        // Exact for uniform scaling, an approximation otherwise
+        self.object.surface_area() * self.transform.determinant().abs().powf(2.0 / 3.0)
+    }
+
+    fn hit_spans(&self, r: &Ray, data: &mut Data) -> Option<Vec<HitSpan>> {
+        let local_ray = self.transform.inverse().ray(r);
+        let mut spans = self.object.hit_spans(&local_ray, data)?;
+        for span in &mut spans {
+            self.to_world(r, &mut span.enter);
+            self.to_world(r, &mut span.exit);
+        }
+        Some(spans)
+    }
+
+    fn is_bounded(&self) -> bool {
+        self.object.is_bounded()
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        self.object.material_type()
+    }
+}
@@ -0,0 +1,87 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{spans_from_crossings, HitRecord, HitSpan, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// An infinite plane through `point`, facing `normal`
+/// Since it is unbounded it is not put in the acceleration structures
+/// For constructive solid geometry, the half-space behind the plane is considered to be inside
+#[derive(Serialize, Deserialize)]
+pub struct Plane {
+    point: Point3,
+    normal: Vec3,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, mat: Rc<dyn Material>) -> Self {
+        Self { point, normal: normal.unit(), mat }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        // Check if the ray and plane are parallel
+        let denom = self.normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = self.normal.dot(&(self.point - *r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+
+        // The texture coordinates repeat every unit along the plane
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = rec.p - self.point;
+        rec.u = offset.dot(&tangent).rem_euclid(1.0);
+        rec.v = offset.dot(&bitangent).rem_euclid(1.0);
+        rec.set_face_normal(r, self.normal);
+        rec.color = None;
+        rec.mat = Some(Rc::clone(&self.mat));
+
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        AABB::universe()
+    }
+
+    fn centroid(&self) -> Point3 {
+        self.point
+    }
+
+    fn surface_area(&self) -> f64 {
+        f64::INFINITY
+    }
+
+    fn hit_spans(&self, r: &Ray, data: &mut Data) -> Option<Vec<HitSpan>> {
+        let mut rec = HitRecord::default();
+        if self.hit(r, Interval::universe(), &mut rec, data) {
+            return Some(spans_from_crossings(vec![rec]));
+        }
+        // The ray is parallel to the plane, so it is either completely inside or outside
+        let inside = self.normal.dot(&(*r.origin() - self.point)) < 0.0;
+        let infinity = |t: f64| HitRecord { t, ..Default::default() };
+        Some(if inside { vec![HitSpan { enter: infinity(f64::NEG_INFINITY), exit: infinity(f64::INFINITY) }] } else { vec![] })
+    }
+
+    fn is_bounded(&self) -> bool {
+        false
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{HitRecord, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A parallelogram with corner `q` and the edges `u` and `v`
+/// Based on https://raytracing.github.io/books/RayTracingTheNextWeek.html#quadrilaterals
+#[derive(Serialize, Deserialize)]
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, mat: Rc<dyn Material>) -> Self {
+        Self { q, u, v, mat }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        let n = self.u.cross(&self.v);
+        let normal = n.unit();
+
+        // Check if the ray and plane are parallel
+        let denom = normal.dot(r.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        // Get the distance along the ray
+        let d = normal.dot(&self.q);
+        let t = (d - normal.dot(r.origin())) / denom;
+        if !ray_t.contains(t) {
+            return false;
+        }
+
+        // Express the intersection point in the (u, v) coordinates of the plane
+        let p = r.at(t);
+        let planar = p - self.q;
+        let w = n / n.dot(&n);
+        let alpha = w.dot(&planar.cross(&self.v));
+        let beta = w.dot(&self.u.cross(&planar));
+        let unit = Interval::new(0.0, 1.0);
+        if !unit.contains(alpha) || !unit.contains(beta) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = p;
+        rec.u = alpha;
+        rec.v = beta;
+        rec.set_face_normal(r, normal);
+        rec.color = None;
+        rec.mat = Some(Rc::clone(&self.mat));
+
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        let corners = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        AABB::from_points(&corners).padded(1e-4)
+    }
+
+    fn centroid(&self) -> Point3 {
+        self.q + 0.5 * (self.u + self.v)
+    }
+
+    fn surface_area(&self) -> f64 {
+        self.u.cross(&self.v).length()
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
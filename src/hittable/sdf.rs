@@ -0,0 +1,261 @@
+use crate::acceleration::aabb::AABB;
+use crate::hittable::sphere::sphere_uv;
+use crate::hittable::{spans_from_crossings, HitRecord, HitSpan, Hittable};
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// Distance to the surface at which the sphere tracing counts a hit
+const EPSILON: f64 = 1e-4;
+/// Step size for the central differences of the normal
+const NORMAL_DELTA: f64 = 1e-5;
+const MAX_STEPS: usize = 512;
+
+/// A signed distance function, built as a tree of shapes and operations
+/// All shapes are centered at the origin, use `Translate` or an `Instance` to move them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sdf {
+    Sphere { radius: f64 },
+    Box { half_extents: Vec3 },
+    /// A box with its edges rounded off by `radius`, within the same `half_extents`
+    RoundBox { half_extents: Vec3, radius: f64 },
+    /// A torus around the y axis
+    Torus { major_radius: f64, minor_radius: f64 },
+    Union { a: Box<Sdf>, b: Box<Sdf> },
+    /// Union that blends both shapes together over a distance of about `k`
+    SmoothUnion { a: Box<Sdf>, b: Box<Sdf>, k: f64 },
+    /// `a` minus `b`
+    Subtract { a: Box<Sdf>, b: Box<Sdf> },
+    Translate { offset: Vec3, shape: Box<Sdf> },
+    /// Repeats the shape every `spacing`, `limit` times in both directions of every axis
+    /// The shape should fit inside a single cell for the distance to stay correct
+    Repeat { spacing: Vec3, limit: [u32; 3], shape: Box<Sdf> },
+    /// Rotates the shape around the y axis by `amount` radians per unit of height
+    Twist { amount: f64, shape: Box<Sdf> },
+}
+
+impl Sdf {
+    /// The signed distance from `p` to the surface, negative inside
+    /// It can be an underestimate, but should never overestimate more than `lipschitz()` allows
+    pub fn distance(&self, p: Point3) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half_extents } => {
+                let q = abs(p) - *half_extents;
+                max(q, 0.0).length() + q.x().max(q.y()).max(q.z()).min(0.0)
+            }
+            Sdf::RoundBox { half_extents, radius } => {
+                let q = abs(p) - *half_extents + Vec3::new(*radius, *radius, *radius);
+                max(q, 0.0).length() + q.x().max(q.y()).max(q.z()).min(0.0) - radius
+            }
+            Sdf::Torus { major_radius, minor_radius } => {
+                let radial = (p.x() * p.x() + p.z() * p.z()).sqrt() - major_radius;
+                (radial * radial + p.y() * p.y()).sqrt() - minor_radius
+            }
+            Sdf::Union { a, b } => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion { a, b, k } => {
+                // Polynomial smooth minimum by Inigo Quilez
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0.0, 1.0);
+                d2 + (d1 - d2) * h - k * h * (1.0 - h)
+            }
+            Sdf::Subtract { a, b } => a.distance(p).max(-b.distance(p)),
+            Sdf::Translate { offset, shape } => shape.distance(p - *offset),
+            Sdf::Repeat { spacing, limit, shape } => {
+                let mut q = p;
+                for axis in 0..3 {
+                    if spacing[axis] > 0.0 {
+                        let limit = limit[axis as usize] as f64;
+                        let cell = (p[axis] / spacing[axis]).round().clamp(-limit, limit);
+                        q[axis] = p[axis] - spacing[axis] * cell;
+                    }
+                }
+                shape.distance(q)
+            }
+            Sdf::Twist { amount, shape } => {
+                // Rotate the point back, so the shape itself looks twisted
+                let (sin, cos) = (-amount * p.y()).sin_cos();
+                shape.distance(Point3::new(cos * p.x() - sin * p.z(), p.y(), sin * p.x() + cos * p.z()))
+            }
+        }
+    }
+
+    /// Conservative bounds of the surface
+    pub fn bounds(&self) -> AABB {
+        match self {
+            Sdf::Sphere { radius } => AABB::new(Point3::new(-radius, -radius, -radius), Point3::new(*radius, *radius, *radius)),
+            Sdf::Box { half_extents } | Sdf::RoundBox { half_extents, .. } => AABB::new(-*half_extents, *half_extents),
+            Sdf::Torus { major_radius, minor_radius } => {
+                let outer = major_radius + minor_radius;
+                AABB::new(Point3::new(-outer, -minor_radius, -outer), Point3::new(outer, *minor_radius, outer))
+            }
+            Sdf::Union { a, b } => a.bounds() + b.bounds(),
+            Sdf::SmoothUnion { a, b, k } => {
+                // The smooth minimum is at most k/4 below the minimum, so the blend can bulge out that far
+                let bounds = a.bounds() + b.bounds();
+                let bulge = k.abs() / 4.0;
+                AABB::new(bounds.min - Vec3::new(bulge, bulge, bulge), bounds.max + Vec3::new(bulge, bulge, bulge))
+            }
+            Sdf::Subtract { a, .. } => a.bounds(),
+            Sdf::Translate { offset, shape } => {
+                let bounds = shape.bounds();
+                AABB::new(bounds.min + *offset, bounds.max + *offset)
+            }
+            Sdf::Repeat { spacing, limit, shape } => {
+                let bounds = shape.bounds();
+                let extent = Vec3::new(
+                    spacing.x().max(0.0) * limit[0] as f64,
+                    spacing.y().max(0.0) * limit[1] as f64,
+                    spacing.z().max(0.0) * limit[2] as f64,
+                );
+                AABB::new(bounds.min - extent, bounds.max + extent)
+            }
+            Sdf::Twist { shape, .. } => {
+                let bounds = shape.bounds();
+                let radius = twist_radius(&bounds);
+                AABB::new(Point3::new(-radius, bounds.min.y(), -radius), Point3::new(radius, bounds.max.y(), radius))
+            }
+        }
+    }
+
+    /// How much faster than the real distance `distance()` can change
+    /// Sphere tracing divides its steps by this, so it does not step through the surface
+    pub fn lipschitz(&self) -> f64 {
+        match self {
+            Sdf::Sphere { .. } | Sdf::Box { .. } | Sdf::RoundBox { .. } | Sdf::Torus { .. } => 1.0,
+            Sdf::Union { a, b } | Sdf::SmoothUnion { a, b, .. } | Sdf::Subtract { a, b } => a.lipschitz().max(b.lipschitz()),
+            Sdf::Translate { shape, .. } | Sdf::Repeat { shape, .. } => shape.lipschitz(),
+            Sdf::Twist { amount, shape } => {
+                let stretch = amount * twist_radius(&shape.bounds());
+                shape.lipschitz() * (1.0 + stretch * stretch).sqrt()
+            }
+        }
+    }
+
+    /// The gradient of the distance using central differences, which is the outward normal at the surface
+    pub fn normal(&self, p: Point3) -> Vec3 {
+        let gradient = |axis: u32| {
+            let mut offset = Vec3::default();
+            offset[axis] = NORMAL_DELTA;
+            self.distance(p + offset) - self.distance(p - offset)
+        };
+        Vec3::new(gradient(0), gradient(1), gradient(2)).unit()
+    }
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
+
+fn max(v: Vec3, value: f64) -> Vec3 {
+    Vec3::new(v.x().max(value), v.y().max(value), v.z().max(value))
+}
+
+/// The largest distance from the y axis within `bounds`
+fn twist_radius(bounds: &AABB) -> f64 {
+    let x = bounds.min.x().abs().max(bounds.max.x().abs());
+    let z = bounds.min.z().abs().max(bounds.max.z().abs());
+    (x * x + z * z).sqrt()
+}
+
+/// A shape defined by a signed distance function, rendered using sphere tracing
+#[derive(Serialize, Deserialize)]
+pub struct SdfShape {
+    sdf: Sdf,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, mat: Rc<dyn Material>) -> Self {
+        Self { sdf, mat }
+    }
+
+    /// Finds the first point in `ray_t` where the distance changes sign, using sphere tracing
+    /// Returns the distance along the ray and whether the ray started inside the shape
+    fn march(&self, r: &Ray, ray_t: Interval, bounds: &AABB, data: &mut Data) -> Option<(f64, bool)> {
+        let (t_min, t_max) = bounds.enter_and_exit(r, ray_t)?;
+        let step_scale = 1.0 / (r.direction().length() * self.sdf.lipschitz());
+
+        let mut t = t_min;
+        // Entering the bounding box always starts outside the shape, otherwise the ray may start on the surface
+        let mut inside = (t_min > ray_t.min).then_some(false);
+        for _ in 0..MAX_STEPS {
+            data.add_traversal_step();
+            let distance = self.sdf.distance(r.at(t));
+            // The side of the surface is decided by the first point that is clearly on one side
+            let Some(start_inside) = inside else {
+                if distance.abs() >= EPSILON {
+                    inside = Some(distance < 0.0);
+                }
+                t += distance.abs().max(2.0 * EPSILON) * step_scale;
+                if t > t_max {
+                    return None;
+                }
+                continue;
+            };
+
+            let distance = if start_inside { -distance } else { distance };
+            if distance < EPSILON {
+                return Some((t, start_inside));
+            }
+            t += distance * step_scale;
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn record(&self, r: &Ray, t: f64) -> HitRecord {
+        let outward_normal = self.sdf.normal(r.at(t));
+        HitRecord::at(r, t, outward_normal, sphere_uv(&outward_normal), &self.mat)
+    }
+}
+
+#[typetag::serde]
+impl Hittable for SdfShape {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        let Some((t, _)) = self.march(r, ray_t, &self.to_aabb(), data) else {
+            return false;
+        };
+        rec.update(self.record(r, t));
+        true
+    }
+
+    fn to_aabb(&self) -> AABB {
+        // Pad for the distance at which a hit is counted
+        self.sdf.bounds().padded(2.0 * EPSILON)
+    }
+
+    fn centroid(&self) -> Point3 {
+        let aabb = self.to_aabb();
+        0.5 * (aabb.min + aabb.max)
+    }
+
+    fn surface_area(&self) -> f64 {
+        // Only the bounding box is known
+        self.to_aabb().surface_area()
+    }
+
+    /// Keeps marching after every crossing, the ray always starts outside at the bounding box
+    fn hit_spans(&self, r: &Ray, data: &mut Data) -> Option<Vec<HitSpan>> {
+        let bounds = self.to_aabb();
+        let mut crossings = Vec::new();
+        let mut ray_t = Interval::universe();
+        while let Some((t, _)) = self.march(r, ray_t, &bounds, data) {
+            crossings.push(self.record(r, t));
+            ray_t = Interval::new(t, f64::INFINITY);
+        }
+        Some(spans_from_crossings(crossings))
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
@@ -1,10 +1,10 @@
 use std::f64::consts::PI;
 use crate::acceleration::aabb::AABB;
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, HitSpan, Hittable};
 use crate::value::interval::Interval;
-use crate::value::material::{Material, MaterialType};
+use crate::value::material::{Material, MaterialType, shared};
 use crate::value::ray::Ray;
-use crate::value::vec3::Point3;
+use crate::value::vec3::{Point3, Vec3};
 use serde::{Deserialize, Serialize};
 use std::rc::Rc;
 use crate::value::data::Data;
@@ -13,6 +13,7 @@ use crate::value::data::Data;
 pub struct Sphere {
     center: Point3,
     radius: f64,
+    #[serde(with = "shared")]
     mat: Rc<dyn Material>,
 }
 
@@ -50,6 +51,8 @@ impl Hittable for Sphere {
         rec.p = r.at(rec.t);
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, outward_normal);
+        (rec.u, rec.v) = sphere_uv(&outward_normal);
+        rec.color = None;
         rec.mat = Some(Rc::clone(&self.mat));
 
         true
@@ -74,7 +77,38 @@ impl Hittable for Sphere {
         4.0 * PI * self.radius
     }
 
+    fn hit_spans(&self, r: &Ray, _data: &mut Data) -> Option<Vec<HitSpan>> {
+        let oc = self.center - *r.origin();
+        let a = r.direction().length_squared();
+        let h = r.direction().dot(&oc);
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            return Some(vec![]);
+        }
+
+        let sqrt_discriminant = discriminant.sqrt();
+        let record = |t: f64| {
+            let outward_normal = (r.at(t) - self.center) / self.radius;
+            HitRecord::at(r, t, outward_normal, sphere_uv(&outward_normal), &self.mat)
+        };
+        Some(vec![HitSpan {
+            enter: record((h - sqrt_discriminant) / a),
+            exit: record((h + sqrt_discriminant) / a),
+        }])
+    }
+
     fn material_type(&self) -> Option<MaterialType> {
         Some(self.mat.get_type())
     }
 }
+
+/// Returns the (u, v) coordinates of a point on the unit sphere
+/// u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1, both scaled to [0, 1]
+/// From https://raytracing.github.io/books/RayTracingTheNextWeek.html#texturemapping
+pub fn sphere_uv(p: &Vec3) -> (f64, f64) {
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
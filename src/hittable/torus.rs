@@ -0,0 +1,113 @@
+use std::f64::consts::PI;
+use crate::acceleration::aabb::AABB;
+use crate::hittable::{spans_from_crossings, HitRecord, HitSpan, Hittable};
+use crate::utils::polynomial::solve_quartic;
+use crate::value::data::Data;
+use crate::value::interval::Interval;
+use crate::value::material::{Material, MaterialType, shared};
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// A torus around the y axis, centered at the origin
+/// `major_radius` is the distance from the center to the middle of the tube, `minor_radius` the radius of the tube
+#[derive(Serialize, Deserialize)]
+pub struct Torus {
+    major_radius: f64,
+    minor_radius: f64,
+    #[serde(with = "shared")]
+    mat: Rc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(major_radius: f64, minor_radius: f64, mat: Rc<dyn Material>) -> Self {
+        Self { major_radius: f64::max(0.0, major_radius), minor_radius: f64::max(0.0, minor_radius), mat }
+    }
+
+    /// Calls `visit` with the distance, outward normal and (u, v) coordinates
+    /// of every point where the (infinite) ray crosses the surface
+    fn surface_hits(&self, r: &Ray, mut visit: impl FnMut(f64, Vec3, (f64, f64))) {
+        // The quartic is badly conditioned for far away origins,
+        // so start at the bounding box and use a unit direction
+        let Some((t_enter, _)) = self.to_aabb().enter_and_exit(r, Interval::universe()) else {
+            return;
+        };
+        let length = r.direction().length();
+        let d = *r.direction() / length;
+        let o = r.at(t_enter);
+
+        // From Ray Tracing from the Ground Up, chapter 19
+        let major_squared = self.major_radius * self.major_radius;
+        let minor_squared = self.minor_radius * self.minor_radius;
+        let e = o.length_squared() - major_squared - minor_squared;
+        let f = o.dot(&d);
+        let roots = solve_quartic(
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * major_squared * d.y() * d.y(),
+            4.0 * f * e + 8.0 * major_squared * o.y() * d.y(),
+            e * e - 4.0 * major_squared * (minor_squared - o.y() * o.y()),
+        );
+
+        for distance in roots.as_slice() {
+            // Convert the distance from the entry point back to the original ray
+            let t = t_enter + distance / length;
+            let p = r.at(t);
+
+            // The normal points away from the closest point on the center circle of the tube
+            let radial = Vec3::new(p.x(), 0.0, p.z());
+            let radial_length = radial.length();
+            let center = if radial_length > 0.0 { radial * (self.major_radius / radial_length) } else { radial };
+            let outward_normal = (p - center).unit();
+
+            let phi = (-p.z()).atan2(p.x()) + PI;
+            let theta = p.y().atan2(radial_length - self.major_radius) + PI;
+            visit(t, outward_normal, (phi / (2.0 * PI), theta / (2.0 * PI)));
+        }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
+        let mut closest: Option<(f64, Vec3, (f64, f64))> = None;
+        self.surface_hits(r, |t, outward_normal, uv| {
+            if ray_t.surrounds(t) && closest.is_none_or(|(closest_t, ..)| t < closest_t) {
+                closest = Some((t, outward_normal, uv));
+            }
+        });
+
+        if let Some((t, outward_normal, uv)) = closest {
+            rec.update(HitRecord::at(r, t, outward_normal, uv, &self.mat));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn to_aabb(&self) -> AABB {
+        let outer = self.major_radius + self.minor_radius;
+        AABB::new(Point3::new(-outer, -self.minor_radius, -outer), Point3::new(outer, self.minor_radius, outer))
+    }
+
+    fn centroid(&self) -> Point3 {
+        Point3::default()
+    }
+
+    fn surface_area(&self) -> f64 {
+        4.0 * PI * PI * self.major_radius * self.minor_radius
+    }
+
+    fn hit_spans(&self, r: &Ray, _data: &mut Data) -> Option<Vec<HitSpan>> {
+        let mut crossings = Vec::new();
+        self.surface_hits(r, |t, outward_normal, uv| {
+            crossings.push(HitRecord::at(r, t, outward_normal, uv, &self.mat));
+        });
+        Some(spans_from_crossings(crossings))
+    }
+
+    fn material_type(&self) -> Option<MaterialType> {
+        Some(self.mat.get_type())
+    }
+}
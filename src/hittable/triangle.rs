@@ -1,80 +1,176 @@
 use crate::hittable::{HitRecord, Hittable};
+use crate::value::color::Color;
 use crate::value::interval::Interval;
-use crate::value::material::{Material, MaterialType};
+use crate::value::material::{Material, MaterialType, shared};
 use crate::value::ray::Ray;
-use crate::value::vec3::Point3;
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
 use serde::{Deserialize, Serialize};
 use std::rc::Rc;
 use crate::acceleration::aabb::AABB;
 use crate::value::data::Data;
+use crate::utils::rtweekend::TriangleAlgorithm;
 
 #[derive(Serialize, Deserialize)]
 pub struct Triangle {
     v0: Point3,
     v1: Point3,
     v2: Point3,
+    #[serde(with = "shared")]
     mat: Rc<dyn Material>,
-    centroid: Point3,
+    /// Vertex normals, used to interpolate a smooth shading normal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normals: Option<[Vec3; 3]>,
+    /// Vertex texture coordinates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uvs: Option<[(f64, f64); 3]>,
+    /// Vertex colors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    colors: Option<[Color; 3]>,
 }
 
 impl Triangle {
     pub fn new(v0: Point3, v1: Point3, v2: Point3, mat: Rc<dyn Material>) -> Self {
-        let centroid = Point3::new(
-            (v0.x() + v1.x() + v2.x()) / 3.0,
-            (v0.y() + v1.y() + v2.y()) / 3.0,
-            (v0.z() + v1.z() + v2.z()) / 3.0,
-        );
-        Self { v0, v1, v2, mat, centroid }
+        Self { v0, v1, v2, mat, normals: None, uvs: None, colors: None }
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Self {
+        self.normals = Some(normals.map(|n| n.unit()));
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn with_colors(mut self, colors: [Color; 3]) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// The triangle moved by `transform`, a mirroring transform swaps the winding so the front stays in front
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let mut triangle = Self::new(transform.point(self.v0), transform.point(self.v1), transform.point(self.v2), Rc::clone(&self.mat));
+        triangle.normals = self.normals.map(|normals| normals.map(|n| transform.normal(n).unit()));
+        triangle.uvs = self.uvs;
+        triangle.colors = self.colors;
+        if transform.determinant() < 0.0 {
+            std::mem::swap(&mut triangle.v1, &mut triangle.v2);
+            triangle.normals.iter_mut().for_each(|normals| normals.swap(1, 2));
+            triangle.uvs.iter_mut().for_each(|uvs| uvs.swap(1, 2));
+            triangle.colors.iter_mut().for_each(|colors| colors.swap(1, 2));
+        }
+        triangle
+    }
+
+    /// Möller-Trumbore intersection
+    /// Returns the distance and the barycentric coordinates of `v1` and `v2`
+    fn hit_moller_trumbore(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+        let v0v1 = self.v1 - self.v0;
+        let v0v2 = self.v2 - self.v0;
+        let p_vec = r.direction().cross(&v0v2);
+        let det = v0v1.dot(&p_vec);
+        // The ray and the triangle are parallel
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let t_vec = *r.origin() - self.v0;
+        let u = t_vec.dot(&p_vec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q_vec = t_vec.cross(&v0v1);
+        let v = r.direction().dot(&q_vec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = v0v2.dot(&q_vec) * inv_det;
+        ray_t.surrounds(t).then_some((t, u, v))
+    }
+
+    /// Watertight intersection from "Watertight Ray/Triangle Intersection" (Woop, Benthin and Wald, 2013)
+    /// The vertices are moved into a space where the ray starts at the origin and points along z,
+    /// so the edge tests of adjacent triangles use exactly the same values and cannot disagree
+    /// Returns the distance and the barycentric coordinates of `v1` and `v2`
+    fn hit_watertight(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+        let d = r.direction();
+
+        // The dimension where the direction is largest becomes z, keeping the winding
+        let kz = (0..3).max_by(|&a, &b| d[a].abs().total_cmp(&d[b].abs()))?;
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        // Shear constants
+        let sz = 1.0 / d[kz];
+        let sx = d[kx] * sz;
+        let sy = d[ky] * sz;
+
+        let a = self.v0 - *r.origin();
+        let b = self.v1 - *r.origin();
+        let c = self.v2 - *r.origin();
+        let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+        let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+        let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+        // Scaled barycentric coordinates, all of them have to have the same sign
+        // A value of exactly zero (on an edge) is accepted by both triangles
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t_scaled = u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz];
+        let t = t_scaled / det;
+        ray_t.surrounds(t).then_some((t, v / det, w / det))
     }
 
     pub fn a(&self) -> Point3 { self.v0 }
     pub fn b(&self) -> Point3 { self.v1 }
     pub fn c(&self) -> Point3 { self.v2 }
+    pub fn material(&self) -> &Rc<dyn Material> { &self.mat }
+    pub fn normals(&self) -> Option<[Vec3; 3]> { self.normals }
+    pub fn uvs(&self) -> Option<[(f64, f64); 3]> { self.uvs }
+    pub fn colors(&self) -> Option<[Color; 3]> { self.colors }
 }
 
 #[typetag::serde]
 impl Hittable for Triangle {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, _data: &mut Data) -> bool {
-        // Calculate the normal by the cross product of AB and AC
-        let v0v1 = self.v1 - self.v0; // AB
-        let v0v2 = self.v2 - self.v0; // AC
-        let n = v0v1.cross(&v0v2);
-
-        // Check if the ray and plane are parallel
-        let n_dot_dir = n.dot(r.direction());
-        if !ray_t.surrounds(n_dot_dir) {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord, data: &mut Data) -> bool {
+        let hit = match data.options().triangle {
+            TriangleAlgorithm::MollerTrumbore => self.hit_moller_trumbore(r, ray_t),
+            TriangleAlgorithm::Watertight => self.hit_watertight(r, ray_t),
+        };
+        let Some((t, u, v)) = hit else {
             return false;
-        }
+        };
 
-        // Get the distance from the origin to the plane
-        let d = -n.dot(&self.v0);
-        // Get the distance along the ray
-        rec.t = -(n.dot(r.origin()) + d) / n_dot_dir;
-
-        // The triangle is not visible if it is behind the camera
-        if rec.t < 0.0 {
-            return false;
+        rec.t = t;
+        rec.p = r.at(t);
+        let w = 1.0 - u - v;
+        (rec.u, rec.v) = match self.uvs {
+            Some([uv0, uv1, uv2]) => (w * uv0.0 + u * uv1.0 + v * uv2.0, w * uv0.1 + u * uv1.1 + v * uv2.1),
+            None => (u, v),
+        };
+        // The side is decided by the geometric normal, the vertex normals only change the shading
+        let n = (self.v1 - self.v0).cross(&(self.v2 - self.v0));
+        rec.set_face_normal(r, n.unit());
+        if let Some([n0, n1, n2]) = self.normals {
+            let shading_normal = (w * n0 + u * n1 + v * n2).unit();
+            rec.normal = if rec.front_face { shading_normal } else { -shading_normal };
         }
-        // Get the intersection point
-        rec.p = r.at(rec.t);
-        // Check if the plane intersection is inside the triangle
-        // (inside-outside test)
-        let v0p = rec.p - self.v0;
-        if n.dot(&v0v1.cross(&v0p)) <= 0.0 {
-            return false;
-        }
-        let v1v2 = self.v2 - self.v1;
-        let v1p = rec.p - self.v1;
-        if n.dot(&v1v2.cross(&v1p)) <= 0.0 {
-            return false;
-        }
-        let v2v0 = self.v0 - self.v2;
-        let v2p = rec.p - self.v2;
-        if n.dot(&v2v0.cross(&v2p)) <= 0.0 {
-            return false;
-        }
-
-        rec.set_face_normal(r, n);
+        rec.color = self.colors.map(|[c0, c1, c2]| w * c0 + u * c1 + v * c2);
         rec.mat = Some(Rc::clone(&self.mat));
 
         true
@@ -92,7 +188,7 @@ impl Hittable for Triangle {
     }
 
     fn centroid(&self) -> Point3 {
-        self.centroid
+        (self.v0 + self.v1 + self.v2) / 3.0
     }
 
     fn surface_area(&self) -> f64 {
@@ -106,4 +202,8 @@ impl Hittable for Triangle {
     fn material_type(&self) -> Option<MaterialType> {
         Some(self.mat.get_type())
     }
+
+    fn as_triangle(&self) -> Option<&Triangle> {
+        Some(self)
+    }
 }
\ No newline at end of file
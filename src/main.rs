@@ -1,14 +1,17 @@
 use crate::camera::Camera;
-use value::color::Color;
 use value::data::Data;
-use value::material::{Lambertian, MaterialType};
-use utils::parser::parse_ply;
-use utils::rtweekend::{check_valid_options, get_output_filename, Cli, FileFormat, Options};
+use value::material::MaterialType;
+use hittable::hittable_list::HittableList;
+use utils::binary;
+use utils::mesh::{load_mesh, ImportOptions};
+use utils::parser::ParseError;
+use utils::rtweekend::{check_valid_options, get_output_filename, Cli, Command, FileFormat, Options, SceneCommand};
 use value::vec3::{Point3, Vec3};
 use clap::Parser;
 use std::fs::File;
-use std::rc::Rc;
+use std::path::Path;
 use std::time::Instant;
+use utils::scene_file;
 use utils::scenes;
 
 mod hittable;
@@ -22,29 +25,54 @@ mod test;
 fn main() {
     // Parse CLI arguments
     let args = Cli::parse();
+    if let Some(command) = args.command {
+        return run_command(command);
+    }
     if let Some(error) = check_valid_options(&args.options) {
         panic!("{error}")
     }
     run(args)
 }
 
+fn run_command(command: Command) {
+    match command {
+        Command::Scene(SceneCommand::Convert { input, output, format, import }) => {
+            let format = format.or(FileFormat::from_path(&input)).unwrap_or_else(|| panic!("Unknown format of {input:?}, use --format"));
+            let start = Instant::now();
+            let world = load(&input, format, &import).unwrap_or_else(|e| panic!("Failed to open {format:?} scene: {e}"));
+            binary::save_binary(&world, &output).unwrap_or_else(|e| panic!("Failed to write {output:?}: {e}"));
+            eprintln!("Converted {input:?} to {output:?} ({} objects) in {:3.2?}", world.objects.len(), start.elapsed());
+        }
+        Command::Scene(SceneCommand::Upgrade { files }) => {
+            for file in files {
+                match scene_file::upgrade(&file) {
+                    Ok(scene_file::SCENE_VERSION) => eprintln!("{file:?} is up to date"),
+                    Ok(version) => eprintln!("Upgraded {file:?} from version {version} to {}", scene_file::SCENE_VERSION),
+                    Err(e) => panic!("Failed to upgrade {file:?}: {e}"),
+                }
+            }
+        }
+    }
+}
+
+/// Loads a scene in any format
+fn load(path: &Path, format: FileFormat, import: &ImportOptions) -> Result<HittableList, ParseError> {
+    match format {
+        // Deserialize the object, with the scenes and meshes it uses
+        FileFormat::Native => HittableList::load(path),
+        FileFormat::Binary => binary::load_binary(path),
+        format => load_mesh(&path.into(), format, None, import),
+    }
+}
+
 fn run(args: Cli) {
     let options = Options::new(&args);
 
+    let start = Instant::now();
     let (mut world, filename) = if let Some(filename) = args.filename {
-        match args.format {
-            FileFormat::Native => {
-                // Deserialize the object
-                let file = File::open(&filename).expect("Could not open scene file");
-                let world = serde_json::from_reader(&file).expect("Could not read scene file");
-                (world, filename)
-            }
-            FileFormat::PLY => {
-                let material = Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1)));
-                let world = parse_ply(&filename.clone().into(), material).expect("Failed to open PLY scene");
-                (world, filename)
-            }
-        }
+        let world = load(filename.as_ref(), args.format, &args.import)
+            .unwrap_or_else(|e| panic!("Failed to open {:?} scene: {e}", args.format));
+        (world, filename)
     } else {
         // let (world, filename) = scenes::weekend_final();
         // let (world, filename) = scenes::weekend_custom(2, 0.9, 0.05);
@@ -55,16 +83,20 @@ fn run(args: Cli) {
         // let (world, filename) = scenes::simple_fuzzy_metal();
         // let (world, filename) = scenes::simple_triangle();
         // let (world, filename) = scenes::triangle_materials();
-        let (world, filename) = scenes::triangle_test();
+        // let (world, filename) = scenes::triangle_test();
+        // let (world, filename) = scenes::primitives();
+        // let (world, filename) = scenes::quadrics();
+        // let (world, filename) = scenes::csg();
+        let (world, filename) = scenes::sdf();
 
         // Serialize the world
         let filename = format!("scenes/{filename}.json");
-        let file = File::create(&filename).expect("Could not open scene file");
-        serde_json::to_writer(&file, &world).expect("Could not write to scene file");
+        scene_file::save(&world, Path::new(&filename)).expect("Could not write scene file");
         eprintln!("Wrote scene to {filename}");
         // Return world
         (world, filename)
     };
+    let load_time = start.elapsed().as_secs_f64();
 
     world.algorithm = args.algorithm;
     world.options = options.clone();
@@ -77,11 +109,11 @@ fn run(args: Cli) {
     cam.defocus_angle = 0.1;
     cam.focus_dist = 1.0;
 
-    // TODO this is very hacky, encode this in the json files
-    if filename.starts_with("scenes/weekend") {
-        cam.vfov = 20.0;
-        cam.look_from = Point3::new(13.0, 2.0, 3.0);
-        cam.look_at = Point3::new(0.0, 0.0, 0.0);
+    cam.v_up = Vec3::new(0.0, 1.0, 0.0);
+
+    // Use the camera from the scene, PLY files have no camera so the dragon positions are hard-coded
+    if let Some(settings) = &world.camera {
+        cam.apply(settings);
     } else if filename.starts_with("scenes/dragon") {
         cam.vfov = 20.0;
         cam.focus_dist = 50.0;
@@ -101,7 +133,6 @@ fn run(args: Cli) {
         // cam.look_from = Point3::new(1.0, 1.0, 1.0);
         cam.look_at = Point3::new(0.0, 0.0, -1.0);
     }
-    cam.v_up = Vec3::new(0.0, 1.0, 0.0);
 
     // Scene statistics
     if args.stats {
@@ -122,11 +153,18 @@ fn run(args: Cli) {
         .expect("Could not open image file");
 
     let mut data: Data = Data::new(filename.to_string(), world.objects.len(), args.algorithm, options, cam.image_width, cam.image_height(), cam.samples_per_pixel, cam.max_depth);
+    data.set_load_time(load_time);
 
     let start = Instant::now();
     // Initialise structures like BVH
     world.init();
     data.set_init_time(start.elapsed().as_secs_f64());
+    if let Some(cost) = world.sah_cost() {
+        data.set_sah_cost(cost);
+    }
+    if let Some(cost) = world.unoptimized_sah_cost() {
+        data.set_unoptimized_sah_cost(cost);
+    }
 
     // Render pixels
     cam.render(&world, &mut file, &mut data)
@@ -1,4 +1,4 @@
-use crate::utils::rtweekend::{AlgorithmOptions, IntersectionAlgorithm};
+use crate::utils::rtweekend::{AlgorithmOptions, IntersectionAlgorithm, TriangleAlgorithm};
 use crate::{run, Cli};
 
 #[test]
@@ -99,6 +99,14 @@ fn test_triangle_materials_grid_1() {
     settings.grid_size = 1.0;
     run(settings);
 }
+#[test]
+fn test_triangle_materials_watertight() {
+    let mut settings = Cli::new_from_json("scenes/triangle_materials.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    settings.triangle = TriangleAlgorithm::Watertight;
+    run(settings);
+}
 
 
 #[test]
@@ -194,9 +202,178 @@ fn test_weekend_final_grid_1() {
     run(settings);
 }
 
+#[test]
+fn test_primitives_naive() {
+    let mut settings = Cli::new_from_json("scenes/primitives.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_primitives_bvh_sah_full() {
+    let mut settings = Cli::new_from_json("scenes/primitives.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_primitives_grid_5() {
+    let mut settings = Cli::new_from_json("scenes/primitives.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_quadrics_naive() {
+    let mut settings = Cli::new_from_json("scenes/quadrics.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_quadrics_bvh_sah_full() {
+    let mut settings = Cli::new_from_json("scenes/quadrics.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_quadrics_grid_5() {
+    let mut settings = Cli::new_from_json("scenes/quadrics.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_csg_naive() {
+    let mut settings = Cli::new_from_json("scenes/csg.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_csg_bvh_sah_full() {
+    let mut settings = Cli::new_from_json("scenes/csg.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_csg_grid_5() {
+    let mut settings = Cli::new_from_json("scenes/csg.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_sdf_naive() {
+    let mut settings = Cli::new_from_json("scenes/sdf.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_sdf_bvh_sah_full() {
+    let mut settings = Cli::new_from_json("scenes/sdf.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_sdf_grid_5() {
+    let mut settings = Cli::new_from_json("scenes/sdf.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_obj_materials_naive() {
+    let mut settings = Cli::new_from_obj("scenes/obj/materials.obj".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_obj_materials_bvh_sah_full() {
+    let mut settings = Cli::new_from_obj("scenes/obj/materials.obj".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_obj_materials_grid_5() {
+    let mut settings = Cli::new_from_obj("scenes/obj/materials.obj".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_gltf_scene_naive() {
+    let mut settings = Cli::new_from_gltf("scenes/gltf/scene.gltf".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_gltf_scene_bvh_sah_full() {
+    let mut settings = Cli::new_from_gltf("scenes/gltf/scene.gltf".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_gltf_scene_glb_grid_5() {
+    let mut settings = Cli::new_from_gltf("scenes/gltf/scene.glb".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_stl_parts_naive() {
+    let mut settings = Cli::new_from_stl("scenes/stl/parts.stl".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_stl_parts_bvh_sah_full() {
+    let mut settings = Cli::new_from_stl("scenes/stl/parts.stl".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_stl_parts_grid_5() {
+    let mut settings = Cli::new_from_stl("scenes/stl/parts.stl".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
+#[test]
+fn test_pbrt_scene_naive() {
+    let mut settings = Cli::new_from_pbrt("scenes/pbrt/scene.pbrt".to_string());
+    settings.algorithm = IntersectionAlgorithm::Naive;
+    run(settings);
+}
+#[test]
+fn test_pbrt_scene_bvh_sah_full() {
+    let mut settings = Cli::new_from_pbrt("scenes/pbrt/scene.pbrt".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_pbrt_scene_grid_5() {
+    let mut settings = Cli::new_from_pbrt("scenes/pbrt/scene.pbrt".to_string());
+    settings.algorithm = IntersectionAlgorithm::Grid;
+    settings.grid_size = 5.0;
+    run(settings);
+}
+
 #[test]
 fn test_dragon_4_bvh_naive() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::BVH;
     settings.add_option(AlgorithmOptions::BvhNaive);
     run(settings);
@@ -204,6 +381,7 @@ fn test_dragon_4_bvh_naive() {
 #[test]
 fn test_dragon_4_bvh_sah_plane() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::BVH;
     settings.add_option(AlgorithmOptions::BvhSahPlane);
     run(settings);
@@ -211,13 +389,87 @@ fn test_dragon_4_bvh_sah_plane() {
 #[test]
 fn test_dragon_4_bvh_sah_full() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::BVH;
     settings.add_option(AlgorithmOptions::BvhSahPosition);
     run(settings);
 }
 #[test]
+fn test_dragon_4_bvh_sah_binned_16() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahBinned16);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh_sah_binned_32() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahBinned32);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh_sbvh() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSbvh);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh_hlbvh() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhHlbvh);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh_naive_optimize() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhNaive);
+    settings.add_option(AlgorithmOptions::BvhOptimize);
+    settings.optimize_seconds = Some(1.0);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_kd_tree() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::KdTree;
+    run(settings);
+}
+#[test]
+fn test_dragon_4_octree() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::Octree;
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh4_sah_binned_16() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH4;
+    settings.add_option(AlgorithmOptions::BvhSahBinned16);
+    run(settings);
+}
+#[test]
+fn test_dragon_4_bvh8_sah_binned_16() {
+    let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
+    settings.algorithm = IntersectionAlgorithm::BVH8;
+    settings.add_option(AlgorithmOptions::BvhSahBinned16);
+    run(settings);
+}
+#[test]
 fn test_dragon_4_grid_25() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::Grid;
     settings.grid_size = 25.0;
     run(settings);
@@ -225,6 +477,7 @@ fn test_dragon_4_grid_25() {
 #[test]
 fn test_dragon_4_grid_5() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::Grid;
     settings.grid_size = 5.0;
     run(settings);
@@ -232,10 +485,28 @@ fn test_dragon_4_grid_5() {
 #[test]
 fn test_dragon_4_grid_1() {
     let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+    settings.import.scale = 100.0;
     settings.algorithm = IntersectionAlgorithm::Grid;
     settings.grid_size = 1.0;
     run(settings);
 }
+#[test]
+fn test_dragon_glass_bvh_sah_full() {
+    let mut settings = Cli::new_from_json("scenes/dragon_glass.json".to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
+#[test]
+fn test_dragon_glass_binary_bvh_sah_full() {
+    let world = crate::hittable::hittable_list::HittableList::load("scenes/dragon_glass.json".as_ref()).unwrap();
+    let path = std::env::temp_dir().join("dragon_glass_binary.rtb");
+    crate::utils::binary::save_binary(&world, &path).unwrap();
+    let mut settings = Cli::new_from_binary(path.to_string_lossy().to_string());
+    settings.algorithm = IntersectionAlgorithm::BVH;
+    settings.add_option(AlgorithmOptions::BvhSahPosition);
+    run(settings);
+}
 
 
 #[allow(unused)]
@@ -246,6 +517,7 @@ mod camera {
     #[test]
     fn sah_pos_0() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::BVH;
         settings.add_option(AlgorithmOptions::BvhSahPosition);
         settings.camera = Some(0);
@@ -254,6 +526,7 @@ mod camera {
     #[test]
     fn sah_pos_1() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::BVH;
         settings.add_option(AlgorithmOptions::BvhSahPosition);
         settings.camera = Some(1);
@@ -262,6 +535,7 @@ mod camera {
     #[test]
     fn sah_pos_2() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::BVH;
         settings.add_option(AlgorithmOptions::BvhSahPosition);
         settings.camera = Some(2);
@@ -270,6 +544,7 @@ mod camera {
     #[test]
     fn sah_pos_3() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::BVH;
         settings.add_option(AlgorithmOptions::BvhSahPosition);
         settings.camera = Some(3);
@@ -279,6 +554,7 @@ mod camera {
     #[test]
     fn grid_pos_0() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::Grid;
         settings.grid_size = 5.0;
         settings.camera = Some(0);
@@ -287,6 +563,7 @@ mod camera {
     #[test]
     fn grid_pos_1() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::Grid;
         settings.grid_size = 5.0;
         settings.camera = Some(1);
@@ -295,6 +572,7 @@ mod camera {
     #[test]
     fn grid_pos_2() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::Grid;
         settings.grid_size = 5.0;
         settings.camera = Some(2);
@@ -303,9 +581,2216 @@ mod camera {
     #[test]
     fn grid_pos_3() {
         let mut settings = Cli::new_from_ply("scenes/dragon_recon/dragon_vrip_res4.ply".to_string());
+        settings.import.scale = 100.0;
         settings.algorithm = IntersectionAlgorithm::Grid;
         settings.grid_size = 5.0;
         settings.camera = Some(3);
         run(settings);
     }
-}
\ No newline at end of file
+}
+
+mod primitives {
+    use std::rc::Rc;
+    use crate::hittable::cuboid::Cuboid;
+    use crate::hittable::disk::Disk;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::plane::Plane;
+    use crate::hittable::quad::Quad;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::utils::rtweekend::{IntersectionAlgorithm, Options};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::{Lambertian, Material};
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    pub(super) fn material() -> Rc<dyn Material> {
+        Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    pub(super) fn hit(object: &dyn Hittable, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(origin, direction);
+        object.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec, &mut Data::default())
+            .then_some(rec)
+    }
+
+    pub(super) fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn quad() {
+        let quad = Quad::new(Point3::new(-1.0, -1.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), material());
+        let rec = hit(&quad, Point3::new(0.5, -0.5, 5.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.p, Point3::new(0.5, -0.5, 0.0));
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        // The coordinates along the edges
+        assert!((rec.u - 0.75).abs() < 1e-9 && (rec.v - 0.25).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // From behind, the normal faces the ray
+        let rec = hit(&quad, Point3::new(0.5, -0.5, -5.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        assert!(hit(&quad, Point3::new(1.1, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(&quad, Point3::new(0.0, -1.1, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(&quad, Point3::new(0.0, 0.0, 5.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+
+        // The flat side is padded
+        let aabb = quad.to_aabb();
+        assert_near(aabb.min, Point3::new(-1.0, -1.0, -5e-5));
+        assert_near(aabb.max, Point3::new(1.0, 1.0, 5e-5));
+    }
+
+    #[test]
+    fn disk() {
+        let disk = Disk::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 2.0, 0.0), 2.0, material());
+        let rec = hit(&disk, Point3::new(1.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(rec.front_face);
+        // v is the distance to the center, u the angle around it
+        assert!((rec.v - 0.5).abs() < 1e-9);
+        let opposite = hit(&disk, Point3::new(-1.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!(((rec.u - opposite.u).abs() - 0.5).abs() < 1e-9, "{} {}", rec.u, opposite.u);
+        assert!((0.0..=1.0).contains(&rec.u));
+
+        let rec = hit(&disk, Point3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+
+        // Inside the square around the disk, but outside the circle
+        assert!(hit(&disk, Point3::new(1.5, 5.0, 1.5), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        assert!(hit(&disk, Point3::new(2.1, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+
+        let aabb = disk.to_aabb();
+        assert_near(aabb.min, Point3::new(-2.0, 1.0 - 5e-5, -2.0));
+        assert_near(aabb.max, Point3::new(2.0, 1.0 + 5e-5, 2.0));
+        // A tilted disk is narrower along the axes it is tilted towards
+        let tilted = Disk::new(Point3::default(), Vec3::new(1.0, 1.0, 0.0), 2.0, material()).to_aabb();
+        assert_near(tilted.max, Point3::new(2f64.sqrt(), 2f64.sqrt(), 2.0));
+    }
+
+    #[test]
+    fn cuboid() {
+        let cuboid = Cuboid::new(Point3::new(1.0, 2.0, 3.0), Point3::new(-1.0, -1.0, -1.0), material());
+        let rec = hit(&cuboid, Point3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 7.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        // The other two axes of the face, from 0 to 1 across it
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 1.0 / 3.0).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // From inside the exit is hit, facing the ray
+        let rec = hit(&cuboid, Point3::new(0.0, 0.5, 1.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        assert!(hit(&cuboid, Point3::new(1.1, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(&cuboid, Point3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, 1.0)).is_none());
+
+        let aabb = cuboid.to_aabb();
+        assert_near(aabb.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_near(aabb.max, Point3::new(1.0, 2.0, 3.0));
+
+        // Turned 45 degrees around z, the corner is hit first
+        let oriented = Cuboid::oriented(Point3::default(), Vec3::new(1.0, 1.0, 1.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0), material());
+        let rec = hit(&oriented, Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - (5.0 - 2f64.sqrt())).abs() < 1e-9);
+        assert_near(oriented.to_aabb().max, Point3::new(2f64.sqrt(), 2f64.sqrt(), 1.0));
+    }
+
+    #[test]
+    fn plane() {
+        let plane = Plane::new(Point3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 3.0, 0.0), material());
+        let rec = hit(&plane, Point3::new(100.5, 1.0, -20.25), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(rec.front_face);
+        // The texture coordinates repeat
+        assert!((0.0..1.0).contains(&rec.u) && (0.0..1.0).contains(&rec.v));
+        let next = hit(&plane, Point3::new(100.5, 1.0, -19.25), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((next.u - rec.u).abs() < 1e-9 || (next.v - rec.v).abs() < 1e-9);
+
+        assert!(!hit(&plane, Point3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap().front_face);
+        assert!(hit(&plane, Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(hit(&plane, Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).is_none());
+        assert!(!plane.is_bounded());
+        assert!(plane.to_aabb().contains(Point3::new(1e300, -1e300, 0.0)));
+    }
+
+    #[test]
+    fn unbounded_with_acceleration() {
+        for algorithm in [IntersectionAlgorithm::BVH, IntersectionAlgorithm::BVH4, IntersectionAlgorithm::BVH8,
+                          IntersectionAlgorithm::KdTree, IntersectionAlgorithm::Octree] {
+            let mut world = HittableList::default();
+            world.add(Rc::new(Sphere::new(Point3::new(0.0, 0.0, -5.0), 1.0, material())));
+            world.add(Rc::new(Plane::new(Point3::new(0.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0), material())));
+            world.algorithm = algorithm;
+            world.options = Options::default();
+            world.init();
+
+            // The plane is found next to the structure, far outside of its bounds
+            let rec = hit(&world, Point3::new(100.0, 0.0, 100.0), Vec3::new(0.0, -1.0, 0.0)).expect("plane");
+            assert!((rec.t - 2.0).abs() < 1e-9, "{algorithm:?}");
+            // The closest of the sphere and the plane
+            let rec = hit(&world, Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)).expect("sphere");
+            assert!((rec.t - 4.0).abs() < 1e-9, "{algorithm:?}");
+            let rec = hit(&world, Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, -1.0, 0.0)).expect("inside the sphere");
+            assert!((rec.t - 1.0).abs() < 1e-9, "{algorithm:?}");
+            let rec = hit(&world, Point3::new(0.0, 10.0, -5.0), Vec3::new(0.0, -1.0, 0.0)).expect("sphere before the plane");
+            assert!((rec.t - 9.0).abs() < 1e-9, "{algorithm:?}");
+        }
+    }
+}
+
+mod surfaces {
+    use std::rc::Rc;
+    use super::primitives::{assert_near, hit, material};
+    use crate::hittable::cone::Cone;
+    use crate::hittable::cylinder::Cylinder;
+    use crate::hittable::instance::Instance;
+    use crate::hittable::paraboloid::Paraboloid;
+    use crate::hittable::quad::Quad;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::torus::Torus;
+    use crate::hittable::Hittable;
+    use crate::value::transform::Transform;
+    use crate::value::vec3::{Point3, Vec3};
+
+    #[test]
+    fn cylinder() {
+        let cylinder = Cylinder::new(1.0, 2.0, true, material());
+        let rec = hit(&cylinder, Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+        // u goes around the axis, v up along it
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+        let rec = hit(&cylinder, Point3::new(0.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.25).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // The top cap
+        let rec = hit(&cylinder, Point3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!((rec.u - 0.75).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // Without caps the ray goes in through the top and hits the inside of the side
+        let origin = Point3::new(0.0, 3.0, 0.0);
+        let direction = Vec3::new(1.0, -2.0, 0.0);
+        let rec = hit(&cylinder, origin, direction).unwrap();
+        assert!((rec.t - 0.5).abs() < 1e-9);
+        let rec = hit(&Cylinder::new(1.0, 2.0, false, material()), origin, direction).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        assert!(hit(&cylinder, Point3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+        assert!(hit(&cylinder, Point3::new(5.0, 1.0, 1.1), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+        let aabb = cylinder.to_aabb();
+        assert_near(aabb.min, Point3::new(-1.0, 0.0, -1.0));
+        assert_near(aabb.max, Point3::new(1.0, 2.0, 1.0));
+    }
+
+    #[test]
+    fn cone() {
+        let cone = Cone::new(1.0, 2.0, true, material());
+        // Half way up the radius is halved
+        let rec = hit(&cone, Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(2.0, 1.0, 0.0).unit());
+        assert!(rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // The base
+        let rec = hit(&cone, Point3::new(0.5, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+        assert!(rec.front_face);
+        assert!((rec.u - 0.75).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+        // Without the base the ray goes on to the inside of the side
+        let rec = hit(&Cone::new(1.0, 2.0, false, material()), Point3::new(0.5, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((rec.t - 6.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+
+        // The apex
+        let rec = hit(&cone, Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        assert!(hit(&cone, Point3::new(5.0, 1.0, 0.6), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+        assert!(hit(&cone, Point3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn paraboloid() {
+        let paraboloid = Paraboloid::new(1.0, 1.0, true, material());
+        let rec = hit(&paraboloid, Point3::new(5.0, 0.25, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, -1.0, 0.0).unit());
+        assert!(rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.25).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        // The vertex from below
+        let rec = hit(&paraboloid, Point3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+
+        // The cap, and without it the inside of the vertex
+        let rec = hit(&paraboloid, Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+        let rec = hit(&Paraboloid::new(1.0, 1.0, false, material()), Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        // Above the height the surface is cut off
+        assert!(hit(&paraboloid, Point3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn torus() {
+        let torus = Torus::new(2.0, 0.5, material());
+        let rec = hit(&torus, Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+        // u goes around the y axis, v around the tube
+        assert!((rec.u - 0.5).abs() < 1e-6 && (rec.v - 0.5).abs() < 1e-6, "{} {}", rec.u, rec.v);
+
+        let rec = hit(&torus, Point3::new(2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-6);
+        assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-6);
+        assert!((rec.v - 0.75).abs() < 1e-6);
+
+        // From the center the inner side of the tube is hit, facing the hole
+        let rec = hit(&torus, Point3::default(), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 1.5).abs() < 1e-6);
+        assert_near(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+
+        // Through the hole and over the top
+        assert!(hit(&torus, Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+        assert!(hit(&torus, Point3::new(5.0, 0.6, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+        let aabb = torus.to_aabb();
+        assert_near(aabb.min, Point3::new(-2.5, -0.5, -2.5));
+        assert_near(aabb.max, Point3::new(2.5, 0.5, 2.5));
+    }
+
+    #[test]
+    fn instance_rotated() {
+        // A cylinder lying along the negative x axis, moved back
+        let transform = Transform::translate(Vec3::new(0.0, 0.0, -5.0)) * Transform::rotate(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let instance = Instance::new(Rc::new(Cylinder::new(1.0, 2.0, true, material())), transform);
+
+        let rec = hit(&instance, Point3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_near(rec.p, Point3::new(-1.0, 0.0, -4.0));
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        // The bottom cap now faces +x, the texture coordinates stay those of the object
+        let rec = hit(&instance, Point3::new(5.0, 0.5, -5.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+        assert!((rec.u - 0.75).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.u, rec.v);
+
+        let aabb = instance.to_aabb();
+        assert_near(aabb.min, Point3::new(-2.0, -1.0, -6.0));
+        assert_near(aabb.max, Point3::new(0.0, 1.0, -4.0));
+    }
+
+    #[test]
+    fn instance_scaled() {
+        // An ellipsoid x^2 / 4 + y^2 + z^2 = 1
+        let instance = Instance::new(Rc::new(Sphere::new(Point3::default(), 1.0, material())), Transform::scale(Vec3::new(2.0, 1.0, 1.0)));
+        let rec = hit(&instance, Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        // The normal follows the gradient (x / 4, y, z), not the scaled sphere normal
+        // and t is in world units, also for a direction that is not normalised
+        let (x, y) = (2f64.sqrt(), 0.5f64.sqrt());
+        let rec = hit(&instance, Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -2.0, 0.0)).unwrap();
+        assert!((rec.t - (5.0 - y) / 2.0).abs() < 1e-9);
+        assert_near(rec.p, Point3::new(x, y, 0.0));
+        assert_near(rec.normal, Vec3::new(1.0, 2.0, 0.0).unit());
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn instance_mirrored() {
+        // A negative determinant must not turn a closed object inside out
+        let transform = Transform::scale(Vec3::new(-2.0, 1.0, 1.0));
+        assert!(transform.determinant() < 0.0);
+        let instance = Instance::new(Rc::new(Sphere::new(Point3::default(), 1.0, material())), transform);
+        let rec = hit(&instance, Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+        let rec = hit(&instance, Point3::default(), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, -1.0));
+
+        // A mirrored quad faces the mirrored way and the normal still faces the ray
+        let quad = Quad::new(Point3::new(-1.0, -1.0, 1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), material());
+        let instance = Instance::new(Rc::new(quad), Transform::scale(Vec3::new(1.0, 1.0, -1.0)));
+        let rec = hit(&instance, Point3::default(), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+        assert_near(rec.p, Point3::new(0.0, 0.0, -1.0));
+        assert!(!rec.front_face);
+        assert_near(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+}
+
+mod polynomial {
+    use crate::utils::polynomial::{solve_cubic, solve_quartic};
+
+    fn assert_roots(mut roots: Vec<f64>, mut expected: Vec<f64>) {
+        roots.sort_by(f64::total_cmp);
+        expected.sort_by(f64::total_cmp);
+        assert_eq!(roots.len(), expected.len(), "{roots:?} != {expected:?}");
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-9, "{root} != {expected}");
+        }
+    }
+
+    #[test]
+    fn cubic_three_roots() {
+        // (x - 1)(x - 2)(x + 3) = x^3 - 7x + 6
+        assert_roots(solve_cubic(0.0, -7.0, 6.0).as_slice().to_vec(), vec![1.0, 2.0, -3.0]);
+    }
+
+    #[test]
+    fn quartic_four_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4) = x^4 - 10x^3 + 35x^2 - 50x + 24
+        assert_roots(solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0).as_slice().to_vec(), vec![1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn quartic_biquadratic() {
+        // (x^2 - 1)(x^2 - 4) = x^4 - 5x^2 + 4
+        assert_roots(solve_quartic(1.0, 0.0, -5.0, 0.0, 4.0).as_slice().to_vec(), vec![-2.0, -1.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn quartic_two_roots() {
+        // (x - 0.5)(x + 1.5)(x^2 + 1)
+        let (a, b) = (-0.5, 1.5);
+        let roots = solve_quartic(1.0, a + b, a * b + 1.0, a + b, a * b);
+        assert_roots(roots.as_slice().to_vec(), vec![0.5, -1.5]);
+    }
+
+    #[test]
+    fn quartic_no_roots() {
+        // (x^2 + 1)(x^2 + 2)
+        assert!(solve_quartic(1.0, 0.0, 3.0, 0.0, 2.0).as_slice().is_empty());
+    }
+}
+
+
+mod csg {
+    use std::rc::Rc;
+    use crate::hittable::csg::{Csg, CsgOperation};
+    use crate::hittable::cuboid::Cuboid;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn hit(object: &dyn Hittable, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(origin, direction);
+        object.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec, &mut Data::default())
+            .then_some(rec)
+    }
+
+    #[test]
+    fn difference_has_hole() {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let block = Rc::new(Cuboid::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0), material.clone()));
+        let hole = Rc::new(Sphere::new(Point3::new(0.0, 0.0, 1.0), 0.5, material));
+        let csg = Csg::new(CsgOperation::Difference, block, hole);
+
+        // Next to the hole, the front of the block is hit
+        let rec = hit(&csg, Point3::new(0.8, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert!(rec.front_face);
+
+        // Through the hole, the inside of the sphere is hit, facing the ray
+        let rec = hit(&csg, Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!((rec.normal.z() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn intersection_of_spheres() {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let a = Rc::new(Sphere::new(Point3::new(-0.5, 0.0, 0.0), 1.0, material.clone()));
+        let b = Rc::new(Sphere::new(Point3::new(0.5, 0.0, 0.0), 1.0, material));
+        let csg = Csg::new(CsgOperation::Intersection, a, b);
+
+        let rec = hit(&csg, Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9);
+        // Outside the overlapping part nothing is hit
+        assert!(hit(&csg, Point3::new(-5.0, 0.9, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn closed_mesh_spans() {
+        // A tetrahedron with outward facing triangles
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let v = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 0.0, 1.0)];
+        let mut mesh = HittableList::default();
+        for [a, b, c] in [[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]] {
+            mesh.add(Rc::new(Triangle::new(v[a], v[b], v[c], material.clone())));
+        }
+
+        let ray = Ray::new(Point3::new(0.2, 0.2, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let spans = mesh.hit_spans(&ray, &mut Data::default()).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].enter.t - 1.0).abs() < 1e-9);
+        assert!((spans[0].exit.t - 1.6).abs() < 1e-9);
+    }
+}
+
+mod sdf {
+    use std::rc::Rc;
+    use crate::hittable::sdf::{Sdf, SdfShape};
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn shape(sdf: Sdf) -> SdfShape {
+        SdfShape::new(sdf, Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))))
+    }
+
+    fn hit(object: &dyn Hittable, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(origin, direction);
+        object.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rec, &mut Data::default())
+            .then_some(rec)
+    }
+
+    #[test]
+    fn sphere_matches_analytic() {
+        let sphere = shape(Sdf::Sphere { radius: 1.0 });
+        let rec = hit(&sphere, Point3::new(0.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0)).unwrap();
+        let expected_t = (5.0 - 0.75_f64.sqrt()) / 2.0;
+        assert!((rec.t - expected_t).abs() < 1e-3);
+        assert!((rec.normal - Vec3::new(0.5, 0.0, 0.75_f64.sqrt())).length() < 1e-3);
+        assert!(rec.front_face);
+
+        // From the inside the far side is hit, facing the ray
+        let rec = hit(&sphere, Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-3);
+        assert!(!rec.front_face);
+
+        assert!(hit(&sphere, Point3::new(1.1, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+    }
+
+    #[test]
+    fn subtract_has_hole() {
+        let sdf = Sdf::Subtract {
+            a: Box::new(Sdf::Box { half_extents: Vec3::new(1.0, 1.0, 1.0) }),
+            b: Box::new(Sdf::Sphere { radius: 0.5 }),
+        };
+        assert!((sdf.distance(Point3::new(0.0, 0.0, 0.0)) - 0.5).abs() < 1e-9);
+        assert!((sdf.distance(Point3::new(0.0, 0.0, 3.0)) - 2.0).abs() < 1e-9);
+
+        let spans = shape(sdf).hit_spans(&Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), &mut Data::default()).unwrap();
+        assert_eq!(spans.len(), 2);
+        for (span, (enter, exit)) in spans.iter().zip([(4.0, 4.5), (5.5, 6.0)]) {
+            assert!((span.enter.t - enter).abs() < 1e-3);
+            assert!((span.exit.t - exit).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn bounds_contain_twist() {
+        let sdf = Sdf::Twist { amount: 1.0, shape: Box::new(Sdf::Box { half_extents: Vec3::new(1.0, 2.0, 0.2) }) };
+        let bounds = sdf.bounds();
+        for y in [-2.0, -1.0, 0.0, 1.0, 2.0] {
+            for angle in 0..16 {
+                let (sin, cos) = (angle as f64 * std::f64::consts::PI / 8.0).sin_cos();
+                let p = Point3::new(1.2 * cos, y, 1.2 * sin);
+                // Points outside the bounds have to be outside the shape
+                if !bounds.contains(p) {
+                    assert!(sdf.distance(p) > 0.0);
+                }
+            }
+        }
+        assert!(sdf.lipschitz() > 1.0);
+    }
+
+    #[test]
+    fn bounds_contain_smooth_union() {
+        let sphere = |x: f64| Box::new(Sdf::Translate { offset: Vec3::new(x, 0.0, 0.0), shape: Box::new(Sdf::Sphere { radius: 1.0 }) });
+        let sdf = Sdf::SmoothUnion { a: sphere(-1.0), b: sphere(1.0), k: 2.0 };
+        // Between the spheres the blend reaches above both of them, up to y = 1.118
+        let p = Point3::new(0.0, 1.1, 0.0);
+        assert!(sdf.distance(p) < 0.0);
+        assert!(!Sdf::Union { a: sphere(-1.0), b: sphere(1.0) }.bounds().contains(p));
+        assert!(sdf.bounds().contains(p));
+    }
+}
+
+mod triangle {
+    use std::rc::Rc;
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::utils::rtweekend::{IntersectionAlgorithm, Options, TriangleAlgorithm};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn data(triangle: TriangleAlgorithm) -> Data {
+        let options = Options { triangle, ..Default::default() };
+        Data::new(String::new(), 0, IntersectionAlgorithm::Naive, options, 0, 0, 0, 0)
+    }
+
+    fn closest_hit(mesh: &[Triangle], ray: &Ray, data: &mut Data) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+        for triangle in mesh {
+            let mut rec = HitRecord::default();
+            let ray_t = Interval::new(0.0, closest.as_ref().map_or(f64::INFINITY, |rec| rec.t));
+            if triangle.hit(ray, ray_t, &mut rec, data) {
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+
+    /// The cube from -1 to 1, every face split in two along its diagonal, facing outwards
+    fn cube() -> Vec<Triangle> {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let corner = |i: usize| Point3::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        );
+        let faces = [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        faces.iter()
+            .flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]])
+            .map(|[a, b, c]| Triangle::new(corner(a), corner(b), corner(c), material.clone()))
+            .collect()
+    }
+
+    /// A sphere of radius 1, made by subdividing an octahedron and moving the vertices outwards
+    fn octasphere(subdivisions: u32) -> Vec<Triangle> {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let (x, y, z) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let mut faces = vec![];
+        for sx in [-1.0, 1.0] {
+            for sy in [-1.0, 1.0] {
+                for sz in [-1.0, 1.0] {
+                    let (a, b, c) = (sx * x, sy * y, sz * z);
+                    // Keep the winding counter-clockwise seen from outside
+                    faces.push(if sx * sy * sz > 0.0 { [a, b, c] } else { [a, c, b] });
+                }
+            }
+        }
+        for _ in 0..subdivisions {
+            faces = faces.into_iter().flat_map(|[a, b, c]| {
+                let (ab, bc, ca) = ((a + b).unit(), (b + c).unit(), (c + a).unit());
+                [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            }).collect();
+        }
+        faces.into_iter()
+            .map(|[a, b, c]| Triangle::new(a, b, c, material.clone()))
+            .collect()
+    }
+
+    /// Rays from the center of the cube to a grid of points on its faces, including all edges and corners
+    fn cube_rays() -> Vec<Ray> {
+        let steps = 16;
+        let mut rays = vec![];
+        for axis in 0..3u32 {
+            for side in [-1.0, 1.0] {
+                for i in -steps..=steps {
+                    for j in -steps..=steps {
+                        let mut direction = Vec3::default();
+                        direction[axis] = side;
+                        direction[(axis + 1) % 3] = i as f64 / steps as f64;
+                        direction[(axis + 2) % 3] = j as f64 / steps as f64;
+                        rays.push(Ray::new(Point3::default(), direction));
+                    }
+                }
+            }
+        }
+        rays
+    }
+
+    #[test]
+    fn watertight_cube_from_inside() {
+        let mesh = cube();
+        let mut data = data(TriangleAlgorithm::Watertight);
+        for ray in cube_rays() {
+            let rec = closest_hit(&mesh, &ray, &mut data).unwrap_or_else(|| panic!("Ray leaked: {:?}", ray.direction()));
+            assert!((rec.t - 1.0).abs() < 1e-9);
+            assert!(!rec.front_face);
+        }
+    }
+
+    #[test]
+    fn watertight_cube_parallel_grid() {
+        // Parallel rays along every axis, many of them go exactly through the diagonals and edges
+        let mesh = cube();
+        let mut data = data(TriangleAlgorithm::Watertight);
+        let steps = 16;
+        for axis in 0..3u32 {
+            for i in -steps..=steps {
+                for j in -steps..=steps {
+                    let mut origin = Point3::default();
+                    origin[axis] = 5.0;
+                    origin[(axis + 1) % 3] = i as f64 / steps as f64;
+                    origin[(axis + 2) % 3] = j as f64 / steps as f64;
+                    let mut direction = Vec3::default();
+                    direction[axis] = -1.0;
+                    let ray = Ray::new(origin, direction);
+                    let rec = closest_hit(&mesh, &ray, &mut data).unwrap_or_else(|| panic!("Ray leaked: {:?}", origin));
+                    assert!((rec.t - 4.0).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn watertight_sphere_from_inside() {
+        let mesh = octasphere(4);
+        let mut data = data(TriangleAlgorithm::Watertight);
+        // A dense spiral of directions, which also passes through the original octahedron vertices
+        let count = 20000;
+        for i in 0..count {
+            let y = 1.0 - 2.0 * i as f64 / (count - 1) as f64;
+            let radius = (1.0 - y * y).sqrt();
+            let phi = i as f64 * std::f64::consts::PI * (3.0 - 5.0_f64.sqrt());
+            let origin = Point3::new(0.01, -0.02, 0.03);
+            let ray = Ray::new(origin, Vec3::new(radius * phi.cos(), y, radius * phi.sin()));
+            assert!(closest_hit(&mesh, &ray, &mut data).is_some(), "Ray leaked: {:?}", ray.direction());
+        }
+        // Rays towards the shared vertices
+        for triangle in &mesh {
+            for vertex in [triangle.a(), triangle.b(), triangle.c()] {
+                let ray = Ray::new(Point3::default(), vertex);
+                let rec = closest_hit(&mesh, &ray, &mut data).unwrap_or_else(|| panic!("Ray leaked: {:?}", vertex));
+                assert!((rec.t - 1.0).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn algorithms_agree() {
+        let mesh = octasphere(2);
+        let mut moller_trumbore = data(TriangleAlgorithm::MollerTrumbore);
+        let mut watertight = data(TriangleAlgorithm::Watertight);
+        for ray in cube_rays() {
+            let ray = Ray::new(Point3::new(0.0, 0.0, 3.0), *ray.direction() - Vec3::new(0.0, 0.0, 3.0));
+            let a = closest_hit(&mesh, &ray, &mut moller_trumbore);
+            let b = closest_hit(&mesh, &ray, &mut watertight);
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    // Möller-Trumbore can slip through an edge of the front and hit the back instead
+                    assert!(b.t <= a.t + 1e-9);
+                    if (a.t - b.t).abs() < 1e-9 {
+                        assert!((a.p - b.p).length() < 1e-9);
+                    }
+                }
+                (Some(_), None) => panic!("Only Möller-Trumbore hit: {:?}", ray.direction()),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_ray_misses() {
+        let mesh = cube();
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        for algorithm in [TriangleAlgorithm::MollerTrumbore, TriangleAlgorithm::Watertight] {
+            let rec = closest_hit(&mesh, &ray, &mut data(algorithm)).unwrap();
+            // The face in the plane of the ray is ignored, the side is hit
+            assert!((rec.t - 1.0).abs() < 1e-9);
+            assert!((rec.normal.x() + 1.0).abs() < 1e-9);
+        }
+    }
+}
+
+mod obj {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use crate::utils::mesh::{triangulate, ImportOptions};
+    use crate::utils::obj::parse_obj;
+    use crate::utils::parser::ParseError;
+    use crate::value::color::Color;
+    use crate::value::material::{Lambertian, MaterialType};
+    use crate::value::vec3::Point3;
+
+    fn parse(name: &str, contents: &str) -> Result<usize, ParseError> {
+        let path = std::env::temp_dir().join(format!("raytracer-test-{name}.obj"));
+        fs::write(&path, contents).unwrap();
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let world = parse_obj(&path, material, &ImportOptions::default());
+        fs::remove_file(&path).unwrap();
+        world.map(|world| world.objects.len())
+    }
+
+    #[test]
+    fn materials_scene() {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let world = parse_obj(&PathBuf::from("scenes/obj/materials.obj"), material, &ImportOptions::default()).unwrap();
+        // Floor, cube, sphere, octahedron and prism
+        assert_eq!(world.objects.len(), 2 + 12 + 528 + 8 + 20);
+        let count = |material_type: MaterialType| world.objects.iter().filter(|o| o.material_type().as_ref() == Some(&material_type)).count();
+        assert_eq!(count(MaterialType::Metal), 12);
+        assert_eq!(count(MaterialType::Dielectric), 8);
+        assert_eq!(count(MaterialType::Lambertian), 2 + 528 + 20);
+    }
+
+    #[test]
+    fn negative_indices() {
+        let relative = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf -4 -3 -2 -1\n";
+        let absolute = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+        assert_eq!(parse("relative", relative).unwrap(), 2);
+        assert_eq!(parse("absolute", absolute).unwrap(), 2);
+    }
+
+    #[test]
+    fn corner_formats() {
+        let contents = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+            g first\nf 1/1 2/2 3/3\no second\nf 1//1 2//1 3//1\nf 1/1/1 2/2/1 3/3/1\n";
+        assert_eq!(parse("corners", contents).unwrap(), 3);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse("out-of-range", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n").unwrap_err();
+        assert!(matches!(error, ParseError::ParseLine(_, 4, _)), "{error}");
+        let error = parse("zero", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n").unwrap_err();
+        assert!(matches!(error, ParseError::ParseLine(_, 4, _)), "{error}");
+        let error = parse("number", "v 0 zero 0\n").unwrap_err();
+        assert!(matches!(error, ParseError::ParseLine(_, 1, _)), "{error}");
+
+        // The cause is part of the message
+        let mtl = std::env::temp_dir().join("raytracer-test-texture.mtl");
+        fs::write(&mtl, "newmtl textured\nKd 1 1 1\nmap_Kd raytracer-test-missing.png\n").unwrap();
+        let error = parse("texture", "mtllib raytracer-test-texture.mtl\n").unwrap_err().to_string();
+        fs::remove_file(&mtl).unwrap();
+        assert!(error.starts_with("Error loading texture: ") && error.contains("No such file"), "{error}");
+    }
+
+    #[test]
+    fn triangulate_concave() {
+        // An L shape, a fan from the first corner would cover the missing corner
+        let polygon = [(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)]
+            .map(|(x, y)| Point3::new(x, y, 0.0));
+        let triangles = triangulate(&polygon);
+        assert_eq!(triangles.len(), 4);
+        let area: f64 = triangles.iter()
+            .map(|&[a, b, c]| 0.5 * (polygon[b] - polygon[a]).cross(&(polygon[c] - polygon[a])).z())
+            .sum();
+        // All triangles keep the counter-clockwise winding, so the signed areas add up
+        assert!((area - 3.0).abs() < 1e-9);
+        for &[a, b, c] in &triangles {
+            assert!((polygon[b] - polygon[a]).cross(&(polygon[c] - polygon[a])).z() > 0.0);
+        }
+    }
+}
+
+mod gltf {
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::utils::gltf::parse_gltf;
+    use crate::utils::mesh::ImportOptions;
+    use crate::value::color::Color;
+    use crate::value::light::Light;
+    use crate::value::material::{Lambertian, MaterialType};
+    use crate::value::vec3::Point3;
+
+    fn load(file: &str) -> HittableList {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        parse_gltf(&PathBuf::from(file), material, &ImportOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn scene_contents() {
+        for file in ["scenes/gltf/scene.gltf", "scenes/gltf/scene.glb"] {
+            let world = load(file);
+            // Ground, two cubes, sphere, hexagon (as a fan) and the triangle without a material
+            assert_eq!(world.objects.len(), 2 + 12 + 12 + 528 + 6 + 1, "{file}");
+            let count = |material_type: MaterialType| world.objects.iter().filter(|o| o.material_type().as_ref() == Some(&material_type)).count();
+            assert_eq!(count(MaterialType::Metal), 12);
+            assert_eq!(count(MaterialType::Dielectric), 528);
+            assert_eq!(count(MaterialType::DiffuseLight), 6);
+            assert_eq!(count(MaterialType::Lambertian), 2 + 12 + 1);
+        }
+    }
+
+    #[test]
+    fn camera_and_lights() {
+        let world = load("scenes/gltf/scene.gltf");
+        let camera = world.camera.expect("scene has a camera");
+        assert!((camera.look_from - Point3::new(0.0, 0.6, 2.2)).length() < 1e-6);
+        // Rotated 12 degrees down around the x axis
+        let direction = (camera.look_at - camera.look_from).unit();
+        assert!((direction.y() + 12f64.to_radians().sin()).abs() < 1e-6);
+        assert!((camera.vfov - 0.8f64.to_degrees()).abs() < 1e-4);
+
+        assert_eq!(world.lights.len(), 2);
+        assert!(matches!(world.lights[0], Light::Point { intensity, .. } if intensity == 8.0));
+        let Light::Directional { direction, .. } = world.lights[1] else { panic!("second light should be directional") };
+        assert!(direction.y() < 0.0);
+    }
+
+    #[test]
+    fn node_hierarchy() {
+        let world = load("scenes/gltf/scene.gltf");
+        // The red cube comes after the ground, it is moved by its own and its parent's transform
+        let center = world.objects[2..14].iter()
+            .fold(Point3::default(), |sum, triangle| sum + triangle.centroid()) / 12.0;
+        let angle = 30f64.to_radians();
+        let expected = Point3::new(-0.6 - 0.5 * angle.cos(), -0.2, -1.0 + 0.5 * angle.sin());
+        assert!((center - expected).length() < 1e-5, "{center:?}");
+    }
+
+    #[test]
+    fn errors_have_the_cause() {
+        let path = std::env::temp_dir().join("raytracer-test-invalid.gltf");
+        std::fs::write(&path, "{\"asset\": {}}").unwrap();
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let error = parse_gltf(&path, material, &ImportOptions::default()).err().unwrap().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("Error loading glTF file: ") && error.contains("version"), "{error}");
+    }
+}
+
+mod ply {
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::acceleration::aabb::AABB;
+    use crate::utils::mesh::{ImportOptions, UpAxis};
+    use crate::utils::parser::{parse_ply, ParseError};
+    use crate::utils::rtweekend::{IntersectionAlgorithm, Options};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    /// A unit square with a roof, as a quad, a triangle and a pentagon covering both
+    const POSITIONS: [(f64, f64); 5] = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.5, 1.5)];
+    const FACES: [&[u32]; 3] = [&[0, 1, 2, 3], &[3, 2, 4], &[0, 1, 2, 4, 3]];
+
+    /// Writes the mesh with double positions, float normals, uchar colors, float `s`/`t` and uint indices
+    fn write(name: &str, format: &str, faces: &[&[u32]]) -> std::path::PathBuf {
+        let mut contents = format!("ply\nformat {format} 1.0\nelement vertex {}\n\
+            property double x\nproperty double y\nproperty double z\n\
+            property float nx\nproperty float ny\nproperty float nz\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            property float s\nproperty float t\n\
+            element face {}\nproperty list uchar uint vertex_indices\nend_header\n", POSITIONS.len(), faces.len()).into_bytes();
+        let big_endian = format == "binary_big_endian";
+        let double = |value: f64| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let float = |value: f32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        for (x, y) in POSITIONS {
+            if format == "ascii" {
+                contents.extend(format!("{x} {y} 0 0 0 1 255 128 0 {x} {y}\n").into_bytes());
+                continue;
+            }
+            contents.extend([x, y, 0.0].into_iter().flat_map(double));
+            contents.extend([0.0, 0.0, 1.0].into_iter().flat_map(float));
+            contents.extend([255, 128, 0]);
+            contents.extend([x as f32, y as f32].into_iter().flat_map(float));
+        }
+        for face in faces {
+            if format == "ascii" {
+                let indices = face.iter().map(|index| index.to_string()).collect::<Vec<_>>().join(" ");
+                contents.extend(format!("{} {indices}\n", face.len()).into_bytes());
+                continue;
+            }
+            contents.push(face.len() as u8);
+            for &index in *face {
+                contents.extend(if big_endian { index.to_be_bytes() } else { index.to_le_bytes() });
+            }
+        }
+        let path = std::env::temp_dir().join(format!("raytracer-test-{name}.ply"));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn parse(name: &str, format: &str, faces: &[&[u32]]) -> Result<HittableList, ParseError> {
+        let path = write(name, format, faces);
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let world = parse_ply(&path, material, &ImportOptions::default());
+        fs::remove_file(&path).unwrap();
+        world
+    }
+
+    fn hit(world: &HittableList, x: f64, y: f64) -> HitRecord {
+        let mut data = Data::new(String::new(), 0, IntersectionAlgorithm::Naive, Options::default(), 0, 0, 0, 0);
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Point3::new(x, y, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(world.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rec, &mut data));
+        rec
+    }
+
+    #[test]
+    fn encodings_and_attributes() {
+        for format in ["ascii", "binary_little_endian", "binary_big_endian"] {
+            let mut world = parse(format, format, &FACES).unwrap();
+            world.algorithm = IntersectionAlgorithm::Naive;
+            // The quad and the pentagon are triangulated
+            assert_eq!(world.objects.len(), 2 + 1 + 3, "{format}");
+
+            let rec = hit(&world, 0.25, 0.75);
+            assert!((rec.u - 0.25).abs() < 1e-6 && (rec.v - 0.75).abs() < 1e-6, "{format}: {} {}", rec.u, rec.v);
+            assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-6);
+            let color = rec.color.expect("vertex colors");
+            assert!((color - Color::new(1.0, (128.0f64 / 255.0).powi(2), 0.0)).length() < 1e-6, "{format}");
+
+            let rec = hit(&world, 0.5, 1.25);
+            assert!((rec.v - 1.25).abs() < 1e-6, "{format}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_element() {
+        let Err(error) = parse("out-of-range", "ascii", &[&[0, 1, 2], &[0, 1, 7]]) else { panic!("index out of range") };
+        assert!(matches!(error, ParseError::ParseElement("face", 1, _)), "{error}");
+        assert_eq!(error.to_string(), "Parse error in face 1: Vertex 7 not found");
+        let Err(error) = parse("too-small", "binary_little_endian", &[&[0, 1]]) else { panic!("face with 2 vertices") };
+        assert!(matches!(error, ParseError::ParseElement("face", 0, _)), "{error}");
+    }
+
+    fn bounds(world: &HittableList) -> AABB {
+        world.objects.iter().map(|object| object.to_aabb()).reduce(|a, b| a + b).unwrap()
+    }
+
+    #[test]
+    fn import_options() {
+        let path = write("import", "ascii", &FACES);
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let options = ImportOptions { scale: 3.0, up_axis: UpAxis::Z, recenter: true, fit_size: Some(2.0), transform: None };
+        let mut world = parse_ply(&path, material, &options).unwrap();
+        fs::remove_file(&path).unwrap();
+        world.algorithm = IntersectionAlgorithm::Naive;
+
+        // The mesh lies flat after the conversion from Z-up, the largest side (1.5) is scaled to 2 and then by 3
+        let bounds = bounds(&world);
+        assert!((bounds.min - Point3::new(-2.0, 0.0, -3.0)).length() < 1e-9, "{:?}", bounds.min);
+        assert!((bounds.max - Point3::new(2.0, 0.0, 3.0)).length() < 1e-9, "{:?}", bounds.max);
+
+        // The normals are rotated as well
+        let mut data = Data::new(String::new(), 0, IntersectionAlgorithm::Naive, Options::default(), 0, 0, 0, 0);
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(Point3::new(0.0, 10.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(world.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rec, &mut data));
+        assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn scene_file_meshes() {
+        let path = write("scene-mesh", "binary_little_endian", &FACES);
+        let scene = format!(r#"{{"objects": [], "meshes": [{{"path": {path:?}, "format": "ply", "import": {{"scale": 2.0}}}}]}}"#);
+        let mut world: HittableList = serde_json::from_str(&scene).unwrap();
+        world.load_meshes(Path::new("")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(world.objects.len(), 6);
+        assert!((bounds(&world).max - Point3::new(2.0, 3.0, 0.0)).length() < 1e-9);
+    }
+}
+
+mod stl {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::utils::mesh::ImportOptions;
+    use crate::utils::parser::ParseError;
+    use crate::utils::rtweekend::{IntersectionAlgorithm, Options};
+    use crate::utils::stl::parse_stl;
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn load(path: &PathBuf) -> Result<HittableList, ParseError> {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut world = parse_stl(path, material, &ImportOptions::default())?;
+        world.algorithm = IntersectionAlgorithm::Naive;
+        Ok(world)
+    }
+
+    fn parse(name: &str, contents: &[u8]) -> Result<HittableList, ParseError> {
+        let path = std::env::temp_dir().join(format!("raytracer-test-{name}.stl"));
+        fs::write(&path, contents).unwrap();
+        let world = load(&path);
+        fs::remove_file(&path).unwrap();
+        world
+    }
+
+    fn hit(world: &HittableList, origin: Point3, direction: Vec3) -> HitRecord {
+        let mut data = Data::new(String::new(), 0, IntersectionAlgorithm::Naive, Options::default(), 0, 0, 0, 0);
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&Ray::new(origin, direction), Interval::new(0.0, f64::INFINITY), &mut rec, &mut data));
+        rec
+    }
+
+    fn albedo(rec: &HitRecord) -> Color {
+        let (mut attenuation, mut scattered) = (Color::default(), Ray::default());
+        rec.mat.as_ref().unwrap().scatter(&Ray::default(), rec, &mut attenuation, &mut scattered);
+        attenuation
+    }
+
+    /// A unit triangle in ascii, `normal` is the facet normal written to the file
+    fn ascii_facet(normal: &str, z: f64) -> String {
+        format!("  facet normal {normal}\n    outer loop\n      vertex 0 0 {z}\n      vertex 1 0 {z}\n      vertex 0 1 {z}\n    endloop\n  endfacet\n")
+    }
+
+    #[test]
+    fn binary_scene() {
+        let world = load(&PathBuf::from("scenes/stl/parts.stl")).unwrap();
+        // Ground, sphere and cube
+        assert_eq!(world.objects.len(), 2 + 512 + 12);
+
+        // The facet colors are materials, the ground has no color and gets the default material
+        let center = Point3::new(-0.6, -0.1, -1.6);
+        let sphere = hit(&world, Point3::default(), center - Point3::default());
+        let color = albedo(&sphere);
+        assert!(color.x() > 0.9 && color.y() < 0.05 && color.z() < 0.05, "{color:?}");
+        let ground = hit(&world, Point3::new(0.0, 0.0, -3.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!((albedo(&ground) - Color::new(0.5, 0.5, 0.5)).length() < 1e-9);
+    }
+
+    #[test]
+    fn smooth_normals() {
+        let world = load(&PathBuf::from("scenes/stl/parts.stl")).unwrap();
+        // The welded sphere gets smooth normals, almost the same as the normal of a real sphere
+        let center = Point3::new(-0.6, -0.1, -1.6);
+        let origin = Point3::new(-0.3, 0.5, 0.0);
+        let rec = hit(&world, origin, center - origin);
+        let radial = (rec.p - center).unit();
+        assert!(rec.normal.dot(&radial) > 0.999, "{:?} {radial:?}", rec.normal);
+
+        // The edges of the cube are sharper than the crease angle, so the faces stay flat
+        let rec = hit(&world, Point3::new(0.6, 2.0, -1.6), Vec3::new(0.0, -1.0, 0.0));
+        assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9, "{:?}", rec.normal);
+    }
+
+    #[test]
+    fn ascii_and_normals() {
+        // The second facet has a normal pointing the wrong way, the winding is used
+        let contents = format!("solid test\n{}{}endsolid test\n", ascii_facet("0 0 1", 0.0), ascii_facet("0 0 -1", -1.0));
+        let world = parse("ascii", contents.as_bytes()).unwrap();
+        assert_eq!(world.objects.len(), 2);
+        let rec = hit(&world, Point3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(rec.front_face && (rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn magics_colors() {
+        let mut contents = b"COLOR=\x10\x20\xff\xff".to_vec();
+        contents.resize(80, b' ');
+        contents.extend(2u32.to_le_bytes());
+        for (z, attribute) in [(0.0f32, 0x8000u16), (-1.0, 31)] {
+            contents.extend([0.0f32, 0.0, 1.0, 0.0, 0.0, z, 1.0, 0.0, z, 0.0, 1.0, z].iter().flat_map(|value| value.to_le_bytes()));
+            contents.extend(attribute.to_le_bytes());
+        }
+        let world = parse("magics", &contents).unwrap();
+
+        // The first facet uses the default color from the header, the second has its own red color
+        let rec = hit(&world, Point3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let [r, g, b] = [0x10, 0x20, 0xff].map(|c| (c as f64 / 255.0).powi(2));
+        assert!((albedo(&rec) - Color::new(r, g, b)).length() < 1e-9);
+        let rec = hit(&world, Point3::new(0.2, 0.2, -0.5), Vec3::new(0.0, 0.0, -1.0));
+        assert!((albedo(&rec) - Color::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn errors() {
+        let contents = "solid test\n  vertex 0 0 0\nendsolid test\n";
+        let Err(error) = parse("outside", contents.as_bytes()) else { panic!("vertex outside a facet") };
+        assert!(matches!(error, ParseError::ParseLine(_, 2, _)), "{error}");
+        let contents = format!("solid test\n{}", ascii_facet("0 0 1", 0.0).replace("vertex 1 0 0", "vertex 1 0"));
+        let Err(error) = parse("values", contents.as_bytes()) else { panic!("vertex with 2 values") };
+        assert!(matches!(error, ParseError::ParseLine(_, 5, _)), "{error}");
+
+        // Binary file with 3 facets, but only 1 in the file
+        let mut contents = vec![b' '; 80];
+        contents.extend(3u32.to_le_bytes());
+        contents.extend([0u8; 50]);
+        let Err(error) = parse("truncated", &contents) else { panic!("missing facets") };
+        assert!(matches!(error, ParseError::ParseElement("facet", 1, _)), "{error}");
+    }
+}
+
+mod pbrt {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::utils::mesh::ImportOptions;
+    use crate::utils::parser::ParseError;
+    use crate::utils::pbrt::parse_pbrt;
+    use crate::utils::rtweekend::{IntersectionAlgorithm, Options};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::light::Light;
+    use crate::value::material::{Lambertian, MaterialType};
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn load(path: &Path) -> Result<HittableList, ParseError> {
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut world = parse_pbrt(path, material, &ImportOptions::default())?;
+        world.algorithm = IntersectionAlgorithm::Naive;
+        Ok(world)
+    }
+
+    fn parse(name: &str, contents: &str) -> Result<HittableList, ParseError> {
+        let path = std::env::temp_dir().join(format!("raytracer-test-{name}.pbrt"));
+        fs::write(&path, contents).unwrap();
+        let world = load(&path);
+        fs::remove_file(&path).unwrap();
+        world
+    }
+
+    fn hit(world: &HittableList, origin: Point3, direction: Vec3) -> HitRecord {
+        let mut data = Data::new(String::new(), 0, IntersectionAlgorithm::Naive, Options::default(), 0, 0, 0, 0);
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&Ray::new(origin, direction), Interval::new(0.0, f64::INFINITY), &mut rec, &mut data));
+        rec
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn scene_contents() {
+        let world = load(&PathBuf::from("scenes/pbrt/scene.pbrt")).unwrap();
+        // Ground, cube (from the included file and its PLY file) and spheres
+        assert_eq!(world.objects.len(), 2 + 12 + 4);
+        assert_eq!(world.lights.len(), 1);
+
+        // pbrt is left-handed, the scene is mirrored in x
+        let glass = hit(&world, Point3::new(1.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_near(glass.p, Point3::new(1.5, 2.0, 0.0));
+        assert_eq!(glass.mat.as_ref().unwrap().get_type(), MaterialType::Dielectric);
+        let gold = hit(&world, Point3::new(-1.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_eq!(gold.mat.as_ref().unwrap().get_type(), MaterialType::Metal);
+        let ground = hit(&world, Point3::new(5.0, 1.0, 5.0), Vec3::new(0.0, -1.0, 0.0));
+        assert_near(ground.p, Point3::new(5.0, 0.0, 5.0));
+        // The rotated cube
+        let cube = hit(&world, Point3::new(0.0, 3.0, 2.5), Vec3::new(0.0, -1.0, 0.0));
+        assert_near(cube.p, Point3::new(0.0, 1.6, 2.5));
+
+        let camera = world.camera.unwrap();
+        assert_near(camera.look_from, Point3::new(0.0, 3.0, -8.0));
+        assert_near((camera.look_at - camera.look_from).unit(), (Point3::new(0.0, 1.0, 0.0) - camera.look_from).unit());
+        assert_eq!(camera.vfov, 40.0);
+        assert_eq!(camera.image_width, Some(480));
+        assert_eq!(camera.aspect_ratio, Some(480.0 / 270.0));
+        assert_eq!(camera.samples_per_pixel, Some(8));
+        let Light::Point { position, intensity, .. } = world.lights[0] else { panic!("Expected a point light") };
+        assert_near(position, Point3::new(-2.0, 5.0, -3.0));
+        assert_eq!(intensity, 1.0);
+    }
+
+    #[test]
+    fn transforms_and_attributes() {
+        let world = parse("transforms", r#"
+            AttributeBegin
+                Translate 0 0 5
+                CoordinateSystem "raised"
+                Scale 2 2 2
+                Shape "sphere" "float radius" 0.5
+            AttributeEnd
+            # Back at the identity, column-major like pbrt
+            ConcatTransform [ 1 0 0 0  0 1 0 0  0 0 1 0  0 0 -5 1 ]
+            Shape "sphere"
+            TransformBegin
+                CoordSysTransform "raised"
+                Translate 0 3 0
+                Shape "trianglemesh" "point3 P" [ 0 0 0  1 0 0  0 0 1 ]
+            TransformEnd
+        "#).unwrap();
+        assert_eq!(world.objects.len(), 3);
+        let scaled = hit(&world, Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_near(scaled.p, Point3::new(0.0, 0.0, 4.0));
+        let moved = hit(&world, Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_near(moved.p, Point3::new(0.0, 0.0, -4.0));
+        // x is mirrored
+        let triangle = hit(&world, Point3::new(-0.2, 10.0, 5.2), Vec3::new(0.0, -1.0, 0.0));
+        assert_near(triangle.p, Point3::new(-0.2, 3.0, 5.2));
+    }
+
+    #[test]
+    fn named_materials_and_lights() {
+        let world = parse("materials", r#"
+            MakeNamedMaterial "mirror" "string type" "conductor" "rgb reflectance" [ 0.9 0.9 0.9 ]
+            Material "dielectric"
+            AttributeBegin
+                NamedMaterial "mirror"
+                Shape "sphere"
+            AttributeEnd
+            Translate 0 0 5
+            Shape "sphere"
+            LightSource "spot" "point3 from" [ 0 0 0 ] "point3 to" [ 1 0 0 ] "float coneangle" 30 "float conedelta" 10
+            LightSource "distant" "point3 from" [ 0 1 0 ] "point3 to" [ 0 0 0 ] "rgb L" [ 2 2 2 ]
+            LightSource "infinite"
+        "#).unwrap();
+        let mirror = hit(&world, Point3::new(0.0, 0.0, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(mirror.mat.as_ref().unwrap().get_type(), MaterialType::Metal);
+        let glass = hit(&world, Point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(glass.mat.as_ref().unwrap().get_type(), MaterialType::Dielectric);
+
+        // The infinite light is skipped
+        assert_eq!(world.lights.len(), 2);
+        let Light::Spot { position, direction, inner_cone_angle, outer_cone_angle, .. } = world.lights[0] else { panic!("Expected a spot light") };
+        assert_near(position, Point3::new(0.0, 0.0, 5.0));
+        assert_near(direction, Vec3::new(-1.0, 0.0, 0.0));
+        assert!((inner_cone_angle - 20f64.to_radians()).abs() < 1e-12 && (outer_cone_angle - 30f64.to_radians()).abs() < 1e-12);
+        let Light::Directional { direction, color, .. } = world.lights[1] else { panic!("Expected a directional light") };
+        assert_near(direction, Vec3::new(0.0, -1.0, 0.0));
+        assert_near(color, Color::new(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = |contents: &str| match parse("errors", contents) {
+            Err(ParseError::ParseLine(_, line, message)) => (line, message),
+            Err(error) => panic!("Unexpected error {error}"),
+            Ok(_) => panic!("Expected an error"),
+        };
+        assert_eq!(error("Translate 1 2\n").0, 1);
+        assert_eq!(error("WorldBegin\n\nNamedMaterial \"missing\"\n").0, 3);
+        assert_eq!(error("AttributeEnd").0, 1);
+        assert_eq!(error("Shape \"trianglemesh\"\n  \"point3 P\" [ 0 0 0  1 0 0  0 1 0 ]\n  \"integer indices\" [ 0 1 3 ]").0, 1);
+        let (line, message) = error("Film \"rgb\"\n  \"integer xresolution\" [ 100\n");
+        assert_eq!((line, message.as_str()), (1, "List is not closed"));
+        assert_eq!(error("Shape \"sphere\" \"float radius\" one").0, 1);
+        assert_eq!(error("Include \"raytracer-test-errors.pbrt\"").1, "Files are included more than 32 levels deep");
+    }
+}
+
+mod scene {
+    use clap::Parser;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use crate::acceleration::aabb::AABB;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::utils::parser::ParseError;
+    use crate::utils::rtweekend::{Cli, Command, IntersectionAlgorithm, Options, SceneCommand};
+    use crate::utils::scene_file::{self, SCENE_VERSION};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::{Dielectric, Lambertian, Material, MaterialType};
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+
+    /// Writes the files to a new directory, relative paths in the scenes point into it
+    fn write(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("raytracer-test-{name}"));
+        fs::create_dir_all(directory.join("meshes")).unwrap();
+        fs::copy("scenes/pbrt/cube.ply", directory.join("meshes/cube.ply")).unwrap();
+        for (file, contents) in files {
+            fs::create_dir_all(directory.join(file).parent().unwrap()).unwrap();
+            fs::write(directory.join(file), contents).unwrap();
+        }
+        directory
+    }
+
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<HittableList, ParseError> {
+        let directory = write(name, files);
+        let world = HittableList::load(&directory.join(files[0].0));
+        fs::remove_dir_all(&directory).unwrap();
+        world
+    }
+
+    fn bounds(objects: &[Rc<dyn Hittable>]) -> AABB {
+        objects.iter().map(|object| object.to_aabb()).reduce(|a, b| a + b).unwrap()
+    }
+
+    #[test]
+    fn meshes_materials_and_includes() {
+        let world = load("compose", &[
+            ("scene.json", r#"{
+                "include": ["library/materials.json"],
+                "materials": {"red": {"type": "Metal", "albedo": {"e": [0.8, 0.1, 0.1]}, "fuzz": 0.0}},
+                "objects": [{"type": "Sphere", "center": {"e": [0, 5, 0]}, "radius": 1, "mat": {"type": "Dielectric", "refraction_index": 1.5}}],
+                "meshes": [
+                    {"path": "meshes/cube.ply", "material": "glass", "transform": [{"translate": [0, 1, 0]}]},
+                    {"path": "meshes/cube.ply", "material": "red", "import": {"scale": 0.5}, "transform": [{"translate": [3, 0, 0]}]},
+                    {"path": "meshes/cube.ply", "format": "ply", "material": {"type": "Lambertian", "albedo": {"e": [0.5, 0.5, 0.5]}}}
+                ]
+            }"#),
+            ("library/materials.json", r#"{"include": ["../lights.json"], "materials": {
+                "glass": {"type": "Dielectric", "refraction_index": 1.5},
+                "red": {"type": "Lambertian", "albedo": {"e": [0.8, 0.1, 0.1]}}
+            }}"#),
+            ("lights.json", r#"{"lights": [{"type": "point", "position": {"e": [0, 10, 0]}, "color": {"e": [1, 1, 1]}, "intensity": 100}]}"#),
+        ]).unwrap();
+
+        // Objects of the scene, then the cubes
+        assert_eq!(world.objects.len(), 1 + 3 * 12);
+        assert_eq!(world.lights.len(), 1);
+        let types = world.objects.iter().map(|object| object.material_type().unwrap()).collect::<Vec<_>>();
+        assert_eq!(types[1], MaterialType::Dielectric);
+        // The materials of the including scene take precedence
+        assert_eq!(types[13], MaterialType::Metal);
+        assert_eq!(types[25], MaterialType::Lambertian);
+
+        // The transform is applied after the import options
+        let scaled = bounds(&world.objects[13..25]);
+        assert!((scaled.min - Point3::new(2.5, -0.5, -0.5)).length() < 1e-9, "{:?}", scaled.min);
+        assert!((bounds(&world.objects).max - Point3::new(3.5, 6.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |files: &[(&str, &str)]| match load("errors", files) {
+            Err(error) => error.to_string(),
+            Ok(_) => panic!("Expected an error"),
+        };
+        let undefined = error(&[("scene.json", r#"{"meshes": [{"path": "meshes/cube.ply", "material": "missing"}]}"#)]);
+        assert_eq!(undefined, "Parse error Material \"missing\" is not defined");
+        let format = error(&[("scene.json", r#"{"meshes": [{"path": "meshes/cube.mesh"}]}"#)]);
+        assert!(format.contains("Unknown format"), "{format}");
+        let cycle = error(&[("scene.json", r#"{"include": ["other.json"]}"#), ("other.json", r#"{"include": ["scene.json"]}"#)]);
+        assert_eq!(cycle, "Parse error Scene files are included more than 32 levels deep");
+        let json = error(&[("scene.json", r#"{"objects": [{"type": "Sphere"}]}"#)]);
+        assert!(json.starts_with("Error reading scene file: missing field"), "{json}");
+        assert!(matches!(HittableList::load(Path::new("scenes/missing.json")), Err(ParseError::IO(_))));
+    }
+
+    fn material(world: &HittableList, origin: Point3) -> Rc<dyn Material> {
+        let mut data = Data::new(String::new(), 0, IntersectionAlgorithm::Naive, Options::default(), 0, 0, 0, 0);
+        let mut rec = HitRecord::default();
+        let ray = Ray::new(origin, Vec3::new(0.0, 0.0, -1.0));
+        assert!(world.hit(&ray, Interval::new(0.0, f64::INFINITY), &mut rec, &mut data));
+        rec.mat.unwrap()
+    }
+
+    #[test]
+    fn shared_materials() {
+        let red: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1)));
+        let glass: Rc<dyn Material> = Rc::new(Dielectric::new(1.5));
+        let mut world = HittableList::default();
+        world.add(Rc::new(Sphere::new(Point3::new(0.0, 0.0, -5.0), 1.0, red.clone())));
+        world.add(Rc::new(Sphere::new(Point3::new(3.0, 0.0, -5.0), 1.0, red.clone())));
+        world.add(Rc::new(Triangle::new(Point3::new(5.0, -1.0, -5.0), Point3::new(7.0, -1.0, -5.0), Point3::new(6.0, 1.0, -5.0), glass)));
+        // An equal material is written once as well
+        world.add(Rc::new(Sphere::new(Point3::new(9.0, 0.0, -5.0), 1.0, Rc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1))))));
+
+        let json = serde_json::to_string(&world).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["materials"].as_object().unwrap().keys().collect::<Vec<_>>(), ["lambertian-0", "dielectric-1"]);
+        let references = value["objects"].as_array().unwrap().iter().map(|object| object["mat"].as_u64().unwrap()).collect::<Vec<_>>();
+        assert_eq!(references, [0, 0, 1, 0]);
+
+        // Loading keeps the sharing, and writing the scene again gives the same file
+        let mut loaded: HittableList = serde_json::from_str(&json).unwrap();
+        loaded.algorithm = IntersectionAlgorithm::Naive;
+        let [a, b, c, d] = [0.0, 3.0, 6.0, 9.0].map(|x| material(&loaded, Point3::new(x, 0.0, 0.0)));
+        assert!(Rc::ptr_eq(&a, &b) && Rc::ptr_eq(&a, &d) && !Rc::ptr_eq(&a, &c));
+        assert_eq!(c.get_type(), MaterialType::Dielectric);
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), json);
+    }
+
+    #[test]
+    fn material_references() {
+        // By name, by index and inline, the materials can come after the objects
+        let world: HittableList = serde_json::from_str(r#"{
+            "objects": [
+                {"type": "Sphere", "center": {"e": [0, 0, -5]}, "radius": 1, "mat": "metal"},
+                {"type": "Sphere", "center": {"e": [3, 0, -5]}, "radius": 1, "mat": 1},
+                {"type": "Sphere", "center": {"e": [6, 0, -5]}, "radius": 1, "mat": {"type": "Dielectric", "refraction_index": 1.5}},
+                {"type": "HittableList", "objects": [{"type": "Sphere", "center": {"e": [9, 0, -5]}, "radius": 1, "mat": "metal"}]}
+            ],
+            "materials": {"metal": {"type": "Metal", "albedo": {"e": [0.9, 0.9, 0.9]}, "fuzz": 0.0}, "red": {"type": "Lambertian", "albedo": {"e": [0.8, 0.1, 0.1]}}}
+        }"#).unwrap();
+        let mut world = world;
+        world.algorithm = IntersectionAlgorithm::Naive;
+        let types = [0.0, 3.0, 6.0].map(|x| material(&world, Point3::new(x, 0.0, 0.0)).get_type());
+        assert_eq!(types, [MaterialType::Metal, MaterialType::Lambertian, MaterialType::Dielectric]);
+        // The nested list uses the materials of the scene
+        assert!(Rc::ptr_eq(&world.materials.get("metal").unwrap(), &material(&world, Point3::new(9.0, 0.0, 0.0))));
+
+        let error = |json: &str| match serde_json::from_str::<HittableList>(json) {
+            Err(error) => error.to_string(),
+            Ok(_) => panic!("Expected an error"),
+        };
+        assert_eq!(error(r#"{"objects": [{"type": "Sphere", "center": {"e": [0, 0, 0]}, "radius": 1, "mat": "gold"}]}"#),
+                   "Material \"gold\" is not defined");
+        assert_eq!(error(r#"{"objects": [{"type": "Sphere", "center": {"e": [0, 0, 0]}, "radius": 1, "mat": 2}]}"#),
+                   "Material 2 is not defined, there are 0 materials");
+    }
+
+    #[test]
+    fn inline_materials() {
+        // Scenes written before the material table still load, writing them again stores each material once
+        let world = HittableList::load(Path::new("scenes/triangle_materials.json")).unwrap();
+        let inline = fs::read_to_string("scenes/triangle_materials.json").unwrap().matches(r#""mat":{"#).count();
+        let value = serde_json::to_value(&world).unwrap();
+        assert_eq!(value["objects"].as_array().unwrap().len(), world.objects.len());
+        assert!(value["materials"].as_object().unwrap().len() < inline);
+        let loaded: HittableList = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), value);
+    }
+    #[test]
+    fn migrations() {
+        let triangle = r#"{"type": "Triangle", "v0": {"e": [0, 0, -5]}, "v1": {"e": [2, 0, -5]}, "v2": {"e": [1, 2, -5]},
+            "mat": {"type": "Lambertian", "albedo": {"e": [0.5, 0.5, 0.5]}}, "centroid": {"e": [1, 0.6666, -5]}}"#;
+        let mut scene: serde_json::Value = serde_json::from_str(&format!(r#"{{"objects": [{triangle}, {{"type": "HittableList", "objects": [{triangle}]}}]}}"#)).unwrap();
+        assert_eq!(scene_file::migrate(&mut scene), Ok(1));
+        assert_eq!(scene["version"], SCENE_VERSION);
+        assert!(!scene.to_string().contains("centroid"));
+        // Migrating again changes nothing
+        let migrated = scene.clone();
+        assert_eq!(scene_file::migrate(&mut scene), Ok(SCENE_VERSION));
+        assert_eq!(scene, migrated);
+
+        let error = |json: &str| match serde_json::from_str::<HittableList>(json) {
+            Err(error) => error.to_string(),
+            Ok(_) => panic!("Expected an error"),
+        };
+        assert_eq!(error(&format!(r#"{{"version": {}, "objects": []}}"#, SCENE_VERSION + 1)),
+                   format!("Scene version {} is newer than the supported version {SCENE_VERSION}", SCENE_VERSION + 1));
+        assert_eq!(error(r#"{"version": 0}"#), "Scene version should be a positive integer, found 0");
+    }
+
+    #[test]
+    fn upgrade() {
+        let directory = write("upgrade", &[]);
+        let path = directory.join("scene.json");
+        fs::copy("scenes/triangle_materials.json", &path).unwrap();
+        assert_eq!(scene_file::upgrade(&path).unwrap(), 1);
+        let upgraded = fs::read_to_string(&path).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&upgraded).unwrap();
+        assert_eq!(value["version"], SCENE_VERSION);
+        assert!(!upgraded.contains("centroid"));
+
+        // The file is only rewritten once, and holds the same scene as before
+        assert_eq!(scene_file::upgrade(&path).unwrap(), SCENE_VERSION);
+        assert_eq!(fs::read_to_string(&path).unwrap(), upgraded);
+        let before = HittableList::load(Path::new("scenes/triangle_materials.json")).unwrap();
+        let after = HittableList::load(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(serde_json::to_value(&before).unwrap(), serde_json::to_value(&after).unwrap());
+
+        // Materials of an included file can be used by name, the include is kept
+        let directory = write("upgrade-include", &[
+            ("library.json", r#"{"materials": {"jade": {"type": "Lambertian", "albedo": {"e": [0.1, 0.45, 0.2]}}}}"#),
+            ("scene.json", r#"{"include": ["library.json"], "objects": [{"type": "Triangle", "v0": {"e": [0, 0, -5]},
+                "v1": {"e": [2, 0, -5]}, "v2": {"e": [1, 2, -5]}, "mat": "jade", "centroid": {"e": [1, 0.6666, -5]}}]}"#),
+        ]);
+        let path = directory.join("scene.json");
+        assert_eq!(scene_file::upgrade(&path).unwrap(), 1);
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!((&value["include"][0], &value["objects"][0]["mat"]), (&"library.json".into(), &"jade".into()));
+        let world = HittableList::load(&path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(world.objects[0].material_type(), Some(MaterialType::Lambertian));
+    }
+
+    #[test]
+    fn schema_and_command() {
+        let schema: serde_json::Value = serde_json::from_str(&fs::read_to_string("scenes/scene.schema.json").unwrap()).unwrap();
+        assert_eq!(schema["properties"]["version"]["const"], SCENE_VERSION);
+
+        let cli = Cli::try_parse_from(["raytracer", "scene", "upgrade", "a.json", "b.json"]).unwrap();
+        assert_eq!(cli.command, Some(Command::Scene(SceneCommand::Upgrade { files: vec!["a.json".into(), "b.json".into()] })));
+        assert!(Cli::try_parse_from(["raytracer", "scene", "upgrade"]).is_err());
+        // Rendering a scene still works without a command
+        let cli = Cli::try_parse_from(["raytracer", "scenes/csg.json"]).unwrap();
+        assert_eq!((cli.filename.as_deref(), cli.command), (Some("scenes/csg.json"), None));
+    }
+}
+
+mod binary {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use crate::camera::CameraSettings;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::triangle::Triangle;
+    use crate::utils::binary::{load_binary, save_binary};
+    use crate::utils::mesh::{load_mesh, ImportOptions};
+    use crate::utils::rtweekend::FileFormat;
+    use crate::value::color::Color;
+    use crate::value::light::Light;
+    use crate::value::material::{Dielectric, Lambertian, Material, Metal};
+    use crate::value::vec3::{Point3, Vec3};
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-test-{name}.rtb"))
+    }
+
+    fn round_trip(world: &HittableList, name: &str) -> (HittableList, u64) {
+        let path = temp(name);
+        save_binary(world, &path).unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+        let loaded = load_binary(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        (loaded, size)
+    }
+
+    /// The objects with their materials inline, so the order of the material table does not matter
+    fn objects(world: &HittableList) -> Vec<serde_json::Value> {
+        world.objects.iter().map(|object| serde_json::to_value(object).unwrap()).collect()
+    }
+
+    #[test]
+    fn scene_round_trip() {
+        let red: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1)));
+        let metal: Rc<dyn Material> = Rc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.1));
+        let [a, b, c, d] = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), Point3::new(1.0, 1.0, 0.0)];
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let mut world = HittableList::default();
+        world.add(Rc::new(Sphere::new(Point3::new(0.0, 0.0, -5.0), 1.0, Rc::new(Dielectric::new(1.5)))));
+        world.add(Rc::new(Triangle::new(a, b, c, red.clone())));
+        world.add(Rc::new(Triangle::new(b, d, c, red.clone())));
+        world.add(Rc::new(Triangle::new(a, b, c, metal.clone()).with_normals([up; 3]).with_uvs([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)])));
+        world.add(Rc::new(Triangle::new(b, d, c, metal).with_normals([up; 3]).with_uvs([(1.0, 0.0), (1.0, 1.0), (0.0, 1.0)])
+            .with_colors([Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0)])));
+        world.camera = Some(serde_json::from_str::<CameraSettings>(r#"{"look_from": {"e": [0, 0, 5]}, "look_at": {"e": [0, 0, 0]}, "vfov": 40}"#).unwrap());
+        world.lights.push(Light::Point { position: Point3::new(0.0, 5.0, 0.0), color: Color::new(1.0, 1.0, 1.0), intensity: 50.0 });
+
+        // The objects that are not triangles come first, then the triangles by material and attributes
+        let (loaded, _) = round_trip(&world, "round-trip");
+        assert_eq!(objects(&loaded), objects(&world));
+        assert_eq!(serde_json::to_value(&loaded.camera).unwrap(), serde_json::to_value(&world.camera).unwrap());
+        assert_eq!(serde_json::to_value(&loaded.lights).unwrap(), serde_json::to_value(&world.lights).unwrap());
+        let first = loaded.objects[1].as_triangle().unwrap();
+        let second = loaded.objects[2].as_triangle().unwrap();
+        assert!(Rc::ptr_eq(first.material(), second.material()));
+    }
+
+    #[test]
+    fn meshes_are_smaller() {
+        // The vertices of the dragon are shared between its triangles again
+        let dragon = load_mesh(&"scenes/dragon_recon/dragon_vrip_res4.ply".into(), FileFormat::PLY, None, &ImportOptions::default()).unwrap();
+        let json = serde_json::to_vec(&dragon).unwrap().len() as u64;
+        let (loaded, size) = round_trip(&dragon, "dragon");
+        assert!(size * 5 < json, "{size} bytes, json has {json}");
+        assert_eq!(loaded.objects.len(), dragon.objects.len());
+        assert_eq!(objects(&loaded), objects(&dragon));
+    }
+
+    #[test]
+    fn as_many_objects_as_json() {
+        let dragon = load_mesh(&"scenes/dragon_recon/dragon_vrip_res4.ply".into(), FileFormat::PLY, None, &ImportOptions::default()).unwrap();
+        let json = std::env::temp_dir().join("raytracer-test-dragon.json");
+        let binary = temp("dragon-load");
+        fs::write(&json, serde_json::to_vec(&dragon).unwrap()).unwrap();
+        save_binary(&dragon, &binary).unwrap();
+
+        let from_json = HittableList::load(&json).unwrap();
+        let from_binary = load_binary(&binary).unwrap();
+        fs::remove_file(&json).unwrap();
+        fs::remove_file(&binary).unwrap();
+        assert_eq!(from_binary.objects.len(), from_json.objects.len());
+    }
+
+    #[test]
+    fn errors() {
+        let path = temp("errors");
+        let error = |contents: &[u8]| {
+            fs::write(&path, contents).unwrap();
+            let error = load_binary(&path).err().unwrap().to_string();
+            fs::remove_file(&path).unwrap();
+            error
+        };
+        assert!(error(b"{\"objects\": []}").contains("is not a binary scene file"));
+
+        let mut world = HittableList::default();
+        let gray: Rc<dyn Material> = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        world.add(Rc::new(Triangle::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), gray)));
+        save_binary(&world, &path).unwrap();
+        let contents = fs::read(&path).unwrap();
+        assert!(error(&contents[..contents.len() - 4]).starts_with("Parse error Binary scene file is truncated"));
+        let mut invalid = contents.clone();
+        let last = invalid.len() - 4;
+        invalid[last..].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(error(&invalid), "Parse error in triangle 0: Vertex 7 does not exist in mesh 0, it has 3 vertices");
+
+        assert!(matches!(FileFormat::from_path(Path::new("scene.rtb")), Some(FileFormat::Binary)));
+        assert!(load_mesh(&"scene.rtb".into(), FileFormat::Binary, None, &ImportOptions::default()).is_err());
+    }
+}
+
+mod bvh {
+    use std::rc::Rc;
+    use std::time::Duration;
+    use crate::acceleration::aabb::AABB;
+    use crate::acceleration::bvh::{Bvh, BvhNode};
+    use crate::acceleration::wide_bvh::WideBvh;
+    use crate::hittable::hittable_list::HittableList;
+    use crate::hittable::plane::Plane;
+    use crate::hittable::sphere::Sphere;
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::value::color::Color;
+    use crate::value::data::Data;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::transform::Transform;
+    use crate::value::vec3::{Point3, Vec3};
+    use crate::utils::mesh::{load_mesh, ImportOptions};
+    use crate::utils::rtweekend::{AlgorithmOptions, FileFormat, Options};
+
+    pub(super) fn dragon() -> Vec<Rc<dyn Hittable>> {
+        let options = ImportOptions { scale: 100.0, ..Default::default() };
+        load_mesh(&"scenes/dragon_recon/dragon_vrip_res4.ply".into(), FileFormat::PLY, None, &options).unwrap().objects
+    }
+
+    pub(super) fn build(objects: Vec<Rc<dyn Hittable>>, option: AlgorithmOptions) -> Bvh {
+        Bvh::new(objects, &Options { options: vec![option], ..Default::default() })
+    }
+
+    fn encloses(outer: &AABB, inner: &AABB) -> bool {
+        outer.contains(inner.min) && outer.contains(inner.max)
+    }
+
+    fn overlaps(a: &AABB, b: &AABB) -> bool {
+        (0..3).all(|axis| a.min[axis] <= b.max[axis] && b.min[axis] <= a.max[axis])
+    }
+
+    /// Checks that the children are inside their parent and that the nodes are depth first
+    /// With spatial splits (`clipped`) a leaf only has to overlap its objects
+    /// Returns the number of objects in the leaves and the number of nodes in the subtree
+    fn check(bvh: &Bvh, index: usize, clipped: bool) -> (usize, usize) {
+        let node = &bvh.nodes()[index];
+        if node.is_leaf() {
+            for object in bvh.objects(node) {
+                let aabb = object.to_aabb();
+                assert!(if clipped { overlaps(&node.aabb(), &aabb) } else { encloses(&node.aabb(), &aabb) });
+            }
+            return (node.count(), 1);
+        }
+        let (left, right) = (&bvh.nodes()[index + 1], &bvh.nodes()[node.right()]);
+        assert!(encloses(&node.aabb(), &left.aabb()) && encloses(&node.aabb(), &right.aabb()));
+        let (left_objects, left_nodes) = check(bvh, index + 1, clipped);
+        // The right subtree starts after the left one
+        assert_eq!(node.right(), index + 1 + left_nodes);
+        let (right_objects, right_nodes) = check(bvh, node.right(), clipped);
+        (left_objects + right_objects, 1 + left_nodes + right_nodes)
+    }
+
+    #[test]
+    fn binned_sah() {
+        let objects = dragon();
+        let full = build(objects.clone(), AlgorithmOptions::BvhSahPosition);
+        assert_eq!(check(&full, 0, false), (objects.len(), full.nodes().len()));
+        for option in [AlgorithmOptions::BvhSahBinned16, AlgorithmOptions::BvhSahBinned32] {
+            let binned = build(objects.clone(), option);
+            assert_eq!(check(&binned, 0, false), (objects.len(), binned.nodes().len()));
+            // Close to the full SAH
+            assert!(binned.sah_cost() < full.sah_cost() * 1.1);
+        }
+        let naive = build(objects.clone(), AlgorithmOptions::BvhNaive);
+        assert!(naive.sah_cost() > full.sah_cost());
+    }
+
+    /// Checks that both trees have the same nodes in the same order, with the same objects
+    fn same_tree(a: &Bvh, b: &Bvh) {
+        assert_eq!(a.nodes(), b.nodes());
+        for node in a.nodes().iter().filter(|node| node.is_leaf()) {
+            assert!(a.objects(node).iter().zip(b.objects(node)).all(|(a, b)| Rc::ptr_eq(a, b)));
+        }
+    }
+
+    #[test]
+    fn parallel_build() {
+        let objects = dragon();
+        for option in [AlgorithmOptions::BvhNaive, AlgorithmOptions::BvhSahPlane, AlgorithmOptions::BvhSahBinned16, AlgorithmOptions::BvhSbvh, AlgorithmOptions::BvhHlbvh] {
+            let build = |threads| {
+                let options = Options { options: vec![option], threads: Some(threads), ..Default::default() };
+                Bvh::new(objects.clone(), &options)
+            };
+            same_tree(&build(1), &build(4));
+        }
+    }
+
+    #[test]
+    fn flat_nodes() {
+        assert_eq!(size_of::<BvhNode>(), 32);
+        let objects = dragon();
+        for option in [AlgorithmOptions::BvhNaive, AlgorithmOptions::BvhSahBinned16] {
+            let bvh = build(objects.clone(), option);
+            assert_eq!(check(&bvh, 0, false), (objects.len(), bvh.nodes().len()));
+            // Every intermediate node has two children, there are no unused nodes
+            let leaves = bvh.nodes().iter().filter(|node| node.is_leaf()).count();
+            assert_eq!(bvh.nodes().len(), 2 * leaves - 1);
+        }
+        let empty = build(vec![], AlgorithmOptions::BvhNaive);
+        assert!(empty.nodes().is_empty());
+    }
+
+    /// The closest hit of every object, the naive algorithm
+    fn closest_hit(objects: &[Rc<dyn Hittable>], ray: &Ray) -> Option<f64> {
+        let mut rec = HitRecord::default();
+        let mut closest = f64::INFINITY;
+        for object in objects {
+            if object.hit(ray, Interval::new(0.001, closest), &mut rec, &mut Data::default()) {
+                closest = rec.t;
+            }
+        }
+        closest.is_finite().then_some(closest)
+    }
+
+    /// Rays from the camera towards the dragon
+    pub(super) fn rays() -> impl Iterator<Item = Ray> + Clone {
+        let origin = Point3::new(0.0, 15.0, 50.0);
+        (-10..=10).flat_map(move |x| (-10..=10).map(move |y| Ray::new(origin, Point3::new(x as f64, 12.0 + y as f64, 0.0) - origin)))
+    }
+
+    /// Checks that every ray hits the closest object, returns the statistics of the traversal
+    pub(super) fn trace(objects: &[Rc<dyn Hittable>], rays: impl Iterator<Item = Ray>, hit: impl Fn(&Ray, &mut HitRecord, &mut Data) -> bool) -> Data {
+        let mut data = Data::default();
+        let mut hits = 0;
+        for ray in rays {
+            let mut rec = HitRecord::default();
+            let hit = hit(&ray, &mut rec, &mut data);
+            assert_eq!(hit.then_some(rec.t), closest_hit(objects, &ray));
+            hits += hit as usize;
+        }
+        assert!(hits > 0);
+        data
+    }
+
+    #[test]
+    fn traversal() {
+        let objects = dragon();
+        let bvh = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        trace(&objects, rays(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+    }
+
+    /// Checks that the children are inside their parent, returns the number of objects in the leaves
+    fn check_wide<const N: usize>(bvh: &WideBvh<N>, index: usize) -> usize {
+        let node = &bvh.nodes()[index];
+        assert!(node.children() > 0);
+        (0..node.children()).map(|lane| {
+            assert!(encloses(&node.aabb(), &node.child_aabb(lane)));
+            if node.is_leaf(lane) { bvh.objects(node, lane).len() } else { check_wide(bvh, node.child(lane)) }
+        }).sum()
+    }
+
+    fn wide<const N: usize>(objects: &[Rc<dyn Hittable>], binary: &Bvh, binary_data: &Data) {
+        let options = Options { options: vec![AlgorithmOptions::BvhSahBinned16], ..Default::default() };
+        let bvh = WideBvh::<N>::new(objects.to_vec(), &options);
+        assert_eq!(check_wide(&bvh, 0), objects.len());
+        assert!(bvh.nodes().iter().all(|node| node.children() <= N));
+        assert!(bvh.nodes().len() * 2 < binary.nodes().len());
+
+        let data = trace(objects, rays(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+        assert!(data.traversal_steps() < binary_data.traversal_steps());
+        assert!(data.intersection_checks() < binary_data.intersection_checks());
+        assert!(bvh.sah_cost() < binary.sah_cost());
+    }
+
+    #[test]
+    fn wide_bvh() {
+        let objects = dragon();
+        let binary = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        let binary_data = trace(&objects, rays(), |ray, rec, data| binary.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+        wide::<4>(&objects, &binary, &binary_data);
+        wide::<8>(&objects, &binary, &binary_data);
+    }
+
+    #[test]
+    fn lbvh() {
+        let objects = dragon();
+        let naive = build(objects.clone(), AlgorithmOptions::BvhNaive);
+        let [lbvh, hlbvh] = [AlgorithmOptions::BvhLbvh, AlgorithmOptions::BvhHlbvh].map(|option| {
+            let bvh = build(objects.clone(), option);
+            assert_eq!(check(&bvh, 0, false), (objects.len(), bvh.nodes().len()));
+            trace(&objects, rays(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+            bvh
+        });
+        // The SAH at the top makes the tree better
+        assert!(hlbvh.sah_cost() < lbvh.sah_cost());
+        assert!(lbvh.sah_cost() < naive.sah_cost());
+    }
+
+    /// The dragon in the next frame: its front half moved and twisted a bit
+    fn moved(objects: &[Rc<dyn Hittable>]) -> Vec<Rc<dyn Hittable>> {
+        let transform = &Transform::translate(Vec3::new(2.0, 1.0, 0.0)) * &Transform::rotate(Vec3::new(0.0, 1.0, 0.0), 10.0);
+        objects.iter().map(|object| {
+            let triangle = object.as_triangle().unwrap();
+            if triangle.centroid().x() > 0.0 { Rc::new(triangle.transformed(&transform)) as Rc<dyn Hittable> } else { object.clone() }
+        }).collect()
+    }
+
+    #[test]
+    fn refit() {
+        let objects = dragon();
+        let moved = moved(&objects);
+        let mut refitted = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        let mut rotated = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        let rebuilt = build(moved.clone(), AlgorithmOptions::BvhSahBinned16);
+        refitted.refit(&moved, false);
+        rotated.refit(&moved, true);
+
+        for bvh in [&refitted, &rotated] {
+            assert_eq!(check(bvh, 0, false), (objects.len(), bvh.nodes().len()));
+            trace(&moved, rays(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+        }
+        // Refitting keeps the tree of the first frame, which gets worse when objects move
+        assert!(refitted.sah_cost() > rebuilt.sah_cost());
+        assert!(rotated.sah_cost() < refitted.sah_cost());
+        assert_eq!(rotated.nodes().len(), refitted.nodes().len());
+    }
+
+    #[test]
+    fn optimize() {
+        let objects = dragon();
+        let options = Options { options: vec![AlgorithmOptions::BvhNaive, AlgorithmOptions::BvhOptimize], ..Default::default() };
+        let optimized = Bvh::new(objects.clone(), &options);
+        let naive = build(objects.clone(), AlgorithmOptions::BvhNaive);
+        let sah = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        let initial = optimized.unoptimized_sah_cost().unwrap();
+        assert!((initial - naive.sah_cost()).abs() < 0.01 * initial);
+        // Reinserting nodes makes up for the poor splits of the naive BVH
+        assert!(optimized.sah_cost() < 0.1 * naive.sah_cost());
+        assert!(optimized.sah_cost() < 1.1 * sah.sah_cost());
+        assert!(naive.unoptimized_sah_cost().is_none());
+        assert_eq!(check(&optimized, 0, false), (objects.len(), optimized.nodes().len()));
+        trace(&objects, rays(), |ray, rec, data| optimized.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+
+        // The budgets stop the passes early
+        let mut limited = build(objects.clone(), AlgorithmOptions::BvhNaive);
+        assert_eq!(limited.optimize(Some(2), None), 2);
+        assert!(limited.sah_cost() < naive.sah_cost());
+        assert_eq!(limited.unoptimized_sah_cost(), optimized.unoptimized_sah_cost());
+        let mut limited = build(objects.clone(), AlgorithmOptions::BvhNaive);
+        assert_eq!(limited.optimize(None, Some(Duration::ZERO)), 0);
+        assert_eq!(check(&limited, 0, false).0, objects.len());
+
+        // A good tree does not get worse
+        let mut good = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        good.optimize(None, None);
+        assert!(good.sah_cost() <= sah.sah_cost());
+        assert_eq!(check(&good, 0, false).0, objects.len());
+    }
+
+    #[test]
+    fn update() {
+        let objects = dragon();
+        let mut world = HittableList::default();
+        objects.iter().for_each(|object| world.add(object.clone()));
+        world.options = Options { options: vec![AlgorithmOptions::BvhSahBinned16], ..Default::default() };
+        world.init();
+        let mut data = Data::default();
+        for (index, object) in moved(&objects).into_iter().enumerate() {
+            world.set_object(index, object);
+        }
+        world.transform_object(0, &Transform::translate(Vec3::new(0.0, 0.1, 0.0)));
+        world.update(true, &mut data);
+        world.update(false, &mut data);
+        assert_eq!(data.frames(), 2);
+        assert!(data.frame_time() > 0.0);
+        trace(&world.objects, rays(), |ray, rec, data| world.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+
+        // Added objects and objects that are no longer bounded need a new tree
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        world.add(Rc::new(Sphere::new(Point3::new(0.0, 0.0, -20.0), 1.0, material.clone())));
+        world.update(false, &mut data);
+        world.set_object(1, Rc::new(Plane::new(Point3::new(0.0, -20.0, 0.0), Vec3::new(0.0, 1.0, 0.0), material)));
+        world.update(false, &mut data);
+        assert_eq!(data.frames(), 4);
+        assert_eq!(world.objects.len(), objects.len() + 1);
+        let mut rec = HitRecord::default();
+        assert!(world.hit(&Ray::new(Point3::new(100.0, 0.0, 100.0), Vec3::new(0.0, -1.0, 0.0)), Interval::new(0.001, f64::INFINITY), &mut rec, &mut data));
+        assert!((rec.t - 20.0).abs() < 1e-9);
+    }
+
+    /// The dragon on a tilted ground plane of two triangles, their boxes overlap most of the dragon
+    pub(super) fn dragon_on_ground() -> Vec<Rc<dyn Hittable>> {
+        let mut objects = dragon();
+        let bounds = objects.iter().map(|object| object.to_aabb()).reduce(|a, b| a + b).unwrap();
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let (min, max) = (bounds.min - Vec3::new(10.0, 0.0, 10.0), bounds.max + Vec3::new(10.0, 0.0, 10.0));
+        let corner = |x: f64, z: f64| Point3::new(x, min.y() + (x - min.x()) / (max.x() - min.x()) * (max.y() - min.y()) / 2.0, z);
+        let (a, b, c, d) = (corner(min.x(), min.z()), corner(max.x(), min.z()), corner(max.x(), max.z()), corner(min.x(), max.z()));
+        objects.push(Rc::new(Triangle::new(a, b, c, material.clone())));
+        objects.push(Rc::new(Triangle::new(a, c, d, material)));
+        objects
+    }
+
+    /// Builds a BVH with and without spatial splits, returns the SAH cost and the statistics of both
+    fn spatial_splits(objects: &[Rc<dyn Hittable>], rays: impl Iterator<Item = Ray> + Clone) -> [(f64, Data); 2] {
+        [AlgorithmOptions::BvhSahBinned16, AlgorithmOptions::BvhSbvh].map(|option| {
+            let bvh = build(objects.to_vec(), option);
+            let clipped = option == AlgorithmOptions::BvhSbvh;
+            let (references, _) = check(&bvh, 0, clipped);
+            assert!(references <= objects.len() + (objects.len() as f64 * 0.3) as usize);
+            let data = trace(objects, rays.clone(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+            (bvh.sah_cost(), data)
+        })
+    }
+
+    #[test]
+    fn sbvh() {
+        let objects = dragon_on_ground();
+        let [(binned_cost, binned), (sbvh_cost, sbvh)] = spatial_splits(&objects, rays());
+        assert!(sbvh_cost < binned_cost);
+        assert!(sbvh.overlapping_aabb() < binned.overlapping_aabb());
+        assert!(sbvh.intersection_checks() < binned.intersection_checks());
+
+        let [(binned_cost, binned), (sbvh_cost, sbvh)] = spatial_splits(&dragon(), rays());
+        assert!(sbvh_cost <= binned_cost);
+        assert!(sbvh.overlapping_aabb() <= binned.overlapping_aabb());
+    }
+}
+
+mod kd_tree {
+    use std::collections::HashSet;
+    use std::rc::Rc;
+    use super::bvh::{build, dragon, dragon_on_ground, rays, trace};
+    use crate::acceleration::aabb::AABB;
+    use crate::acceleration::kd_tree::{KdNode, KdTree, MAX_DEPTH};
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::Hittable;
+    use crate::value::color::Color;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+    use crate::utils::rtweekend::{AlgorithmOptions, Options};
+
+    fn build_kd_tree(objects: Vec<Rc<dyn Hittable>>, empty_bonus: Option<f64>) -> KdTree {
+        KdTree::new(objects, &Options { empty_bonus, ..Default::default() })
+    }
+
+    /// Checks that the objects of every leaf overlap its cell and that every object is in a leaf
+    /// Returns the number of empty leaves
+    fn check(kd_tree: &KdTree, objects: &[Rc<dyn Hittable>]) -> usize {
+        let mut found = HashSet::new();
+        let mut empty = 0;
+        let mut stack = vec![(0, kd_tree.aabb().clone(), 0)];
+        while let Some((index, cell, depth)) = stack.pop() {
+            assert!(depth <= MAX_DEPTH);
+            let node = &kd_tree.nodes()[index];
+            match *node {
+                KdNode::Split { axis, position, right } => {
+                    assert!(cell.min[axis] < position && position < cell.max[axis]);
+                    assert!(index + 1 < right as usize);
+                    let (mut left_cell, mut right_cell) = (cell.clone(), cell);
+                    left_cell.max[axis] = position;
+                    right_cell.min[axis] = position;
+                    stack.push((index + 1, left_cell, depth + 1));
+                    stack.push((right as usize, right_cell, depth + 1));
+                }
+                KdNode::Leaf { count, .. } => {
+                    empty += (count == 0) as usize;
+                    for object in kd_tree.objects(node) {
+                        assert!(touches(&cell, &object.to_aabb()));
+                        found.insert(Rc::as_ptr(object) as *const () as usize);
+                    }
+                }
+            }
+        }
+        assert_eq!(found.len(), objects.len());
+        empty
+    }
+
+    fn touches(a: &AABB, b: &AABB) -> bool {
+        (0..3).all(|axis| a.min[axis] <= b.max[axis] && b.min[axis] <= a.max[axis])
+    }
+
+    #[test]
+    fn kd_tree() {
+        let objects = dragon();
+        let kd_tree = build_kd_tree(objects.clone(), None);
+        check(&kd_tree, &objects);
+        assert!(kd_tree.references() >= objects.len());
+        let data = trace(&objects, rays(), |ray, rec, data| kd_tree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+        let bvh = build(objects.clone(), AlgorithmOptions::BvhSahBinned16);
+        let bvh_data = trace(&objects, rays(), |ray, rec, data| bvh.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data, &Options::default()));
+        // The cells do not overlap, so fewer objects are tested
+        assert!(data.intersection_checks() < bvh_data.intersection_checks());
+
+        let empty = build_kd_tree(vec![], None);
+        assert!(empty.nodes().is_empty());
+        assert_eq!(empty.sah_cost(), 0.0);
+    }
+
+    #[test]
+    fn empty_space() {
+        let objects = dragon_on_ground();
+        let trees = [0.0, 0.5].map(|bonus| build_kd_tree(objects.clone(), Some(bonus)));
+        let empty = trees.each_ref().map(|kd_tree| check(kd_tree, &objects));
+        // A larger bonus cuts off more empty space
+        assert!(empty[1] > empty[0]);
+        for kd_tree in &trees {
+            trace(&objects, rays(), |ray, rec, data| kd_tree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+        }
+    }
+
+    #[test]
+    fn flat() {
+        // Triangles in the plane y = 0, all their events are planar on the y axis
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let objects = (0..10).flat_map(|x| (0..10).map(move |z| (x as f64, z as f64)))
+            .map(|(x, z)| Rc::new(Triangle::new(Point3::new(x, 0.0, z), Point3::new(x, 0.0, z + 1.0), Point3::new(x + 1.0, 0.0, z), material.clone())) as Rc<dyn Hittable>)
+            .collect::<Vec<_>>();
+        let kd_tree = build_kd_tree(objects.clone(), None);
+        check(&kd_tree, &objects);
+        let rays = (0..10).flat_map(|x| (0..10).map(move |z| Ray::new(Point3::new(x as f64 + 0.1, 5.0, z as f64 + 0.1), Vec3::new(0.1, -1.0, 0.05))));
+        trace(&objects, rays, |ray, rec, data| kd_tree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+    }
+}
+
+mod octree {
+    use std::collections::HashSet;
+    use std::rc::Rc;
+    use super::bvh::{dragon, dragon_on_ground, rays, trace};
+    use crate::acceleration::aabb::AABB;
+    use crate::acceleration::octree::{Octree, OctreeNode, OctreeStats, MAX_DEPTH, MAX_OBJECTS};
+    use crate::hittable::triangle::Triangle;
+    use crate::hittable::Hittable;
+    use crate::value::color::Color;
+    use crate::value::interval::Interval;
+    use crate::value::material::Lambertian;
+    use crate::value::ray::Ray;
+    use crate::value::vec3::{Point3, Vec3};
+    use crate::utils::rtweekend::Options;
+
+    fn build(objects: Vec<Rc<dyn Hittable>>, options: &Options) -> Octree {
+        Octree::new(objects, options)
+    }
+
+    /// Checks that the children of every cell are its octants, that the objects of every leaf overlap its cell and that
+    /// every object is in a leaf. Returns the statistics counted from the nodes.
+    fn check(octree: &Octree, objects: &[Rc<dyn Hittable>]) -> OctreeStats {
+        let mut found = HashSet::new();
+        let mut stats = OctreeStats::default();
+        let mut stack = vec![(0, octree.aabb().clone(), 0)];
+        while let Some((index, cell, depth)) = stack.pop() {
+            let node = &octree.nodes()[index];
+            stats.cells += 1;
+            match *node {
+                OctreeNode::Cell { first } => {
+                    let center = (cell.min + cell.max) / 2.0;
+                    for child in 0..8 {
+                        let corner = Point3::new(
+                            if child & 4 == 0 { cell.min.x() } else { cell.max.x() },
+                            if child & 2 == 0 { cell.min.y() } else { cell.max.y() },
+                            if child & 1 == 0 { cell.min.z() } else { cell.max.z() },
+                        );
+                        let octant = AABB::from_points(&[center, corner]);
+                        stack.push((first as usize + child, octant, depth + 1));
+                    }
+                }
+                OctreeNode::Leaf { count, .. } => {
+                    stats.leaves += 1;
+                    stats.empty_leaves += (count == 0) as usize;
+                    stats.depth = stats.depth.max(depth);
+                    stats.references += count as usize;
+                    stats.max_objects = stats.max_objects.max(count as usize);
+                    for object in octree.objects(node) {
+                        let aabb = object.to_aabb();
+                        assert!((0..3).all(|axis| cell.min[axis] <= aabb.max[axis] && aabb.min[axis] <= cell.max[axis]));
+                        found.insert(Rc::as_ptr(object) as *const () as usize);
+                    }
+                }
+            }
+        }
+        assert_eq!(found.len(), objects.len());
+        assert_eq!(&stats, octree.stats());
+        stats
+    }
+
+    #[test]
+    fn octree() {
+        let objects = dragon();
+        let octree = build(objects.clone(), &Options::default());
+        let stats = check(&octree, &objects);
+        assert!(stats.depth <= MAX_DEPTH);
+        assert!(stats.empty_leaves > 0);
+        assert_eq!(stats.cells, 8 * (stats.cells - stats.leaves) + 1);
+        trace(&objects, rays(), |ray, rec, data| octree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+
+        // Larger leaves need fewer cells
+        let coarse = build(objects.clone(), &Options { octree_objects: Some(32), ..Default::default() });
+        let coarse_stats = check(&coarse, &objects);
+        assert!(coarse_stats.leaves < stats.leaves);
+        assert!(coarse_stats.max_objects <= 32 || coarse_stats.depth == MAX_DEPTH);
+        trace(&objects, rays(), |ray, rec, data| coarse.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+
+        let shallow = build(objects.clone(), &Options { octree_depth: Some(0), ..Default::default() });
+        assert_eq!(check(&shallow, &objects).max_objects, objects.len());
+
+        let empty = build(vec![], &Options::default());
+        assert!(empty.nodes().is_empty());
+    }
+
+    #[test]
+    fn large_objects() {
+        // The ground is in almost every cell, so cells with the ground and a few triangles of the dragon are split
+        let objects = dragon_on_ground();
+        let octree = build(objects.clone(), &Options::default());
+        let stats = check(&octree, &objects);
+        assert!(stats.max_objects <= MAX_OBJECTS || stats.depth == MAX_DEPTH);
+        trace(&objects, rays(), |ray, rec, data| octree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+    }
+
+    #[test]
+    fn axis_aligned_rays() {
+        // Triangles in the plane y = 0, hit by rays that are parallel to two or all axes
+        let material = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let objects = (0..10).flat_map(|x| (0..10).map(move |z| (x as f64, z as f64)))
+            .map(|(x, z)| Rc::new(Triangle::new(Point3::new(x, 0.0, z), Point3::new(x, 0.0, z + 1.0), Point3::new(x + 1.0, 0.0, z), material.clone())) as Rc<dyn Hittable>)
+            .collect::<Vec<_>>();
+        let octree = build(objects.clone(), &Options { octree_objects: Some(2), ..Default::default() });
+        check(&octree, &objects);
+        let down = (0..10).flat_map(|x| (0..10).map(move |z| Ray::new(Point3::new(x as f64 + 0.3, 5.0, z as f64 + 0.3), Vec3::new(0.0, -1.0, 0.0))));
+        let up = (0..10).map(|x| Ray::new(Point3::new(x as f64 + 0.2, -5.0, 0.4), Vec3::new(0.0, 1.0, 0.0)));
+        let sideways = (0..10).map(|z| Ray::new(Point3::new(-1.0, 0.5, z as f64 + 0.5), Vec3::new(1.0, -0.1, 0.0)));
+        trace(&objects, down.chain(up).chain(sideways), |ray, rec, data| octree.hit(ray, Interval::new(0.001, f64::INFINITY), rec, data));
+    }
+}
@@ -1,3 +1,11 @@
 pub mod rtweekend;
 pub mod scenes;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod polynomial;
+pub mod mesh;
+pub mod obj;
+pub mod gltf;
+pub mod stl;
+pub mod pbrt;
+pub mod scene_file;
+pub mod binary;
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use memmap2::Mmap;
+use serde_json::Value;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::triangle::Triangle;
+use crate::utils::parser::ParseError;
+use crate::value::material::MaterialReference;
+use crate::value::vec3::Vec3;
+
+/// Binary scene files start with this
+const MAGIC: &[u8; 8] = b"RTSCENE\0";
+/// The version of the binary container, the scene inside it has its own version, see `scene_file`
+const BINARY_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 64;
+const MESH_SIZE: usize = 48;
+
+/// Flags for the vertex attributes of a mesh, besides the positions
+const NORMALS: u32 = 1;
+const UVS: u32 = 2;
+const COLORS: u32 = 4;
+
+/// Loads a binary scene file, see `save_binary()` for the layout
+/// The file is memory-mapped, the vertex and index buffers are read from it without copying the file first
+pub fn load_binary(path: &Path) -> Result<HittableList, ParseError> {
+    let file = File::open(path)?;
+    // Safety: the file is only read while it is mapped, it should not be changed by other programs in the meantime
+    let map = unsafe { Mmap::map(&file)? };
+    let contents = Contents(&map);
+
+    if contents.bytes(0, HEADER_SIZE).is_err() || contents.bytes(0, 8)? != MAGIC || contents.u32(8)? != BINARY_VERSION {
+        return Err(ParseError::Parse(format!("{path:?} is not a binary scene file of version {BINARY_VERSION}")));
+    }
+    let mesh_count = contents.u32(12)? as usize;
+    let materials: Value = serde_json::from_slice(contents.bytes(contents.u64(16)?, contents.u64(24)?)?)?;
+    let mut scene: Value = serde_json::from_slice(contents.bytes(contents.u64(32)?, contents.u64(40)?)?)?;
+    let meshes = contents.u64(48)?;
+
+    // The materials are read together with the objects, so both can refer to them by index
+    if let Some(scene) = scene.as_object_mut() {
+        scene.insert("materials".to_string(), materials);
+    }
+    let mut world: HittableList = serde_json::from_value(scene)?;
+
+    for mesh in 0..mesh_count {
+        let header = meshes + (mesh * MESH_SIZE) as u64;
+        let material = world.materials.resolve(&MaterialReference::Index(contents.u32(header)? as usize))
+            .map_err(|e| ParseError::ParseElement("mesh", mesh, e))?;
+        let attributes = contents.u32(header + 4)?;
+        let vertex_count = contents.u64(header + 8)?;
+        let triangle_count = contents.u64(header + 16)?;
+
+        // The vertex buffer holds the positions, followed by the attributes that are present
+        // The triangles are built straight from the mapped buffers, the values are only decoded when a triangle uses them
+        let mut offset = contents.u64(header + 24)?;
+        let mut stream = |present: bool, components: u64| -> Result<Option<&[u8]>, ParseError> {
+            if !present {
+                return Ok(None);
+            }
+            let bytes = contents.bytes(offset, vertex_count.saturating_mul(components * 8))?;
+            offset += vertex_count * components * 8;
+            Ok(Some(bytes))
+        };
+        let positions = stream(true, 3)?.unwrap_or_default();
+        let normals = stream(attributes & NORMALS != 0, 3)?;
+        let uvs = stream(attributes & UVS != 0, 2)?;
+        let colors = stream(attributes & COLORS != 0, 3)?;
+        let indices = contents.bytes(contents.u64(header + 32)?, triangle_count.saturating_mul(12))?;
+
+        let vec3 = |values: &[u8], index: usize| Vec3::new(f64_at(values, 3 * index), f64_at(values, 3 * index + 1), f64_at(values, 3 * index + 2));
+        for (i, triangle) in indices.chunks_exact(12).enumerate() {
+            let [a, b, c] = [0, 1, 2].map(|corner| u32_at(triangle, corner) as usize);
+            if let Some(index) = [a, b, c].into_iter().find(|&index| index as u64 >= vertex_count) {
+                return Err(ParseError::ParseElement("triangle", i, format!("Vertex {index} does not exist in mesh {mesh}, it has {vertex_count} vertices")));
+            }
+            let mut triangle = Triangle::new(vec3(positions, a), vec3(positions, b), vec3(positions, c), material.clone());
+            if let Some(normals) = normals {
+                triangle = triangle.with_normals([a, b, c].map(|index| vec3(normals, index)));
+            }
+            if let Some(uvs) = uvs {
+                triangle = triangle.with_uvs([a, b, c].map(|index| (f64_at(uvs, 2 * index), f64_at(uvs, 2 * index + 1))));
+            }
+            if let Some(colors) = colors {
+                triangle = triangle.with_colors([a, b, c].map(|index| vec3(colors, index)));
+            }
+            world.add(Rc::new(triangle));
+        }
+    }
+    Ok(world)
+}
+
+/// Writes a scene as a binary scene file, the triangles are stored as meshes, one per material and set of attributes
+/// The layout, all numbers are little-endian and the buffers start at a multiple of 8 bytes:
+/// - header: magic, version (u32), mesh count (u32), then offset and length (u64) of the materials and the scene,
+///   and the offset of the mesh table (u64)
+/// - materials: the material table as json
+/// - scene: the objects that are not triangles, the camera and the lights, as a json scene without materials
+/// - mesh table: per mesh the material index (u32), attribute flags (u32), vertex count (u64), triangle count (u64),
+///   offset of the vertices (u64) and offset of the indices (u64)
+/// - buffers: per mesh the positions (3 f64), normals (3 f64), texture coordinates (2 f64) and colors (3 f64)
+///   of all vertices one after another, followed by three u32 vertex indices per triangle
+///
+/// Meshes and included scenes should be loaded first, they are stored in the file as well
+pub fn save_binary(world: &HittableList, path: &Path) -> Result<(), ParseError> {
+    let mut materials = world.materials.clone();
+    let mut meshes: Vec<Mesh> = vec![];
+    let mut mesh_indices: HashMap<(usize, u32), usize> = HashMap::new();
+    let mut scene = HittableList::default();
+    scene.camera = world.camera.clone();
+    scene.lights = world.lights.clone();
+    for object in &world.objects {
+        let Some(triangle) = object.as_triangle() else {
+            scene.add(object.clone());
+            continue;
+        };
+        let material = materials.index_of(triangle.material());
+        let attributes = triangle.normals().map_or(0, |_| NORMALS) | triangle.uvs().map_or(0, |_| UVS) | triangle.colors().map_or(0, |_| COLORS);
+        let index = *mesh_indices.entry((material, attributes)).or_insert_with(|| {
+            meshes.push(Mesh { material: material as u32, attributes, ..Default::default() });
+            meshes.len() - 1
+        });
+        meshes[index].add(triangle)?;
+    }
+
+    // The objects can add materials to the table as well
+    scene.materials = materials;
+    let mut scene = serde_json::to_value(&scene)?;
+    let materials = scene.as_object_mut().and_then(|scene| scene.remove("materials")).unwrap_or(Value::Object(Default::default()));
+    let materials = serde_json::to_vec(&materials)?;
+    let scene = serde_json::to_vec(&scene)?;
+
+    let materials_offset = HEADER_SIZE;
+    let scene_offset = materials_offset + materials.len();
+    let meshes_offset = align(scene_offset + scene.len());
+    let mut offset = meshes_offset + meshes.len() * MESH_SIZE;
+    let mut buffers = vec![];
+    for mesh in &meshes {
+        let vertices = offset;
+        let indices = vertices + mesh.streams.iter().map(Vec::len).sum::<usize>() * 8;
+        buffers.push((vertices, indices));
+        offset = align(indices + mesh.indices.len() * 4);
+    }
+
+    let mut writer = Writer { writer: BufWriter::new(File::create(path)?), position: 0 };
+    writer.write(MAGIC)?;
+    writer.write(&BINARY_VERSION.to_le_bytes())?;
+    writer.write(&(meshes.len() as u32).to_le_bytes())?;
+    for value in [materials_offset, materials.len(), scene_offset, scene.len(), meshes_offset, 0] {
+        writer.write(&(value as u64).to_le_bytes())?;
+    }
+    writer.write(&materials)?;
+    writer.write(&scene)?;
+    writer.pad_to(meshes_offset)?;
+    for (mesh, (vertices, indices)) in meshes.iter().zip(&buffers) {
+        writer.write(&mesh.material.to_le_bytes())?;
+        writer.write(&mesh.attributes.to_le_bytes())?;
+        for value in [mesh.vertex_count, mesh.indices.len() / 3, *vertices, *indices, 0] {
+            writer.write(&(value as u64).to_le_bytes())?;
+        }
+    }
+    for (mesh, (vertices, _)) in meshes.iter().zip(&buffers) {
+        writer.pad_to(*vertices)?;
+        mesh.write_floats(&mut writer)?;
+        for index in &mesh.indices {
+            writer.write(&index.to_le_bytes())?;
+        }
+    }
+    writer.writer.flush()?;
+    Ok(())
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(8)
+}
+
+fn components(v: Vec3) -> [f64; 3] {
+    [v.x(), v.y(), v.z()]
+}
+
+/// The triangles of a scene with the same material and attributes, vertices that are exactly the same are shared
+#[derive(Default)]
+struct Mesh {
+    material: u32,
+    attributes: u32,
+    vertex_count: usize,
+    /// The positions, normals, texture coordinates and colors of the vertices
+    streams: [Vec<f64>; 4],
+    indices: Vec<u32>,
+    /// The index of each vertex, by the bits of its attributes
+    vertices: HashMap<[u64; 11], u32>,
+}
+
+impl Mesh {
+    fn add(&mut self, triangle: &Triangle) -> Result<(), ParseError> {
+        let positions = [triangle.a(), triangle.b(), triangle.c()];
+        for corner in 0..3 {
+            let mut vertex = [vec![], vec![], vec![], vec![]];
+            vertex[0].extend(components(positions[corner]));
+            if let Some(normals) = triangle.normals() { vertex[1].extend(components(normals[corner])) }
+            if let Some(uvs) = triangle.uvs() { vertex[2].extend([uvs[corner].0, uvs[corner].1]) }
+            if let Some(colors) = triangle.colors() { vertex[3].extend(components(colors[corner])) }
+
+            let mut key = [0; 11];
+            for (bits, value) in key.iter_mut().zip(vertex.iter().flatten()) {
+                *bits = value.to_bits();
+            }
+            let index = match self.vertices.get(&key) {
+                Some(&index) => index,
+                None => {
+                    let index = u32::try_from(self.vertex_count)
+                        .map_err(|_| ParseError::Parse("A mesh has more vertices than fit in 32 bit indices".to_string()))?;
+                    for (stream, values) in self.streams.iter_mut().zip(vertex) {
+                        stream.extend(values);
+                    }
+                    self.vertices.insert(key, index);
+                    self.vertex_count += 1;
+                    index
+                }
+            };
+            self.indices.push(index);
+        }
+        Ok(())
+    }
+
+    fn write_floats(&self, writer: &mut Writer) -> Result<(), ParseError> {
+        for value in self.streams.iter().flatten() {
+            writer.write(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+struct Writer {
+    writer: BufWriter<File>,
+    position: usize,
+}
+
+impl Writer {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), ParseError> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    fn pad_to(&mut self, offset: usize) -> Result<(), ParseError> {
+        self.write(&vec![0; offset - self.position])
+    }
+}
+
+/// The contents of a binary scene file, every read is checked against the size of the file
+struct Contents<'a>(&'a [u8]);
+
+impl Contents<'_> {
+    fn bytes(&self, offset: impl TryInto<usize>, length: impl TryInto<usize>) -> Result<&[u8], ParseError> {
+        let range = offset.try_into().ok()
+            .zip(length.try_into().ok())
+            .and_then(|(offset, length): (usize, usize)| Some(offset..offset.checked_add(length)?));
+        range.and_then(|range| self.0.get(range))
+            .ok_or(ParseError::Parse(format!("Binary scene file is truncated, it has {} bytes", self.0.len())))
+    }
+
+    fn u32(&self, offset: impl TryInto<usize>) -> Result<u32, ParseError> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: impl TryInto<usize>) -> Result<u64, ParseError> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+}
+
+/// Value `index` of a buffer of little-endian f64s, the buffer is checked to be large enough when it is read
+fn f64_at(bytes: &[u8], index: usize) -> f64 {
+    f64::from_le_bytes(bytes[8 * index..8 * index + 8].try_into().unwrap())
+}
+
+/// Value `index` of a buffer of little-endian u32s
+fn u32_at(bytes: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(bytes[4 * index..4 * index + 4].try_into().unwrap())
+}
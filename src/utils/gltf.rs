@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use ::gltf::camera::Projection;
+use ::gltf::image::Format;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+use crate::camera::CameraSettings;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::triangle::Triangle;
+use crate::utils::mesh::ImportOptions;
+use crate::utils::parser::ParseError;
+use crate::utils::parser::ParseError::Parse;
+use crate::value::color::Color;
+use crate::value::light::Light;
+use crate::value::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::value::texture::{ImageTexture, Texture};
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
+
+/// Loads a glTF 2.0 file (`.gltf` with its buffers, or a binary `.glb`)
+/// The default scene is used, including the first camera and the lights (KHR_lights_punctual)
+/// Primitives without a material get `mat`
+pub fn parse_gltf(path: &PathBuf, mat: Rc<dyn Material>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+    let scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(Parse("No scenes in glTF file".to_string()))?;
+
+    let mut importer = Importer { buffers, images, default_material: mat, materials: HashMap::new(), textures: HashMap::new(), triangles: vec![], world: HittableList::default() };
+    for node in scene.nodes() {
+        importer.node(&node, &Transform::identity())?;
+    }
+
+    // Cameras and lights are placed together with the meshes
+    let mut world = importer.world;
+    let transform = options.add_triangles(&mut world, importer.triangles);
+    world.camera = world.camera.map(|camera| camera.transformed(&transform));
+    world.lights = world.lights.iter().map(|light| light.transformed(&transform)).collect();
+    Ok(world)
+}
+
+struct Importer {
+    buffers: Vec<::gltf::buffer::Data>,
+    images: Vec<::gltf::image::Data>,
+    default_material: Rc<dyn Material>,
+    /// Materials by index, so triangles share them
+    materials: HashMap<usize, Rc<dyn Material>>,
+    textures: HashMap<usize, Rc<ImageTexture>>,
+    triangles: Vec<Triangle>,
+    /// The camera and lights
+    world: HittableList,
+}
+
+impl Importer {
+    /// Adds a node and its children, `parent` is the transform from the parent to world space
+    fn node(&mut self, node: &::gltf::Node, parent: &Transform) -> Result<(), ParseError> {
+        // glTF matrices are column-major
+        let columns = node.transform().matrix();
+        let matrix = std::array::from_fn(|row| std::array::from_fn(|column| columns[column][row] as f64));
+        let Some(local) = Transform::from_matrix(matrix) else {
+            eprintln!("Warning: skipping glTF node {} with a transform that cannot be inverted", node.index());
+            return Ok(());
+        };
+        let transform = parent * &local;
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, &transform)?;
+            }
+        }
+        if let Some(camera) = node.camera() {
+            self.camera(&camera, &transform);
+        }
+        if let Some(light) = node.light() {
+            self.light(&light, &transform);
+        }
+
+        for child in node.children() {
+            self.node(&child, &transform)?;
+        }
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &::gltf::Primitive, transform: &Transform) -> Result<(), ParseError> {
+        let mode = primitive.mode();
+        if !matches!(mode, Mode::Triangles | Mode::TriangleStrip | Mode::TriangleFan) {
+            eprintln!("Warning: skipping glTF primitive with unsupported mode {mode:?}");
+            return Ok(());
+        }
+
+        let material = self.material(&primitive.material())?;
+        let tex_coord = primitive.material().pbr_metallic_roughness().base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let positions = reader.read_positions()
+            .ok_or(Parse("glTF primitive has no positions".to_string()))?
+            .map(|[x, y, z]| transform.point(Point3::new(x as f64, y as f64, z as f64)))
+            .collect::<Vec<_>>();
+        let normals = reader.read_normals().map(|normals| normals
+            .map(|[x, y, z]| transform.normal(Vec3::new(x as f64, y as f64, z as f64)))
+            .collect::<Vec<_>>());
+        // glTF has the origin of the texture coordinates at the top left of the image
+        let uvs = reader.read_tex_coords(tex_coord).map(|uvs| uvs.into_f32()
+            .map(|[u, v]| (u as f64, 1.0 - v as f64))
+            .collect::<Vec<_>>());
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|index| index as usize).collect::<Vec<_>>(),
+            None => (0..positions.len()).collect(),
+        };
+
+        let mut triangles = match mode {
+            Mode::TriangleStrip => (2..indices.len())
+                // Every other triangle in a strip is flipped
+                .map(|i| if i % 2 == 0 { [indices[i - 2], indices[i - 1], indices[i]] } else { [indices[i - 1], indices[i - 2], indices[i]] })
+                .collect::<Vec<_>>(),
+            Mode::TriangleFan => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            _ => indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect(),
+        };
+        // A mirroring transform flips the winding
+        if transform.determinant() < 0.0 {
+            triangles.iter_mut().for_each(|triangle| triangle.swap(1, 2));
+        }
+
+        for [a, b, c] in triangles {
+            if [a, b, c].iter().any(|&index| index >= positions.len()) {
+                return Err(Parse(format!("glTF index out of range, only {} vertices", positions.len())));
+            }
+            let mut triangle = Triangle::new(positions[a], positions[b], positions[c], material.clone());
+            if let Some(normals) = &normals {
+                triangle = triangle.with_normals([normals[a], normals[b], normals[c]]);
+            }
+            if let Some(uvs) = &uvs {
+                triangle = triangle.with_uvs([uvs[a], uvs[b], uvs[c]]);
+            }
+            self.triangles.push(triangle);
+        }
+        Ok(())
+    }
+
+    /// Maps a metallic-roughness material onto the closest material we support
+    fn material(&mut self, material: &::gltf::Material) -> Result<Rc<dyn Material>, ParseError> {
+        let Some(index) = material.index() else {
+            return Ok(self.default_material.clone());
+        };
+        if let Some(material) = self.materials.get(&index) {
+            return Ok(material.clone());
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color = Color::new(r as f64, g as f64, b as f64);
+        let texture = match pbr.base_color_texture() {
+            Some(info) => Some(self.texture(info.texture().source().index())? as Rc<dyn Texture>),
+            None => None,
+        };
+
+        // Per-texel metallic and roughness are not supported, use the average of the texture instead
+        let (mut metallic, mut roughness) = (pbr.metallic_factor() as f64, pbr.roughness_factor() as f64);
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            let (average_roughness, average_metallic) = self.average_channels(info.texture().source().index());
+            roughness *= average_roughness;
+            metallic *= average_metallic;
+        }
+
+        let [er, eg, eb] = material.emissive_factor();
+        let emissive = material.emissive_strength().unwrap_or(1.0) as f64 * Color::new(er as f64, eg as f64, eb as f64);
+        let transmission = material.transmission().map_or(0.0, |transmission| transmission.transmission_factor());
+
+        let converted: Rc<dyn Material> = if !emissive.near_zero() {
+            Rc::new(DiffuseLight::new(emissive))
+        } else if transmission >= 0.5 {
+            Rc::new(Dielectric::new(material.ior().unwrap_or(1.5) as f64))
+        } else if metallic >= 0.5 {
+            match texture {
+                Some(texture) => Rc::new(Metal::textured(base_color, roughness, texture)),
+                None => Rc::new(Metal::new(base_color, roughness)),
+            }
+        } else {
+            match texture {
+                Some(texture) => Rc::new(Lambertian::textured(base_color, texture)),
+                None => Rc::new(Lambertian::new(base_color)),
+            }
+        };
+        self.materials.insert(index, converted.clone());
+        Ok(converted)
+    }
+
+    fn texture(&mut self, image: usize) -> Result<Rc<ImageTexture>, ParseError> {
+        if let Some(texture) = self.textures.get(&image) {
+            return Ok(texture.clone());
+        }
+        let data = self.images.get(image).ok_or(Parse(format!("glTF image {image} does not exist")))?;
+        let rgb = to_rgb8(data).ok_or(Parse(format!("glTF image {image} has unsupported format {:?}", data.format)))?;
+        let texture = Rc::new(ImageTexture::embedded(data.width as usize, data.height as usize, rgb));
+        self.textures.insert(image, texture.clone());
+        Ok(texture)
+    }
+
+    /// The average of the green and blue channels of an image, from 0 to 1
+    fn average_channels(&self, image: usize) -> (f64, f64) {
+        let Some(rgb) = self.images.get(image).and_then(to_rgb8) else {
+            return (1.0, 1.0);
+        };
+        let pixels = (rgb.len() / 3).max(1) as f64;
+        let sum = |channel: usize| rgb.iter().skip(channel).step_by(3).map(|&c| c as f64).sum::<f64>() / 255.0 / pixels;
+        (sum(1), sum(2))
+    }
+
+    fn camera(&mut self, camera: &::gltf::Camera, transform: &Transform) {
+        // Only the first camera is used
+        if self.world.camera.is_some() {
+            return;
+        }
+        let Projection::Perspective(perspective) = camera.projection() else {
+            eprintln!("Warning: skipping orthographic glTF camera {}", camera.index());
+            return;
+        };
+        // The camera looks along its local -z axis
+        let look_from = transform.point(Point3::default());
+        self.world.camera = Some(CameraSettings {
+            look_from,
+            look_at: look_from + transform.vector(Vec3::new(0.0, 0.0, -1.0)).unit(),
+            v_up: transform.vector(Vec3::new(0.0, 1.0, 0.0)).unit(),
+            vfov: (perspective.yfov() as f64).to_degrees(),
+            aspect_ratio: perspective.aspect_ratio().map(|aspect_ratio| aspect_ratio as f64),
+            defocus_angle: Some(0.0),
+            focus_dist: None,
+            image_width: None,
+            samples_per_pixel: None,
+        });
+    }
+
+    fn light(&mut self, light: &::gltf::khr_lights_punctual::Light, transform: &Transform) {
+        let [r, g, b] = light.color();
+        let color = Color::new(r as f64, g as f64, b as f64);
+        let intensity = light.intensity() as f64;
+        // Lights shine along their local -z axis
+        let position = transform.point(Point3::default());
+        let direction = transform.vector(Vec3::new(0.0, 0.0, -1.0)).unit();
+        self.world.lights.push(match light.kind() {
+            Kind::Point => Light::Point { position, color, intensity },
+            Kind::Directional => Light::Directional { direction, color, intensity },
+            Kind::Spot { inner_cone_angle, outer_cone_angle } => Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                inner_cone_angle: inner_cone_angle as f64,
+                outer_cone_angle: outer_cone_angle as f64,
+            },
+        });
+    }
+}
+
+/// Converts the decoded image to 8-bit RGB, the alpha channel is dropped
+fn to_rgb8(data: &::gltf::image::Data) -> Option<Vec<u8>> {
+    let pixels = &data.pixels;
+    let rgb = match data.format {
+        Format::R8 => pixels.iter().flat_map(|&c| [c, c, c]).collect(),
+        Format::R8G8 => pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0]).collect(),
+        Format::R8G8B8 => pixels.clone(),
+        Format::R8G8B8A8 => pixels.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+        // Keep the most significant byte of the (little-endian) 16-bit values
+        Format::R16G16B16 => pixels.chunks_exact(6).flat_map(|p| [p[1], p[3], p[5]]).collect(),
+        Format::R16G16B16A16 => pixels.chunks_exact(8).flat_map(|p| [p[1], p[3], p[5]]).collect(),
+        _ => return None,
+    };
+    Some(rgb)
+}
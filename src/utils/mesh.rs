@@ -0,0 +1,185 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use crate::acceleration::aabb::AABB;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::triangle::Triangle;
+use crate::utils::gltf::parse_gltf;
+use crate::utils::obj::parse_obj;
+use crate::utils::parser::{parse_ply, ParseError};
+use crate::utils::pbrt::parse_pbrt;
+use crate::utils::rtweekend::FileFormat;
+use crate::utils::stl::parse_stl;
+use crate::value::color::Color;
+use crate::value::material::{Lambertian, Material, MaterialReference};
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
+
+/// How an imported mesh is placed in the scene
+/// The steps are applied in order: up axis, recentring, fitting, scaling and the transform of a scene file
+#[derive(Args, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Uniform scale of imported meshes
+    #[arg(long, default_value_t = 1.0)]
+    pub scale: f64,
+    /// The up axis of imported meshes, the renderer uses Y-up
+    #[arg(long, value_enum, default_value_t = UpAxis::default())]
+    pub up_axis: UpAxis,
+    /// Move the centre of the bounding box of imported meshes to the origin
+    #[arg(long)]
+    pub recenter: bool,
+    /// Scale imported meshes so the largest side of the bounding box has this size
+    #[arg(long)]
+    pub fit_size: Option<f64>,
+    /// Placement after the other steps, set from the `transform` of a mesh in a scene file
+    #[arg(skip)]
+    #[serde(skip)]
+    pub transform: Option<Transform>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { scale: 1.0, up_axis: UpAxis::default(), recenter: false, fit_size: None, transform: None }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug, ValueEnum, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UpAxis {
+    #[default]
+    Y,
+    /// Used by most CAD programs and Blender, converted to Y-up by rotating around the x-axis
+    Z,
+}
+
+impl ImportOptions {
+    /// The transform for a mesh with these vertex positions
+    pub fn transform(&self, positions: impl Iterator<Item = Point3>) -> Transform {
+        let steps = self.steps(positions);
+        match &self.transform {
+            Some(transform) => transform * &steps,
+            None => steps,
+        }
+    }
+
+    fn steps(&self, positions: impl Iterator<Item = Point3>) -> Transform {
+        let mut transform = match self.up_axis {
+            UpAxis::Y => Transform::identity(),
+            UpAxis::Z => Transform::rotate(Vec3::new(1.0, 0.0, 0.0), -90.0),
+        };
+        if !self.recenter && self.fit_size.is_none() {
+            return &Transform::scale(Vec3::new(self.scale, self.scale, self.scale)) * &transform;
+        }
+
+        let positions = positions.map(|p| transform.point(p)).collect::<Vec<_>>();
+        if positions.is_empty() {
+            return transform;
+        }
+        let bounds = AABB::from_points(&positions);
+        if self.recenter {
+            let center = 0.5 * (bounds.min + bounds.max);
+            transform = &Transform::translate(-center) * &transform;
+        }
+        let mut scale = self.scale;
+        if let Some(size) = self.fit_size {
+            let extent = bounds.max - bounds.min;
+            let largest = extent.x().max(extent.y()).max(extent.z());
+            if largest > 0.0 {
+                scale *= size / largest;
+            }
+        }
+        &Transform::scale(Vec3::new(scale, scale, scale)) * &transform
+    }
+
+    /// Adds the triangles of a mesh to `world`, placed using these options
+    /// Returns the transform that was used, so cameras and lights in the file can be moved as well
+    pub fn add_triangles(&self, world: &mut HittableList, triangles: Vec<Triangle>) -> Transform {
+        let transform = self.transform(triangles.iter().flat_map(|triangle| [triangle.a(), triangle.b(), triangle.c()]));
+        for triangle in triangles {
+            let triangle = if transform.is_identity() { triangle } else { triangle.transformed(&transform) };
+            world.add(Rc::new(triangle));
+        }
+        transform
+    }
+}
+
+/// Loads a mesh file, faces without a material get `material`, or a default material for the format
+pub fn load_mesh(path: &PathBuf, format: FileFormat, material: Option<Rc<dyn Material>>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let default = |color: Color| material.clone().unwrap_or_else(|| Rc::new(Lambertian::new(color)));
+    match format {
+        FileFormat::Native | FileFormat::Binary => Err(ParseError::Parse(format!("{path:?} is not a mesh file"))),
+        FileFormat::PLY => parse_ply(path, default(Color::new(0.8, 0.2, 0.1)), options),
+        FileFormat::OBJ => parse_obj(path, default(Color::new(0.8, 0.8, 0.8)), options),
+        FileFormat::GLTF => parse_gltf(path, default(Color::new(0.8, 0.8, 0.8)), options),
+        FileFormat::STL => parse_stl(path, default(Color::new(0.8, 0.8, 0.8)), options),
+        FileFormat::PBRT => parse_pbrt(path, default(Color::new(0.5, 0.5, 0.5)), options),
+    }
+}
+
+/// A mesh file used in a scene file, it is loaded together with the scene
+/// The path is relative to the scene file, the format follows from the extension if it is not given
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MeshReference {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FileFormat>,
+    #[serde(default)]
+    pub import: ImportOptions,
+    /// Used for the faces without a material in the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<MaterialReference>,
+    /// Places the mesh after the import options
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+}
+
+/// Splits a (planar) polygon into triangles using ear clipping, so concave polygons work as well
+/// Returns indices into `polygon`, the triangles keep the winding of the polygon
+pub fn triangulate(polygon: &[Point3]) -> Vec<[usize; 3]> {
+    if polygon.len() < 3 {
+        return vec![];
+    }
+    if polygon.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, also works for concave polygons
+    let mut normal = Vec3::default();
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        normal += Vec3::new(
+            (a.y() - b.y()) * (a.z() + b.z()),
+            (a.z() - b.z()) * (a.x() + b.x()),
+            (a.x() - b.x()) * (a.y() + b.y()),
+        );
+    }
+
+    let mut remaining = (0..polygon.len()).collect::<Vec<_>>();
+    let mut triangles = Vec::with_capacity(polygon.len() - 2);
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]);
+            let (pa, pb, pc) = (polygon[a], polygon[b], polygon[c]);
+            // The corner has to be convex, and no other corner can be inside the ear
+            (pb - pa).cross(&(pc - pb)).dot(&normal) > 0.0
+                && remaining.iter()
+                .filter(|&&other| other != a && other != b && other != c)
+                .all(|&other| !inside_triangle(polygon[other], pa, pb, pc, normal))
+        });
+        // Degenerate polygons have no ears, cut off any corner so it still terminates
+        let i = ear.unwrap_or(0);
+        triangles.push([remaining[(i + n - 1) % n], remaining[i], remaining[(i + 1) % n]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+fn inside_triangle(p: Point3, a: Point3, b: Point3, c: Point3, normal: Vec3) -> bool {
+    (b - a).cross(&(p - a)).dot(&normal) >= 0.0
+        && (c - b).cross(&(p - b)).dot(&normal) >= 0.0
+        && (a - c).cross(&(p - c)).dot(&normal) >= 0.0
+}
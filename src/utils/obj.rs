@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::triangle::Triangle;
+use crate::utils::mesh::{triangulate, ImportOptions};
+use crate::utils::parser::ParseError;
+use crate::utils::parser::ParseError::ParseLine;
+use crate::value::color::Color;
+use crate::value::material::{Dielectric, Lambertian, Material, Metal};
+use crate::value::texture::ImageTexture;
+use crate::value::vec3::{Point3, Vec3};
+
+/// Loads a Wavefront OBJ file, with the materials from its MTL files
+/// Faces without a material get `mat`
+/// Objects and groups are flattened into one list, since the acceleration structures work best on all triangles at once
+pub fn parse_obj(path: &PathBuf, mat: Rc<dyn Material>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let contents = fs::read_to_string(path)?;
+    let error = |line: usize, message: String| ParseLine(path.clone(), line + 1, message);
+
+    let mut positions: Vec<Point3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f64, f64)> = vec![];
+    let mut materials: HashMap<String, Rc<dyn Material>> = HashMap::new();
+    let mut current_material = mat.clone();
+    let mut unsupported = HashSet::new();
+
+    let mut triangles = vec![];
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments = tokens.collect::<Vec<_>>();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats(&arguments, 3, 3).map_err(|e| error(line_number, e))?[..] else { unreachable!() };
+                positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(&arguments, 3, 3).map_err(|e| error(line_number, e))?[..] else { unreachable!() };
+                normals.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                let uv = parse_floats(&arguments, 1, 2).map_err(|e| error(line_number, e))?;
+                uvs.push((uv[0], uv.get(1).copied().unwrap_or_default()));
+            }
+            "f" => {
+                let corners = arguments.iter()
+                    .map(|corner| parse_corner(corner, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| error(line_number, e))?;
+                if corners.len() < 3 {
+                    return Err(error(line_number, "Face should have at least 3 vertices".to_string()));
+                }
+
+                let polygon = corners.iter().map(|corner| positions[corner.position]).collect::<Vec<_>>();
+                for [a, b, c] in triangulate(&polygon) {
+                    let corners = [&corners[a], &corners[b], &corners[c]];
+                    let mut triangle = Triangle::new(polygon[a], polygon[b], polygon[c], current_material.clone());
+                    if let [Some(n0), Some(n1), Some(n2)] = corners.map(|corner| corner.normal) {
+                        triangle = triangle.with_normals([normals[n0], normals[n1], normals[n2]]);
+                    }
+                    if let [Some(uv0), Some(uv1), Some(uv2)] = corners.map(|corner| corner.uv) {
+                        triangle = triangle.with_uvs([uvs[uv0], uvs[uv1], uvs[uv2]]);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+            "mtllib" => {
+                for file in arguments {
+                    let mtl_path = path.parent().unwrap_or(Path::new("")).join(file);
+                    materials.extend(parse_mtl(&mtl_path)?);
+                }
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                current_material = match materials.get(&name) {
+                    Some(material) => material.clone(),
+                    None => {
+                        eprintln!("Warning: material {name:?} used on line {} of {path:?} is not defined", line_number + 1);
+                        mat.clone()
+                    }
+                };
+            }
+            // Objects and groups are flattened, smoothing groups are replaced by the vertex normals
+            "o" | "g" | "s" => {}
+            _ => {
+                if unsupported.insert(keyword.to_string()) {
+                    eprintln!("Warning: ignoring unsupported OBJ statement {keyword:?} in {path:?}");
+                }
+            }
+        }
+    }
+
+    let mut world = HittableList::default();
+    options.add_triangles(&mut world, triangles);
+    Ok(world)
+}
+
+/// The indices of a single face corner (`v`, `v/vt`, `v//vn` or `v/vt/vn`), starting at zero
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+fn parse_corner(corner: &str, positions: usize, uvs: usize, normals: usize) -> Result<Corner, String> {
+    let mut parts = corner.split('/');
+    let position = resolve_index(parts.next().unwrap_or_default(), positions)?
+        .ok_or(format!("Face vertex {corner:?} has no position"))?;
+    let uv = resolve_index(parts.next().unwrap_or_default(), uvs)?;
+    let normal = resolve_index(parts.next().unwrap_or_default(), normals)?;
+    Ok(Corner { position, uv, normal })
+}
+
+/// OBJ indices start at 1, negative indices count back from the last element defined so far
+fn resolve_index(index: &str, count: usize) -> Result<Option<usize>, String> {
+    if index.is_empty() {
+        return Ok(None);
+    }
+    let value = index.parse::<i64>().map_err(|_| format!("Invalid index {index:?}"))?;
+    let resolved = match value {
+        1.. => value - 1,
+        ..=-1 => count as i64 + value,
+        0 => return Err("Index 0 is not valid".to_string()),
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("Index {value} out of range, only {count} defined"));
+    }
+    Ok(Some(resolved as usize))
+}
+
+fn parse_floats(arguments: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if arguments.len() < min {
+        return Err(format!("Expected at least {min} values, found {}", arguments.len()));
+    }
+    // Extra values (like a w coordinate or vertex colors) are ignored
+    arguments.iter().take(max)
+        .map(|value| value.parse::<f64>().map_err(|_| format!("Invalid number {value:?}")))
+        .collect()
+}
+
+/// The properties of a single material in an MTL file
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    shininess: f64,
+    refraction_index: Option<f64>,
+    dissolve: f64,
+    illumination: Option<u32>,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::default(),
+            shininess: 0.0,
+            refraction_index: None,
+            dissolve: 1.0,
+            illumination: None,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Picks the closest material we support
+    fn to_material(&self) -> Result<Rc<dyn Material>, ParseError> {
+        let max = |color: Color| color.x().max(color.y()).max(color.z());
+        // Illumination models 4, 6, 7 and 9 are glass
+        let transparent = self.dissolve < 1.0 || matches!(self.illumination, Some(4 | 6 | 7 | 9));
+        let metallic = self.illumination == Some(3) || max(self.specular) > max(self.diffuse);
+
+        Ok(if transparent {
+            Rc::new(Dielectric::new(self.refraction_index.filter(|&ni| ni > 1.0).unwrap_or(1.5)))
+        } else if metallic {
+            // Rough approximation from the Phong exponent to a fuzz factor
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt();
+            Rc::new(Metal::new(self.specular, fuzz))
+        } else if let Some(diffuse_map) = &self.diffuse_map {
+            Rc::new(Lambertian::textured(self.diffuse, Rc::new(ImageTexture::load(diffuse_map.clone())?)))
+        } else {
+            Rc::new(Lambertian::new(self.diffuse))
+        })
+    }
+}
+
+fn parse_mtl(path: &PathBuf) -> Result<HashMap<String, Rc<dyn Material>>, ParseError> {
+    let contents = fs::read_to_string(path)?;
+    let error = |line: usize, message: String| ParseLine(path.clone(), line + 1, message);
+
+    let mut parsed: Vec<(String, MtlMaterial)> = vec![];
+    let mut unsupported = HashSet::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let arguments = tokens.collect::<Vec<_>>();
+
+        if keyword == "newmtl" {
+            parsed.push((arguments.join(" "), MtlMaterial::default()));
+            continue;
+        }
+        let Some((_, material)) = parsed.last_mut() else {
+            return Err(error(line_number, format!("{keyword:?} before the first newmtl")));
+        };
+        let color = || -> Result<Color, ParseError> {
+            let [r, g, b] = parse_floats(&arguments, 3, 3).map_err(|e| error(line_number, e))?[..] else { unreachable!() };
+            Ok(Color::new(r, g, b))
+        };
+        let float = || -> Result<f64, ParseError> {
+            Ok(parse_floats(&arguments, 1, 1).map_err(|e| error(line_number, e))?[0])
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = float()?,
+            "Ni" => material.refraction_index = Some(float()?),
+            "d" => material.dissolve = float()?,
+            "Tr" => material.dissolve = 1.0 - float()?,
+            "illum" => material.illumination = Some(float()? as u32),
+            "map_Kd" => {
+                // Options like `-s 1 1 1` come before the file name
+                let file = arguments.last().ok_or(error(line_number, "map_Kd without a file".to_string()))?;
+                material.diffuse_map = Some(path.parent().unwrap_or(Path::new("")).join(file));
+            }
+            _ => {
+                if unsupported.insert(keyword.to_string()) {
+                    eprintln!("Warning: ignoring unsupported MTL statement {keyword:?} in {path:?}");
+                }
+            }
+        }
+    }
+
+    parsed.into_iter()
+        .map(|(name, material)| Ok((name, material.to_material()?)))
+        .collect()
+}
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io;
+use std::io::BufReader;
 use std::path::PathBuf;
 use std::rc::Rc;
 use ply_rs::parser::Parser;
@@ -10,73 +11,136 @@ use crate::value::material::Material;
 use crate::utils::parser::ParseError::{Parse, ParseElement, ParseValue};
 use crate::utils::parser::ParseValueError::ParseProperty;
 use crate::hittable::triangle::Triangle;
-use crate::value::vec3::Vec3;
+use crate::utils::mesh::{triangulate, ImportOptions};
+use crate::value::color::Color;
+use crate::value::vec3::{Point3, Vec3};
 
-pub fn parse_ply(path: &PathBuf, mat: Rc<dyn Material>) -> Result<HittableList, ParseError> {
-    let mut file = File::open(path)?;
+/// Loads a PLY file (ascii or binary) with the material `mat`
+/// Polygons are triangulated, optional vertex normals (`nx`, `ny`, `nz`), colors (`red`, `green`, `blue`)
+/// and texture coordinates (`u` and `v`, or `s` and `t`) are added to the triangles
+pub fn parse_ply(path: &PathBuf, mat: Rc<dyn Material>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let mut world = HittableList::default();
+    options.add_triangles(&mut world, read_ply(path, mat)?);
+    Ok(world)
+}
+
+/// The triangles of a PLY file, as they are in the file
+pub fn read_ply(path: &PathBuf, mat: Rc<dyn Material>) -> Result<Vec<Triangle>, ParseError> {
+    let mut file = BufReader::new(File::open(path)?);
     // Create PLY parser
     let parser = Parser::<DefaultElement>::new();
     let ply = parser.read_ply(&mut file)?;
 
     let vertices = ply.payload.get("vertex")
         .ok_or(Parse("No vertices in PLY file".to_string()))?
-        .iter().map(parse_vertex)
+        .iter().enumerate()
+        .map(|(index, element)| parse_vertex(index, element))
         .collect::<Result<Vec<_>, _>>()?;
 
-    let faces = ply.payload.get("face")
+    let mut triangles = vec![];
+    for (index, element) in ply.payload.get("face")
         .ok_or(Parse("No faces in PLY file".to_string()))?
-        .iter().map(|f| parse_face(f, &vertices, Rc::clone(&mat)))
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let mut world = HittableList::default();
-    for face in faces {
-        world.add(Rc::new(face))
+        .iter().enumerate() {
+        triangles.extend(parse_face(index, element, &vertices, &mat)?);
     }
+    Ok(triangles)
+}
 
-    Ok(world)
+/// A vertex with its optional attributes
+struct Vertex {
+    position: Point3,
+    normal: Option<Vec3>,
+    color: Option<Color>,
+    uv: Option<(f64, f64)>,
 }
 
-fn parse_vertex(element: &DefaultElement) -> Result<Vec3, ParseError> {
-    let x = parse_float(element.get("x")
-        .ok_or(ParseElement(element.clone(), "Vertex has no x value".to_string()))?)
-        .map_err(|e| ParseValue(element.clone(), e))?;
-    let y = parse_float(element.get("y")
-        .ok_or(ParseElement(element.clone(), "Vertex has no y value".to_string()))?)
-        .map_err(|e| ParseValue(element.clone(), e))?;
-    let z = parse_float(element.get("z")
-        .ok_or(ParseElement(element.clone(), "Vertex has no z value".to_string()))?)
-        .map_err(|e| ParseValue(element.clone(), e))?;
-    Ok(Vec3::new(x, y, z))
-    // Ok(Vec3::new(x, z, y))
-    // Ok(Vec3::new(y, x, z))
-    // Ok(Vec3::new(y, z, x))
-    // Ok(Vec3::new(z, x, y))
-    // Ok(Vec3::new(z, y, x))
+fn parse_vertex(index: usize, element: &DefaultElement) -> Result<Vertex, ParseError> {
+    let get = |name: &str| element.get(name)
+        .map(|property| parse_number(property).map_err(|e| ParseValue("vertex", index, e)))
+        .transpose();
+    let coordinate = |name: &str| parse_number(element.get(name)
+        .ok_or(ParseElement("vertex", index, format!("Vertex has no {name} value")))?)
+        .map_err(|e| ParseValue("vertex", index, e));
+    // All or nothing for the optional attributes
+    let optional = |names: &[&str]| -> Result<Option<Vec<f64>>, ParseError> {
+        names.iter().map(|&name| get(name)).collect::<Result<Option<Vec<_>>, _>>()
+    };
+
+    let position = Point3::new(coordinate("x")?, coordinate("y")?, coordinate("z")?);
+    let normal = optional(&["nx", "ny", "nz"])?.map(|n| Vec3::new(n[0], n[1], n[2]));
+    let uv = optional(&["u", "v"])?.or(optional(&["s", "t"])?).map(|uv| (uv[0], uv[1]));
+    let color = ["red", "green", "blue"].iter()
+        .map(|&name| element.get(name).map(|property| parse_color(property).map_err(|e| ParseValue("vertex", index, e))).transpose())
+        .collect::<Result<Option<Vec<_>>, _>>()?
+        .map(|c| Color::new(c[0], c[1], c[2]));
+    Ok(Vertex { position, normal, color, uv })
 }
 
-fn parse_face(element: &DefaultElement, vertices: &[Vec3], mat: Rc<dyn Material>) -> Result<Triangle, ParseError> {
-    let vertices = parse_list(element.get("vertex_indices")
-        .ok_or(ParseElement(element.clone(), "Face has no vertex_indices value".to_string()))?)
-        .map_err(|e| ParseValue(element.clone(), e))?
-        .iter().map(|idx| { vertices.get(*idx as usize).ok_or(ParseElement(element.clone(), format!("Vertex {idx} not found"))) })
+fn parse_face(index: usize, element: &DefaultElement, vertices: &[Vertex], mat: &Rc<dyn Material>) -> Result<Vec<Triangle>, ParseError> {
+    let corners = parse_list(element.get("vertex_indices").or(element.get("vertex_index"))
+        .ok_or(ParseElement("face", index, "Face has no vertex_indices value".to_string()))?)
+        .map_err(|e| ParseValue("face", index, e))?
+        .iter().map(|&idx| vertices.get(idx).ok_or(ParseElement("face", index, format!("Vertex {idx} not found"))))
         .collect::<Result<Vec<_>, _>>()?;
-    if vertices.len() != 3 { return Err(ParseElement(element.clone(), "Face should have 3 vertices".to_string())); }
-    Ok(Triangle::new(*vertices[0], *vertices[1], *vertices[2], mat))
+    if corners.len() < 3 { return Err(ParseElement("face", index, "Face should have at least 3 vertices".to_string())); }
+
+    let polygon = corners.iter().map(|corner| corner.position).collect::<Vec<_>>();
+    Ok(triangulate(&polygon).into_iter().map(|[a, b, c]| {
+        let corners = [corners[a], corners[b], corners[c]];
+        let mut triangle = Triangle::new(polygon[a], polygon[b], polygon[c], Rc::clone(mat));
+        if let [Some(n0), Some(n1), Some(n2)] = corners.map(|corner| corner.normal) {
+            triangle = triangle.with_normals([n0, n1, n2]);
+        }
+        if let [Some(c0), Some(c1), Some(c2)] = corners.map(|corner| corner.color) {
+            triangle = triangle.with_colors([c0, c1, c2]);
+        }
+        if let [Some(uv0), Some(uv1), Some(uv2)] = corners.map(|corner| corner.uv) {
+            triangle = triangle.with_uvs([uv0, uv1, uv2]);
+        }
+        triangle
+    }).collect())
 }
 
-fn parse_float(property: &Property) -> Result<f64, ParseValueError> {
-    if let Property::Float(float) = property {
-        Ok(*float as f64 * 100.0)
-    } else {
-        Err(ParseProperty("Property is not a double value.".to_string()))
+fn parse_number(property: &Property) -> Result<f64, ParseValueError> {
+    match *property {
+        Property::Char(value) => Ok(value as f64),
+        Property::UChar(value) => Ok(value as f64),
+        Property::Short(value) => Ok(value as f64),
+        Property::UShort(value) => Ok(value as f64),
+        Property::Int(value) => Ok(value as f64),
+        Property::UInt(value) => Ok(value as f64),
+        Property::Float(value) => Ok(value as f64),
+        Property::Double(value) => Ok(value),
+        _ => Err(ParseProperty("Property is not a number".to_string())),
     }
 }
 
-fn parse_list(property: &Property) -> Result<&Vec<i32>, ParseValueError> {
-    if let Property::ListInt(list) = property {
-        Ok(list)
-    } else {
-        Err(ParseProperty("Property is not a double value.".to_string()))
+/// Integer colors go from 0 to their maximum value, floating point colors from 0 to 1
+/// The colors are stored with sRGB gamma, the renderer works in linear space (with gamma 2)
+fn parse_color(property: &Property) -> Result<f64, ParseValueError> {
+    let value = match *property {
+        Property::UChar(value) => value as f64 / u8::MAX as f64,
+        Property::UShort(value) => value as f64 / u16::MAX as f64,
+        Property::Float(_) | Property::Double(_) => parse_number(property)?.clamp(0.0, 1.0),
+        _ => return Err(ParseProperty("Color should be an unsigned char, unsigned short or floating point value".to_string())),
+    };
+    Ok(value * value)
+}
+
+fn parse_list(property: &Property) -> Result<Vec<usize>, ParseValueError> {
+    fn indices<T: Copy + TryInto<usize>>(list: &[T]) -> Result<Vec<usize>, ParseValueError> {
+        list.iter()
+            .map(|&index| index.try_into().map_err(|_| ParseProperty("Vertex index should not be negative".to_string())))
+            .collect()
+    }
+    match property {
+        Property::ListChar(list) => indices(list),
+        Property::ListUChar(list) => indices(list),
+        Property::ListShort(list) => indices(list),
+        Property::ListUShort(list) => indices(list),
+        Property::ListInt(list) => indices(list),
+        Property::ListUInt(list) => indices(list),
+        _ => Err(ParseProperty("Property is not a list of integers".to_string())),
     }
 }
 
@@ -86,10 +150,18 @@ pub enum ParseError {
     IO(#[from] io::Error),
     #[error("Parse error {0}")]
     Parse(String),
-    #[error("Parse error in {0:?}: {1}")]
-    ParseElement(DefaultElement, String),
-    #[error("Parse error in {0:?}: {1}")]
-    ParseValue(DefaultElement, #[source] ParseValueError),
+    #[error("Parse error in {0} {1}: {2}")]
+    ParseElement(&'static str, usize, String),
+    #[error("Parse error in {0} {1}: {2}")]
+    ParseValue(&'static str, usize, #[source] ParseValueError),
+    #[error("Parse error in {0:?} on line {1}: {2}")]
+    ParseLine(PathBuf, usize, String),
+    #[error("Error loading texture: {0}")]
+    Texture(#[from] image::ImageError),
+    #[error("Error loading glTF file: {0}")]
+    Gltf(#[from] ::gltf::Error),
+    #[error("Error reading scene file: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 #[derive(Error, Debug)]
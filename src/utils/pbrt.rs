@@ -0,0 +1,603 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use crate::camera::CameraSettings;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::instance::Instance;
+use crate::hittable::sphere::Sphere;
+use crate::hittable::triangle::Triangle;
+use crate::utils::mesh::ImportOptions;
+use crate::utils::parser::{read_ply, ParseError};
+use crate::utils::parser::ParseError::ParseLine;
+use crate::value::color::Color;
+use crate::value::light::Light;
+use crate::value::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
+
+/// Included files can include other files, up to this depth
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// Loads a scene in the pbrt-v4 format, only a subset is supported:
+/// perspective cameras, film resolution, samples per pixel, triangle meshes, PLY meshes, spheres,
+/// diffuse, conductor and dielectric materials, point, spot and distant lights, diffuse area lights,
+/// transforms, attributes, named materials and includes
+/// Everything else is skipped with a warning
+/// Shapes without a material get `mat`
+pub fn parse_pbrt(path: &Path, mat: Rc<dyn Material>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let mut importer = Importer {
+        state: State { transform: Transform::identity(), material: mat, area_light: None },
+        stack: vec![],
+        named_materials: HashMap::new(),
+        coordinate_systems: HashMap::new(),
+        camera: None,
+        film: None,
+        samples_per_pixel: None,
+        triangles: vec![],
+        spheres: vec![],
+        lights: vec![],
+        warned: HashSet::new(),
+    };
+    importer.parse_file(path, 0)?;
+    Ok(importer.finish(options))
+}
+
+/// A token with the line it is on
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A directive, number or boolean
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+}
+
+fn tokenize(path: &Path, contents: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    for (line_number, line) in contents.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '#' => break,
+                '[' => tokens.push((Token::Open, line_number)),
+                ']' => tokens.push((Token::Close, line_number)),
+                '"' => {
+                    let end = line[start + 1..].find('"')
+                        .ok_or(ParseLine(path.to_path_buf(), line_number + 1, "String is not closed".to_string()))?;
+                    tokens.push((Token::Quoted(line[start + 1..start + 1 + end].to_string()), line_number));
+                    while chars.next_if(|&(index, _)| index <= start + 1 + end).is_some() {}
+                }
+                c if c.is_whitespace() => {}
+                _ => {
+                    let mut end = line.len();
+                    while let Some(&(index, c)) = chars.peek() {
+                        if c.is_whitespace() || matches!(c, '[' | ']' | '"' | '#') {
+                            end = index;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    tokens.push((Token::Word(line[start..end].to_string()), line_number));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+/// A parameter like `"float fov" 45` or `"rgb reflectance" [ 0.8 0.2 0.1 ]`
+struct Parameter {
+    kind: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+/// The arguments of a directive: values before the parameters and the parameters
+struct Arguments {
+    values: Vec<Value>,
+    parameters: Vec<Parameter>,
+}
+
+impl Arguments {
+    fn parameter(&self, name: &str) -> Option<&Parameter> {
+        self.parameters.iter().find(|parameter| parameter.name == name)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f64>> {
+        self.parameter(name).map(|parameter| parameter.values.iter().filter_map(|value| match value {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }).collect())
+    }
+
+    fn number(&self, name: &str) -> Option<f64> {
+        self.numbers(name)?.first().copied()
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.parameter(name)?.values.first()? {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// A color given as `rgb`, spectra and textures are not supported
+    fn color(&self, name: &str) -> Option<Color> {
+        let parameter = self.parameter(name)?;
+        match (parameter.kind.as_str(), &self.numbers(name)?[..]) {
+            ("rgb" | "color", &[r, g, b]) => Some(Color::new(r, g, b)),
+            _ => None,
+        }
+    }
+
+    fn text(&self, index: usize) -> Option<&str> {
+        match self.values.get(index)? {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn all_numbers(&self) -> Option<Vec<f64>> {
+        self.values.iter().map(|value| match value {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }).collect()
+    }
+}
+
+/// The graphics state that is saved by AttributeBegin
+#[derive(Clone)]
+struct State {
+    /// The current transformation matrix, from object to world space
+    transform: Transform,
+    material: Rc<dyn Material>,
+    /// Emitted light of shapes in a diffuse area light
+    area_light: Option<Color>,
+}
+
+struct PerspectiveCamera {
+    /// From camera to world space
+    transform: Transform,
+    fov: f64,
+    lens_radius: f64,
+    focal_distance: f64,
+}
+
+struct Importer {
+    state: State,
+    stack: Vec<State>,
+    named_materials: HashMap<String, Rc<dyn Material>>,
+    coordinate_systems: HashMap<String, Transform>,
+    camera: Option<PerspectiveCamera>,
+    film: Option<(u32, u32)>,
+    samples_per_pixel: Option<u32>,
+    triangles: Vec<Triangle>,
+    /// Object to world transform, radius and material, spheres are only turned into objects at the end
+    spheres: Vec<(Transform, f64, Rc<dyn Material>)>,
+    lights: Vec<Light>,
+    warned: HashSet<String>,
+}
+
+impl Importer {
+    fn parse_file(&mut self, path: &Path, depth: usize) -> Result<(), ParseError> {
+        let contents = fs::read_to_string(path)?;
+        let tokens = tokenize(path, &contents)?;
+        let error = |line: usize, message: String| ParseLine(path.to_path_buf(), line + 1, message);
+
+        let mut index = 0;
+        while index < tokens.len() {
+            let (Token::Word(directive), line) = &tokens[index] else {
+                return Err(error(tokens[index].1, format!("Expected a directive, found {:?}", tokens[index].0)));
+            };
+            index += 1;
+            // The arguments go on until the next directive
+            let start = index;
+            while index < tokens.len() && !matches!(&tokens[index].0, Token::Word(word) if is_directive(word)) {
+                index += 1;
+            }
+            let arguments = parse_arguments(&tokens[start..index]).map_err(|e| error(*line, e))?;
+            self.directive(directive, &arguments, path, depth).map_err(|e| match e {
+                ParseError::Parse(message) => error(*line, message),
+                e => e,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, directive: &str, arguments: &Arguments, path: &Path, depth: usize) -> Result<(), ParseError> {
+        let numbers = |count: usize| -> Result<Vec<f64>, ParseError> {
+            arguments.all_numbers().filter(|numbers| numbers.len() == count)
+                .ok_or(ParseError::Parse(format!("{directive} expects {count} numbers")))
+        };
+        let text = |index: usize| arguments.text(index)
+            .ok_or(ParseError::Parse(format!("{directive} expects a string")));
+
+        match directive {
+            "Identity" => self.state.transform = Transform::identity(),
+            "Translate" => {
+                let [x, y, z] = numbers(3)?[..] else { unreachable!() };
+                self.apply(Transform::translate(Vec3::new(x, y, z)));
+            }
+            "Scale" => {
+                let [x, y, z] = numbers(3)?[..] else { unreachable!() };
+                self.apply(Transform::scale(Vec3::new(x, y, z)));
+            }
+            "Rotate" => {
+                let [angle, x, y, z] = numbers(4)?[..] else { unreachable!() };
+                self.apply(Transform::rotate(Vec3::new(x, y, z), angle));
+            }
+            "LookAt" => {
+                let [ex, ey, ez, lx, ly, lz, ux, uy, uz] = numbers(9)?[..] else { unreachable!() };
+                self.apply(look_at(Point3::new(ex, ey, ez), Point3::new(lx, ly, lz), Vec3::new(ux, uy, uz))?);
+            }
+            "Transform" | "ConcatTransform" => {
+                // The matrix is given column by column
+                let m = numbers(16)?;
+                let matrix = std::array::from_fn(|row| std::array::from_fn(|column| m[column * 4 + row]));
+                let transform = Transform::from_matrix(matrix)
+                    .ok_or(ParseError::Parse(format!("{directive} matrix cannot be inverted")))?;
+                if directive == "Transform" {
+                    self.state.transform = transform;
+                } else {
+                    self.apply(transform);
+                }
+            }
+            "CoordinateSystem" => {
+                self.coordinate_systems.insert(text(0)?.to_string(), self.state.transform.clone());
+            }
+            "CoordSysTransform" => {
+                let name = text(0)?;
+                match self.coordinate_systems.get(name) {
+                    Some(transform) => self.state.transform = transform.clone(),
+                    None => eprintln!("Warning: unknown coordinate system {name:?} in {path:?}"),
+                }
+            }
+            "Camera" => {
+                // The current transform goes from world to camera space, the "camera" coordinate system is the other way around
+                self.coordinate_systems.insert("camera".to_string(), self.state.transform.inverse());
+                if text(0)? != "perspective" {
+                    self.warn(format!("{} camera", text(0)?), path);
+                    return Ok(());
+                }
+                self.camera = Some(PerspectiveCamera {
+                    transform: self.state.transform.inverse(),
+                    fov: arguments.number("fov").unwrap_or(90.0),
+                    lens_radius: arguments.number("lensradius").unwrap_or(0.0),
+                    focal_distance: arguments.number("focaldistance").unwrap_or(1e6),
+                });
+            }
+            "Film" => {
+                let resolution = |name: &str, default: f64| arguments.number(name).unwrap_or(default).max(1.0) as u32;
+                self.film = Some((resolution("xresolution", 1280.0), resolution("yresolution", 720.0)));
+            }
+            "Sampler" => {
+                self.samples_per_pixel = Some(arguments.number("pixelsamples").unwrap_or(16.0).max(1.0) as u32);
+            }
+            // The world starts with the identity transform
+            "WorldBegin" => self.state.transform = Transform::identity(),
+            "WorldEnd" => {}
+            "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+            "AttributeEnd" | "TransformEnd" => {
+                let saved = self.stack.pop().ok_or(ParseError::Parse(format!("{directive} without a matching begin")))?;
+                if directive == "AttributeEnd" {
+                    self.state = saved;
+                } else {
+                    self.state.transform = saved.transform;
+                }
+            }
+            "Material" => self.state.material = self.material(text(0)?, arguments, path),
+            "MakeNamedMaterial" => {
+                let kind = arguments.string("type").ok_or(ParseError::Parse("Named material has no type".to_string()))?;
+                let material = self.material(kind, arguments, path);
+                self.named_materials.insert(text(0)?.to_string(), material);
+            }
+            "NamedMaterial" => {
+                let name = text(0)?;
+                self.state.material = self.named_materials.get(name).cloned()
+                    .ok_or(ParseError::Parse(format!("Material {name:?} is not defined")))?;
+            }
+            "AreaLightSource" => {
+                if text(0)? != "diffuse" {
+                    self.warn(format!("{} area light", text(0)?), path);
+                    return Ok(());
+                }
+                let scale = arguments.number("scale").unwrap_or(1.0);
+                self.state.area_light = Some(scale * arguments.color("L").unwrap_or(Color::new(1.0, 1.0, 1.0)));
+            }
+            "LightSource" => self.light(text(0)?, arguments, path),
+            "Shape" => self.shape(text(0)?, arguments, path)?,
+            "Include" | "Import" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(ParseError::Parse(format!("Files are included more than {MAX_INCLUDE_DEPTH} levels deep")));
+                }
+                let included = path.parent().unwrap_or(Path::new("")).join(text(0)?);
+                self.parse_file(&included, depth + 1)?;
+            }
+            _ => self.warn(directive.to_string(), path),
+        }
+        Ok(())
+    }
+
+    /// Applies a transform before the current transform
+    fn apply(&mut self, transform: Transform) {
+        self.state.transform = &self.state.transform * &transform;
+    }
+
+    fn warn(&mut self, unsupported: String, path: &Path) {
+        if self.warned.insert(unsupported.clone()) {
+            eprintln!("Warning: ignoring unsupported pbrt {unsupported} in {path:?}");
+        }
+    }
+
+    fn material(&mut self, kind: &str, arguments: &Arguments, path: &Path) -> Rc<dyn Material> {
+        match kind {
+            "conductor" | "coatedconductor" => {
+                let roughness = arguments.number("roughness")
+                    .or(arguments.numbers("uroughness").zip(arguments.numbers("vroughness")).and_then(|(u, v)| Some(0.5 * (u.first()? + v.first()?))))
+                    .unwrap_or(0.0);
+                // Without a reflectance the color is estimated from the name of the metal, copper is the default in pbrt
+                let albedo = arguments.color("reflectance")
+                    .or(arguments.string("eta").and_then(metal_color))
+                    .unwrap_or(Color::new(0.95, 0.64, 0.54));
+                Rc::new(Metal::new(albedo, roughness))
+            }
+            "dielectric" | "thindielectric" => Rc::new(Dielectric::new(arguments.number("eta").unwrap_or(1.5))),
+            _ => {
+                if kind != "diffuse" {
+                    self.warn(format!("{kind} material (using diffuse)"), path);
+                }
+                Rc::new(Lambertian::new(arguments.color("reflectance").unwrap_or(Color::new(0.5, 0.5, 0.5))))
+            }
+        }
+    }
+
+    fn light(&mut self, kind: &str, arguments: &Arguments, path: &Path) {
+        let point = |name: &str, default: Point3| match arguments.numbers(name).as_deref() {
+            Some(&[x, y, z]) => Point3::new(x, y, z),
+            _ => default,
+        };
+        let scale = arguments.number("scale").unwrap_or(1.0);
+        let transform = &self.state.transform;
+        let from = transform.point(point("from", Point3::default()));
+        let light = match kind {
+            "point" => Light::Point { position: from, color: arguments.color("I").unwrap_or(Color::new(1.0, 1.0, 1.0)), intensity: scale },
+            "spot" => {
+                let cone_angle = arguments.number("coneangle").unwrap_or(30.0);
+                let cone_delta = arguments.number("conedelta").unwrap_or(5.0);
+                Light::Spot {
+                    position: from,
+                    direction: (transform.point(point("to", Point3::new(0.0, 0.0, 1.0))) - from).unit(),
+                    color: arguments.color("I").unwrap_or(Color::new(1.0, 1.0, 1.0)),
+                    intensity: scale,
+                    inner_cone_angle: (cone_angle - cone_delta).max(0.0).to_radians(),
+                    outer_cone_angle: cone_angle.to_radians(),
+                }
+            }
+            "distant" => Light::Directional {
+                // Light travels from `from` to `to`
+                direction: (transform.point(point("to", Point3::new(0.0, 0.0, 1.0))) - from).unit(),
+                color: arguments.color("L").unwrap_or(Color::new(1.0, 1.0, 1.0)),
+                intensity: scale,
+            },
+            _ => {
+                self.warn(format!("{kind} light"), path);
+                return;
+            }
+        };
+        self.lights.push(light);
+    }
+
+    fn shape(&mut self, kind: &str, arguments: &Arguments, path: &Path) -> Result<(), ParseError> {
+        let material: Rc<dyn Material> = match self.state.area_light {
+            Some(emit) => Rc::new(DiffuseLight::new(emit)),
+            None => self.state.material.clone(),
+        };
+        let triangles = match kind {
+            "sphere" => {
+                if ["zmin", "zmax", "phimax"].iter().any(|name| arguments.parameter(name).is_some()) {
+                    self.warn("partial sphere (using a full sphere)".to_string(), path);
+                }
+                let radius = arguments.number("radius").unwrap_or(1.0);
+                self.spheres.push((self.state.transform.clone(), radius, material));
+                return Ok(());
+            }
+            "trianglemesh" => triangle_mesh(arguments, material)?,
+            "plymesh" => {
+                let file = arguments.string("filename").ok_or(ParseError::Parse("plymesh has no filename".to_string()))?;
+                read_ply(&path.parent().unwrap_or(Path::new("")).join(file), material)?
+            }
+            _ => {
+                self.warn(format!("{kind} shape"), path);
+                return Ok(());
+            }
+        };
+        let transform = &self.state.transform;
+        self.triangles.extend(triangles.iter().map(|triangle| triangle.transformed(transform)));
+        Ok(())
+    }
+
+    /// pbrt uses a left-handed coordinate system, so the scene is mirrored to get the same image
+    fn finish(self, options: &ImportOptions) -> HittableList {
+        let mirror = Transform::scale(Vec3::new(-1.0, 1.0, 1.0));
+        let corners = |(transform, radius, _): &(Transform, f64, Rc<dyn Material>)| {
+            // The bounding box of the (transformed) sphere, each row of the matrix gives the extent along that axis
+            let center = transform.point(Point3::default());
+            let [x, y, z] = [0, 1, 2].map(|row| radius * transform.matrix()[row][..3].iter().map(|m| m * m).sum::<f64>().sqrt());
+            [center - Vec3::new(x, y, z), center + Vec3::new(x, y, z)]
+        };
+        let positions = self.triangles.iter().flat_map(|triangle| [triangle.a(), triangle.b(), triangle.c()])
+            .chain(self.spheres.iter().flat_map(corners))
+            .map(|p| mirror.point(p));
+        let transform = &options.transform(positions) * &mirror;
+
+        let mut world = HittableList::default();
+        for triangle in &self.triangles {
+            world.add(Rc::new(triangle.transformed(&transform)));
+        }
+        for (object_to_world, radius, material) in self.spheres {
+            let object_to_world = &transform * &object_to_world;
+            match uniform_scale(&object_to_world) {
+                Some(scale) => world.add(Rc::new(Sphere::new(object_to_world.point(Point3::default()), radius * scale, material))),
+                None => world.add(Rc::new(Instance::new(Rc::new(Sphere::new(Point3::default(), radius, material)), object_to_world))),
+            }
+        }
+        world.lights = self.lights.iter().map(|light| light.transformed(&transform)).collect();
+
+        if let Some(camera) = self.camera {
+            let (width, height) = self.film.unwrap_or((16, 9));
+            let aspect_ratio = width as f64 / height as f64;
+            // The field of view is for the shorter side of the image
+            let vfov = if aspect_ratio >= 1.0 {
+                camera.fov
+            } else {
+                2.0 * ((camera.fov.to_radians() / 2.0).tan() / aspect_ratio).atan().to_degrees()
+            };
+            let look_from = camera.transform.point(Point3::default());
+            let settings = CameraSettings {
+                look_from,
+                look_at: look_from + camera.transform.vector(Vec3::new(0.0, 0.0, 1.0)).unit(),
+                v_up: camera.transform.vector(Vec3::new(0.0, 1.0, 0.0)).unit(),
+                vfov,
+                aspect_ratio: Some(aspect_ratio),
+                defocus_angle: Some(2.0 * (camera.lens_radius / camera.focal_distance).atan().to_degrees()),
+                // The focus distance sets the length of the rays, so the default of pbrt is only used with a lens
+                focus_dist: (camera.lens_radius > 0.0).then_some(camera.focal_distance),
+                image_width: self.film.map(|(width, _)| width),
+                samples_per_pixel: self.samples_per_pixel,
+            };
+            world.camera = Some(settings.transformed(&transform));
+        }
+        world
+    }
+}
+
+fn is_directive(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+fn parse_arguments(tokens: &[(Token, usize)]) -> Result<Arguments, String> {
+    let value = |token: &Token| -> Result<Value, String> {
+        match token {
+            // Booleans are kept as text
+            Token::Quoted(text) => Ok(Value::Text(text.clone())),
+            Token::Word(word) if word == "true" || word == "false" => Ok(Value::Text(word.clone())),
+            Token::Word(word) => word.parse().map(Value::Number).map_err(|_| format!("Invalid number {word:?}")),
+            _ => Err(format!("Unexpected {token:?}")),
+        }
+    };
+    // A list or a single value
+    let values = |tokens: &[(Token, usize)], index: &mut usize| -> Result<Vec<Value>, String> {
+        if tokens[*index].0 == Token::Open {
+            let end = tokens[*index..].iter().position(|(token, _)| *token == Token::Close)
+                .ok_or("List is not closed".to_string())? + *index;
+            let list = tokens[*index + 1..end].iter().map(|(token, _)| value(token)).collect();
+            *index = end + 1;
+            list
+        } else {
+            *index += 1;
+            Ok(vec![value(&tokens[*index - 1].0)?])
+        }
+    };
+
+    let mut arguments = Arguments { values: vec![], parameters: vec![] };
+    let mut index = 0;
+    while index < tokens.len() {
+        // Parameters are a quoted type and name, followed by their values
+        if let Token::Quoted(declaration) = &tokens[index].0 {
+            if let [kind, name] = declaration.split_whitespace().collect::<Vec<_>>()[..] {
+                index += 1;
+                if index >= tokens.len() {
+                    return Err(format!("Parameter {name:?} has no value"));
+                }
+                let values = values(tokens, &mut index)?;
+                arguments.parameters.push(Parameter { kind: kind.to_string(), name: name.to_string(), values });
+                continue;
+            }
+        }
+        if !arguments.parameters.is_empty() {
+            return Err(format!("Unexpected {:?} after the parameters", tokens[index].0));
+        }
+        arguments.values.extend(values(tokens, &mut index)?);
+    }
+    Ok(arguments)
+}
+
+fn triangle_mesh(arguments: &Arguments, material: Rc<dyn Material>) -> Result<Vec<Triangle>, ParseError> {
+    let points = |name: &str| arguments.numbers(name).map(|values| values.chunks_exact(3).map(|p| Vec3::new(p[0], p[1], p[2])).collect::<Vec<_>>());
+    let positions = points("P").ok_or(ParseError::Parse("trianglemesh has no positions".to_string()))?;
+    let normals = points("N");
+    let uvs = arguments.numbers("uv").map(|values| values.chunks_exact(2).map(|uv| (uv[0], uv[1])).collect::<Vec<_>>());
+    let indices = match arguments.numbers("indices") {
+        Some(indices) => indices.iter().map(|&index| index as usize).collect::<Vec<_>>(),
+        None if positions.len() == 3 => vec![0, 1, 2],
+        None => return Err(ParseError::Parse("trianglemesh has no indices".to_string())),
+    };
+    if normals.as_ref().is_some_and(|normals| normals.len() != positions.len())
+        || uvs.as_ref().is_some_and(|uvs| uvs.len() != positions.len()) {
+        return Err(ParseError::Parse("trianglemesh should have a normal and uv for every position".to_string()));
+    }
+
+    indices.chunks_exact(3).map(|corners| {
+        let [a, b, c] = [corners[0], corners[1], corners[2]];
+        if corners.iter().any(|&index| index >= positions.len()) {
+            return Err(ParseError::Parse(format!("trianglemesh index out of range, only {} positions", positions.len())));
+        }
+        let mut triangle = Triangle::new(positions[a], positions[b], positions[c], material.clone());
+        if let Some(normals) = &normals {
+            triangle = triangle.with_normals([normals[a], normals[b], normals[c]]);
+        }
+        if let Some(uvs) = &uvs {
+            triangle = triangle.with_uvs([uvs[a], uvs[b], uvs[c]]);
+        }
+        Ok(triangle)
+    }).collect()
+}
+
+/// The LookAt transform of pbrt, from world to camera space
+fn look_at(eye: Point3, look: Point3, up: Vec3) -> Result<Transform, ParseError> {
+    let direction = (look - eye).unit();
+    let right = up.unit().cross(&direction);
+    if right.length() == 0.0 {
+        return Err(ParseError::Parse("LookAt up vector and viewing direction are the same".to_string()));
+    }
+    let right = right.unit();
+    let new_up = direction.cross(&right);
+    // The columns are the camera axes in world space
+    let camera_to_world = [
+        [right.x(), new_up.x(), direction.x(), eye.x()],
+        [right.y(), new_up.y(), direction.y(), eye.y()],
+        [right.z(), new_up.z(), direction.z(), eye.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    Ok(Transform::from_matrix(camera_to_world).unwrap_or_default().inverse())
+}
+
+/// The scale of a transform that only rotates, translates, mirrors and scales the same in every direction
+fn uniform_scale(transform: &Transform) -> Option<f64> {
+    let axes = [Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)].map(|axis| transform.vector(axis));
+    let scale = axes[0].length();
+    let same_length = axes.iter().all(|axis| (axis.length() - scale).abs() <= 1e-9 * scale);
+    let orthogonal = (0..3).all(|i| axes[i].dot(&axes[(i + 1) % 3]).abs() <= 1e-9 * scale * scale);
+    (same_length && orthogonal).then_some(scale)
+}
+
+/// The color of the named metal spectra of pbrt
+fn metal_color(name: &str) -> Option<Color> {
+    let metal = name.strip_prefix("metal-")?.strip_suffix("-eta")?;
+    Some(match metal {
+        "Ag" => Color::new(0.97, 0.96, 0.91),
+        "Al" => Color::new(0.91, 0.92, 0.92),
+        "Au" => Color::new(1.0, 0.78, 0.34),
+        "Cu" => Color::new(0.95, 0.64, 0.54),
+        "CuZn" => Color::new(0.94, 0.80, 0.52),
+        "MgO" | "TiO2" => Color::new(0.9, 0.9, 0.9),
+        _ => return None,
+    })
+}
@@ -0,0 +1,147 @@
+use std::f64::consts::PI;
+
+/// The real roots of a polynomial, in no particular order
+#[derive(Default, Debug)]
+pub struct Roots {
+    values: [f64; 4],
+    len: usize,
+}
+
+impl Roots {
+    fn push(&mut self, value: f64) {
+        if value.is_finite() && self.len < 4 {
+            self.values[self.len] = value;
+            self.len += 1;
+        }
+    }
+
+    pub fn as_slice(&self) -> &[f64] {
+        &self.values[..self.len]
+    }
+}
+
+/// Solves a x^2 + b x + c = 0
+/// Uses the numerically stable form that avoids subtracting two nearly equal numbers
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Roots {
+    let mut roots = Roots::default();
+    if a.abs() < 1e-12 {
+        // Linear equation
+        if b.abs() > 1e-12 { roots.push(-c / b); }
+        return roots;
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return roots;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        // b and c are both zero
+        roots.push(0.0);
+        return roots;
+    }
+    roots.push(q / a);
+    roots.push(c / q);
+    roots
+}
+
+/// Solves x^3 + a x^2 + b x + c = 0
+/// Based on the method in Numerical Recipes (section 5.6)
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Roots {
+    let mut roots = Roots::default();
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let q_cubed = q * q * q;
+
+    if r * r < q_cubed {
+        // Three real roots
+        let theta = (r / q_cubed.sqrt()).clamp(-1.0, 1.0).acos();
+        let factor = -2.0 * q.sqrt();
+        roots.push(factor * (theta / 3.0).cos() - a / 3.0);
+        roots.push(factor * ((theta + 2.0 * PI) / 3.0).cos() - a / 3.0);
+        roots.push(factor * ((theta - 2.0 * PI) / 3.0).cos() - a / 3.0);
+    } else {
+        // One real root
+        let s = -r.signum() * (r.abs() + (r * r - q_cubed).sqrt()).cbrt();
+        let t = if s == 0.0 { 0.0 } else { q / s };
+        roots.push(s + t - a / 3.0);
+    }
+    roots
+}
+
+/// Solves a x^4 + b x^3 + c x^2 + d x + e = 0
+/// Uses Ferrari's method, the roots are refined using Newton's method
+/// since the closed form loses a lot of precision
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Roots {
+    if a.abs() < 1e-12 {
+        return solve_cubic_general(b, c, d, e);
+    }
+    // Normalise to x^4 + b x^3 + c x^2 + d x + e
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // Substitute x = y - b/4 to get the depressed quartic y^4 + p y^2 + q y + r
+    let b_squared = b * b;
+    let p = c - 3.0 * b_squared / 8.0;
+    let q = d - b * c / 2.0 + b_squared * b / 8.0;
+    let r = e - b * d / 4.0 + b_squared * c / 16.0 - 3.0 * b_squared * b_squared / 256.0;
+
+    let mut depressed = Roots::default();
+    if q.abs() < 1e-12 {
+        // Biquadratic: solve for z = y^2
+        for z in solve_quadratic(1.0, p, r).as_slice() {
+            if *z >= 0.0 {
+                depressed.push(z.sqrt());
+                depressed.push(-z.sqrt());
+            }
+        }
+    } else {
+        // Resolvent cubic, any positive root m can be used to split the quartic into two quadratics
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0).as_slice().iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if m <= 0.0 {
+            return Roots::default();
+        }
+        let s = (2.0 * m).sqrt();
+        for root in solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)).as_slice() {
+            depressed.push(*root);
+        }
+        for root in solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)).as_slice() {
+            depressed.push(*root);
+        }
+    }
+
+    let mut roots = Roots::default();
+    for y in depressed.as_slice() {
+        roots.push(polish(y - b / 4.0, &[1.0, b, c, d, e]));
+    }
+    roots
+}
+
+fn solve_cubic_general(a: f64, b: f64, c: f64, d: f64) -> Roots {
+    if a.abs() < 1e-12 {
+        solve_quadratic(b, c, d)
+    } else {
+        solve_cubic(b / a, c / a, d / a)
+    }
+}
+
+/// A few Newton-Raphson iterations on the polynomial with the given coefficients (highest power first)
+fn polish(mut x: f64, coefficients: &[f64]) -> f64 {
+    for _ in 0..4 {
+        // Horner's method for the value and the derivative at the same time
+        let (value, derivative) = coefficients.iter()
+            .fold((0.0, 0.0), |(value, derivative), &coefficient| {
+                (value * x + coefficient, derivative * x + value)
+            });
+        if derivative.abs() < 1e-14 {
+            break;
+        }
+        let step = value / derivative;
+        x -= step;
+        if step.abs() < 1e-12 * x.abs().max(1.0) {
+            break;
+        }
+    }
+    x
+}
@@ -1,9 +1,10 @@
-use crate::utils::rtweekend::AlgorithmOptions::{BvhNaive, BvhSahPlane, BvhSahPosition};
-use clap::{Parser, ValueEnum};
+use crate::utils::mesh::ImportOptions;
+use crate::utils::rtweekend::AlgorithmOptions::{BvhNaive, BvhSahBinned16, BvhSahBinned32, BvhSahPlane, BvhSahPosition, BvhSbvh, BvhLbvh, BvhHlbvh};
+use clap::{Parser, Subcommand, ValueEnum};
 use rand::Rng;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 use std::fmt::{Display, Formatter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Default)]
 pub struct Cli {
@@ -18,15 +19,71 @@ pub struct Cli {
     /// Options for the algorithm
     #[arg(value_enum, long, short)]
     pub options: Vec<AlgorithmOptions>,
+    /// The ray-triangle intersection test
+    #[arg(long, value_enum, default_value_t = TriangleAlgorithm::default())]
+    pub triangle: TriangleAlgorithm,
     /// Grid size (if algorithm is grid)
     #[arg(long, short, default_value_t = 25.0)]
     pub grid_size: f64,
+    /// Threads used to build the BVH (default: one per core)
+    #[arg(long)]
+    pub threads: Option<usize>,
+    /// Maximum number of passes of `-o bvh-optimize` (default: until the SAH cost stops improving)
+    #[arg(long)]
+    pub optimize_iterations: Option<usize>,
+    /// Maximum time of `-o bvh-optimize` in seconds
+    #[arg(long)]
+    pub optimize_seconds: Option<f64>,
+    /// Cost reduction of kd-tree splits that cut off empty space, between 0 and 1 (default: 0.2)
+    #[arg(long)]
+    pub empty_bonus: Option<f64>,
+    /// Octree cells with more objects are split (default: 8)
+    #[arg(long)]
+    pub octree_objects: Option<usize>,
+    /// Maximum depth of the octree (default: 10)
+    #[arg(long)]
+    pub octree_depth: Option<usize>,
     /// Print scene statistics (as LaTeX table row) and exit
     #[arg(long)]
     pub stats: bool,
+    /// Placement of imported meshes
+    #[command(flatten)]
+    pub import: ImportOptions,
     /// Camera position (only for dragon scene)
     #[arg(long)]
     pub camera: Option<usize>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Tasks besides rendering
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// Work with scene files
+    #[command(subcommand)]
+    Scene(SceneCommand),
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum SceneCommand {
+    /// Rewrite scene files in the current version of the format
+    Upgrade {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Convert a scene or mesh file to a binary scene file, which loads faster
+    Convert {
+        /// A scene or mesh file, meshes and included scenes of a json scene are stored in the binary file as well
+        input: PathBuf,
+        /// The binary scene file (`.rtb`)
+        output: PathBuf,
+        /// The format of the input, follows from the extension when it is not given
+        #[arg(long, value_enum)]
+        format: Option<FileFormat>,
+        /// Placement of an imported mesh
+        #[command(flatten)]
+        import: Box<ImportOptions>,
+    },
 }
 
 #[allow(unused)]
@@ -47,6 +104,46 @@ impl Cli {
         }
     }
 
+    pub fn new_from_obj(filename: String) -> Self {
+        Self {
+            filename: Some(filename),
+            format: FileFormat::OBJ,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_from_gltf(filename: String) -> Self {
+        Self {
+            filename: Some(filename),
+            format: FileFormat::GLTF,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_from_stl(filename: String) -> Self {
+        Self {
+            filename: Some(filename),
+            format: FileFormat::STL,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_from_pbrt(filename: String) -> Self {
+        Self {
+            filename: Some(filename),
+            format: FileFormat::PBRT,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_from_binary(filename: String) -> Self {
+        Self {
+            filename: Some(filename),
+            format: FileFormat::Binary,
+            ..Default::default()
+        }
+    }
+
     pub fn add_option(&mut self, option: AlgorithmOptions) {
         self.options.push(option);
     }
@@ -58,7 +155,15 @@ pub enum IntersectionAlgorithm {
     Naive,
     #[default]
     BVH,
+    /// A BVH with 4 children per node, collapsed from the binary BVH
+    BVH4,
+    /// A BVH with 8 children per node, collapsed from the binary BVH
+    BVH8,
     Grid,
+    /// A kd-tree built with the SAH
+    KdTree,
+    /// An octree that splits cells until they have few objects
+    Octree,
 }
 
 impl Display for IntersectionAlgorithm {
@@ -70,19 +175,68 @@ impl Display for IntersectionAlgorithm {
             IntersectionAlgorithm::BVH => {
                 write!(f, "bvh")
             }
+            IntersectionAlgorithm::BVH4 => {
+                write!(f, "bvh4")
+            }
+            IntersectionAlgorithm::BVH8 => {
+                write!(f, "bvh8")
+            }
             IntersectionAlgorithm::Grid => {
                 write!(f, "grid")
             }
+            IntersectionAlgorithm::KdTree => {
+                write!(f, "kd-tree")
+            }
+            IntersectionAlgorithm::Octree => {
+                write!(f, "octree")
+            }
         }
     }
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Default, Copy, Clone, ValueEnum)]
+#[derive(Default, Copy, Clone, Debug, ValueEnum, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum FileFormat {
     #[default]
     Native,
     PLY,
+    /// Wavefront OBJ, with materials from MTL files
+    OBJ,
+    /// glTF 2.0, as `.gltf` or binary `.glb`
+    GLTF,
+    /// STL, ascii or binary
+    STL,
+    /// A subset of pbrt-v4 scenes
+    PBRT,
+    /// The binary scene format of this raytracer (`.rtb`), see `scene convert`
+    Binary,
+}
+
+impl FileFormat {
+    /// The format of a file, from its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(FileFormat::Native),
+            "ply" => Some(FileFormat::PLY),
+            "obj" => Some(FileFormat::OBJ),
+            "gltf" | "glb" => Some(FileFormat::GLTF),
+            "stl" => Some(FileFormat::STL),
+            "pbrt" => Some(FileFormat::PBRT),
+            "rtb" => Some(FileFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, ValueEnum, Debug, PartialEq)]
+pub enum TriangleAlgorithm {
+    /// Möller-Trumbore, fast but rays can slip through shared edges
+    #[default]
+    MollerTrumbore,
+    /// Watertight intersection by Woop et al., rays never slip between adjacent triangles
+    Watertight,
 }
 
 #[derive(Default, Clone, Debug)]
@@ -90,7 +244,14 @@ pub struct Options {
     pub algorithm: IntersectionAlgorithm,
     pub options: Vec<AlgorithmOptions>,
     pub draw_boxes: bool,
+    pub triangle: TriangleAlgorithm,
     pub grid_size: f64,
+    pub threads: Option<usize>,
+    pub optimize_iterations: Option<usize>,
+    pub optimize_seconds: Option<f64>,
+    pub empty_bonus: Option<f64>,
+    pub octree_objects: Option<usize>,
+    pub octree_depth: Option<usize>,
     pub camera: Option<usize>,
 }
 
@@ -104,6 +265,27 @@ impl Display for Options {
         if self.algorithm == IntersectionAlgorithm::Grid {
             option_strs.push(format!("size={}", self.grid_size));
         }
+        if self.triangle == TriangleAlgorithm::Watertight {
+            option_strs.push("watertight".to_string());
+        }
+        if let Some(threads) = self.threads {
+            option_strs.push(format!("threads={threads}"));
+        }
+        if let Some(iterations) = self.optimize_iterations {
+            option_strs.push(format!("iterations={iterations}"));
+        }
+        if let Some(seconds) = self.optimize_seconds {
+            option_strs.push(format!("seconds={seconds}"));
+        }
+        if let Some(bonus) = self.empty_bonus {
+            option_strs.push(format!("bonus={bonus}"));
+        }
+        if let Some(objects) = self.octree_objects {
+            option_strs.push(format!("objects={objects}"));
+        }
+        if let Some(depth) = self.octree_depth {
+            option_strs.push(format!("depth={depth}"));
+        }
         if let Some(pos) = self.camera {
             option_strs.push(format!("pos{}", pos));
         }
@@ -127,7 +309,14 @@ impl Options {
             algorithm: args.algorithm,
             draw_boxes: args.options.contains(&AlgorithmOptions::DrawBoxes),
             options: args.options.clone(),
+            triangle: args.triangle,
             grid_size: args.grid_size,
+            threads: args.threads,
+            optimize_iterations: args.optimize_iterations,
+            optimize_seconds: args.optimize_seconds,
+            empty_bonus: args.empty_bonus,
+            octree_objects: args.octree_objects,
+            octree_depth: args.octree_depth,
             camera: args.camera,
         }
     }
@@ -142,10 +331,22 @@ pub enum AlgorithmOptions {
     BvhSahPlane,
     /// BVH with SAH for the plane and the split position (default)
     BvhSahPosition,
+    /// BVH with SAH evaluated between 16 bins of the centroids, much faster to build than `BvhSahPosition`
+    BvhSahBinned16,
+    /// BVH with SAH evaluated between 32 bins of the centroids
+    BvhSahBinned32,
+    /// Spatial split BVH (SBVH), binned SAH that can also split triangles, overlapping nodes less
+    BvhSbvh,
+    /// Linear BVH (LBVH), the objects sorted by 63-bit Morton codes and split where the codes differ, fast to build
+    BvhLbvh,
+    /// Hierarchical LBVH (HLBVH), LBVH with the top levels built by binned SAH
+    BvhHlbvh,
+    /// Improve the BVH after it is built by reinserting its worst nodes, can be used with every BVH option
+    BvhOptimize,
     /// Draw bounding boxes
     DrawBoxes,
 }
-const BVH_OPTIONS: &[AlgorithmOptions] = &[BvhNaive, BvhSahPlane, BvhSahPosition];
+const BVH_OPTIONS: &[AlgorithmOptions] = &[BvhNaive, BvhSahPlane, BvhSahPosition, BvhSahBinned16, BvhSahBinned32, BvhSbvh, BvhLbvh, BvhHlbvh];
 
 pub fn check_valid_options(options: &[AlgorithmOptions]) -> Option<String> {
     // Only one of the different Bvh options is allowed
@@ -0,0 +1,77 @@
+use crate::hittable::hittable_list::HittableList;
+use crate::utils::parser::ParseError;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The version of the scene files that are written
+/// 1. The first format, without a version, triangles store their centroid
+/// 2. Adds the version and the table of materials, triangles no longer store their centroid
+pub const SCENE_VERSION: u64 = 2;
+
+/// The migrations from every older version to the next one, `MIGRATIONS[0]` upgrades version 1 to 2
+const MIGRATIONS: [fn(&mut Value); SCENE_VERSION as usize - 1] = [drop_centroids];
+
+/// The version of a scene file, files without a version are version 1
+pub fn version(scene: &Value) -> Result<u64, String> {
+    match scene.get("version") {
+        None => Ok(1),
+        Some(version) => version.as_u64().filter(|&version| version > 0)
+            .ok_or(format!("Scene version should be a positive integer, found {version}")),
+    }
+}
+
+/// Upgrades a scene to the current version, returns the version it had before
+/// Scenes of a newer version than this program supports are rejected
+pub fn migrate(scene: &mut Value) -> Result<u64, String> {
+    let version = version(scene)?;
+    if version > SCENE_VERSION {
+        return Err(format!("Scene version {version} is newer than the supported version {SCENE_VERSION}"));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(scene);
+    }
+    if let Some(scene) = scene.as_object_mut() {
+        scene.insert("version".to_string(), SCENE_VERSION.into());
+    }
+    Ok(version)
+}
+
+/// Rewrites a scene file in the current version, returns the version it had before
+/// Files that are up to date are left alone, included scenes and meshes are not touched
+pub fn upgrade(path: &Path) -> Result<u64, ParseError> {
+    let mut scene: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let version = version(&scene).map_err(ParseError::Parse)?;
+    if version == SCENE_VERSION {
+        return Ok(version);
+    }
+    // Only the json is migrated, reading the scene would need the materials of the files it includes
+    migrate(&mut scene).map_err(ParseError::Parse)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &scene)?;
+    writer.flush()?;
+    Ok(version)
+}
+
+/// Writes a scene file in the current version
+pub fn save(world: &HittableList, path: &Path) -> Result<(), ParseError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, world)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Version 1 to 2: triangles compute their centroid instead of storing it
+fn drop_centroids(scene: &mut Value) {
+    match scene {
+        Value::Object(map) => {
+            if map.get("type").and_then(Value::as_str) == Some("Triangle") {
+                map.remove("centroid");
+            }
+            map.values_mut().for_each(drop_centroids);
+        }
+        Value::Array(values) => values.iter_mut().for_each(drop_centroids),
+        _ => {}
+    }
+}
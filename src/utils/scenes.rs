@@ -1,11 +1,24 @@
 use std::rc::Rc;
+use crate::camera::CameraSettings;
 use crate::value::color::Color;
 use crate::hittable::hittable_list::HittableList;
 use crate::value::material::{Dielectric, Lambertian, Metal};
 use crate::utils::rtweekend::{random_double, random_double_range};
 use crate::hittable::sphere::Sphere;
 use crate::hittable::triangle::Triangle;
-use crate::value::vec3::Point3;
+use crate::hittable::quad::Quad;
+use crate::hittable::disk::Disk;
+use crate::hittable::cuboid::Cuboid;
+use crate::hittable::plane::Plane;
+use crate::hittable::instance::Instance;
+use crate::hittable::cylinder::Cylinder;
+use crate::hittable::cone::Cone;
+use crate::hittable::paraboloid::Paraboloid;
+use crate::hittable::torus::Torus;
+use crate::hittable::csg::{Csg, CsgOperation};
+use crate::hittable::sdf::{Sdf, SdfShape};
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
 
 #[allow(dead_code)]
 pub fn weekend_final() -> (HittableList, String) {
@@ -59,6 +72,18 @@ pub fn weekend_custom(small_sphere_multiplier: i32, diffuse_prob: f64, mat_prob:
     let material_3 = Rc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
     world.add(Rc::new(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, material_3)));
 
+    world.camera = Some(CameraSettings {
+        look_from: Point3::new(13.0, 2.0, 3.0),
+        look_at: Point3::new(0.0, 0.0, 0.0),
+        v_up: Vec3::new(0.0, 1.0, 0.0),
+        vfov: 20.0,
+        aspect_ratio: None,
+        defocus_angle: None,
+        focus_dist: None,
+        image_width: None,
+        samples_per_pixel: None,
+    });
+
     (world, format!("weekend-{small_sphere_multiplier}-{}-{}", (diffuse_prob * 100.0).round() as u32, (mat_prob * 100.0).round() as u32))
 }
 
@@ -183,3 +208,167 @@ pub fn triangle_test() -> (HittableList, String) {
     
     (world, "triangle_test".to_string())
 }
+
+#[allow(dead_code)]
+pub fn primitives() -> (HittableList, String) {
+    let mut world = HittableList::default();
+    let material_ground = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let material_red = Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1)));
+    let material_blue = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+    let material_metal = Rc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 0.1));
+    let material_glass = Rc::new(Dielectric::new(1.5));
+
+    world.add(Rc::new(Plane::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), material_ground)));
+
+    // A mirror in the back
+    world.add(Rc::new(Quad::new(Point3::new(-2.0, -0.5, -3.0), Vec3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), material_metal)));
+
+    world.add(Rc::new(Disk::new(Point3::new(-1.2, 0.0, -1.6), Vec3::new(1.0, 0.3, 1.0), 0.4, material_red.clone())));
+    world.add(Rc::new(Cuboid::new(Point3::new(-0.35, -0.5, -2.0), Point3::new(0.35, 0.2, -1.3), material_blue)));
+    world.add(Rc::new(Cuboid::oriented(
+        Point3::new(1.2, -0.1, -1.6), Vec3::new(0.3, 0.3, 0.3),
+        Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 1.0), material_red,
+    )));
+    world.add(Rc::new(Sphere::new(Point3::new(0.5, -0.25, -1.0), 0.25, material_glass)));
+
+    (world, "primitives".to_string())
+}
+
+#[allow(dead_code)]
+pub fn quadrics() -> (HittableList, String) {
+    let mut world = HittableList::default();
+    let material_ground = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let material_red = Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1)));
+    let material_blue = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+    let material_gold = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.2));
+    let material_glass = Rc::new(Dielectric::new(1.5));
+
+    world.add(Rc::new(Plane::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), material_ground)));
+
+    let cylinder = Rc::new(Cylinder::new(0.3, 0.8, true, material_blue));
+    world.add(Rc::new(Instance::new(cylinder, Transform::translate(Vec3::new(-1.4, -0.5, -2.0)))));
+
+    let cone = Rc::new(Cone::new(0.35, 0.9, true, material_red));
+    world.add(Rc::new(Instance::new(cone, Transform::translate(Vec3::new(-0.5, -0.5, -1.8)))));
+
+    // An open bowl, upside down
+    let paraboloid = Rc::new(Paraboloid::new(0.35, 0.5, false, material_gold));
+    let transform = Transform::translate(Vec3::new(0.4, 0.2, -1.9)) * Transform::rotate(Vec3::new(1.0, 0.0, 0.0), 180.0);
+    world.add(Rc::new(Instance::new(paraboloid, transform)));
+
+    // The same torus, once standing up and once lying down
+    let torus = Rc::new(Torus::new(0.3, 0.1, material_glass));
+    let transform = Transform::translate(Vec3::new(1.3, -0.1, -1.6)) * Transform::rotate(Vec3::new(1.0, 0.0, 0.2), 70.0);
+    world.add(Rc::new(Instance::new(torus.clone(), transform)));
+    let transform = Transform::translate(Vec3::new(0.0, -0.42, -1.1)) * Transform::scale(Vec3::new(0.8, 0.8, 0.8));
+    world.add(Rc::new(Instance::new(torus, transform)));
+
+    (world, "quadrics".to_string())
+}
+
+#[allow(dead_code)]
+pub fn csg() -> (HittableList, String) {
+    let mut world = HittableList::default();
+    let material_ground = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let material_red = Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1)));
+    let material_blue = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+    let material_steel = Rc::new(Metal::new(Color::new(0.7, 0.7, 0.75), 0.15));
+    let material_glass = Rc::new(Dielectric::new(1.5));
+
+    world.add(Rc::new(Plane::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), material_ground)));
+
+    // A biconvex lens: the intersection of two large spheres
+    let lens = Csg::new(
+        CsgOperation::Intersection,
+        Rc::new(Sphere::new(Point3::new(-2.1, 0.0, -2.0), 1.0, material_glass.clone())),
+        Rc::new(Sphere::new(Point3::new(-0.3, 0.0, -2.0), 1.0, material_glass)),
+    );
+    world.add(Rc::new(lens));
+
+    // A machined block: a box with a hole drilled through it and rounded-off corners
+    let block = Rc::new(Cuboid::new(Point3::new(-0.4, -0.5, -2.2), Point3::new(0.4, 0.1, -1.4), material_steel));
+    let drill = Rc::new(Instance::new(
+        Rc::new(Cylinder::new(0.15, 1.5, true, material_red.clone())),
+        Transform::translate(Vec3::new(0.0, -0.2, -2.6)) * Transform::rotate(Vec3::new(1.0, 0.0, 0.0), 90.0),
+    ));
+    let drilled = Rc::new(Csg::new(CsgOperation::Difference, block, drill));
+    let rounded = Csg::new(
+        CsgOperation::Intersection,
+        drilled,
+        Rc::new(Sphere::new(Point3::new(0.0, -0.2, -1.8), 0.6, material_red.clone())),
+    );
+    world.add(Rc::new(rounded));
+
+    // A union of a sphere and a box, cut in half by a plane
+    let union = Rc::new(Csg::new(
+        CsgOperation::Union,
+        Rc::new(Sphere::new(Point3::new(1.2, -0.1, -1.8), 0.35, material_blue.clone())),
+        Rc::new(Cuboid::new(Point3::new(1.0, -0.5, -2.0), Point3::new(1.6, -0.2, -1.4), material_red)),
+    ));
+    let cut = Csg::new(
+        CsgOperation::Intersection,
+        union,
+        Rc::new(Plane::new(Point3::new(1.2, 0.0, -1.8), Vec3::new(1.0, 0.0, 1.0), material_blue)),
+    );
+    world.add(Rc::new(cut));
+
+    (world, "csg".to_string())
+}
+
+#[allow(dead_code)]
+pub fn sdf() -> (HittableList, String) {
+    let mut world = HittableList::default();
+    let material_ground = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
+    let material_red = Rc::new(Lambertian::new(Color::new(0.8, 0.2, 0.1)));
+    let material_blue = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+    let material_gold = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.1));
+    let material_glass = Rc::new(Dielectric::new(1.5));
+
+    world.add(Rc::new(Plane::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 1.0, 0.0), material_ground)));
+
+    let sphere = |radius: f64, x: f64, y: f64, z: f64| Box::new(Sdf::Translate {
+        offset: Vec3::new(x, y, z),
+        shape: Box::new(Sdf::Sphere { radius }),
+    });
+
+    // An organic blob of smoothly merged spheres
+    let blob = Sdf::SmoothUnion {
+        a: Box::new(Sdf::SmoothUnion { a: sphere(0.3, -1.4, -0.2, -2.0), b: sphere(0.2, -1.1, 0.1, -1.9), k: 0.2 }),
+        b: sphere(0.15, -1.5, 0.15, -1.8),
+        k: 0.15,
+    };
+    world.add(Rc::new(SdfShape::new(blob, material_red)));
+
+    // A twisted rounded column
+    let column = Sdf::Translate {
+        offset: Vec3::new(-0.4, 0.0, -2.2),
+        shape: Box::new(Sdf::Twist {
+            amount: 2.0,
+            shape: Box::new(Sdf::RoundBox { half_extents: Vec3::new(0.15, 0.5, 0.15), radius: 0.04 }),
+        }),
+    };
+    world.add(Rc::new(SdfShape::new(column, material_gold)));
+
+    // A box with a repeated pattern of holes
+    let sponge = Sdf::Translate {
+        offset: Vec3::new(0.5, -0.2, -1.9),
+        shape: Box::new(Sdf::Subtract {
+            a: Box::new(Sdf::Box { half_extents: Vec3::new(0.3, 0.3, 0.3) }),
+            b: Box::new(Sdf::Repeat {
+                spacing: Vec3::new(0.2, 0.2, 0.2),
+                limit: [1, 1, 1],
+                shape: Box::new(Sdf::Sphere { radius: 0.13 }),
+            }),
+        }),
+    };
+    world.add(Rc::new(SdfShape::new(sponge, material_blue)));
+
+    // A glass ring
+    let torus = Sdf::Translate {
+        offset: Vec3::new(1.3, -0.1, -2.0),
+        shape: Box::new(Sdf::Torus { major_radius: 0.3, minor_radius: 0.1 }),
+    };
+    world.add(Rc::new(SdfShape::new(torus, material_glass)));
+
+    (world, "sdf".to_string())
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::triangle::Triangle;
+use crate::utils::mesh::ImportOptions;
+use crate::utils::parser::ParseError;
+use crate::utils::parser::ParseError::{ParseElement, ParseLine};
+use crate::value::color::Color;
+use crate::value::material::{Lambertian, Material};
+use crate::value::vec3::{Point3, Vec3};
+
+/// Faces meeting at a sharper angle (in degrees) keep a hard edge
+const CREASE_ANGLE: f64 = 45.0;
+
+/// Loads an STL file, either ascii or binary
+/// Duplicate vertices are welded to compute smooth normals, facet colors (VisCAM/SolidView or Materialise Magics)
+/// become Lambertian materials, facets without a color get `mat`
+pub fn parse_stl(path: &PathBuf, mat: Rc<dyn Material>, options: &ImportOptions) -> Result<HittableList, ParseError> {
+    let contents = fs::read(path)?;
+    let facets = if is_binary(&contents) {
+        parse_binary(&contents)?
+    } else {
+        let text = String::from_utf8_lossy(&contents);
+        parse_ascii(path, &text)?
+    };
+
+    let mut materials: HashMap<[u8; 3], Rc<dyn Material>> = HashMap::new();
+    let mut mesh = Mesh::default();
+    let mut disagreeing_normals = 0;
+    let mut degenerate = 0;
+    for facet in &facets {
+        let normal = (facet.vertices[1] - facet.vertices[0]).cross(&(facet.vertices[2] - facet.vertices[0]));
+        if normal.length() == 0.0 {
+            degenerate += 1;
+            continue;
+        }
+        // The winding is used, a normal of zero means the exporter left it to the winding
+        if facet.normal.dot(&normal) < 0.0 {
+            disagreeing_normals += 1;
+        }
+        let material = match facet.color {
+            // The colors are stored with sRGB gamma, the renderer works in linear space (with gamma 2)
+            Some(color) => materials.entry(color).or_insert_with(|| {
+                let [r, g, b] = color.map(|c| (c as f64 / 255.0).powi(2));
+                Rc::new(Lambertian::new(Color::new(r, g, b)))
+            }).clone(),
+            None => mat.clone(),
+        };
+        mesh.add(facet.vertices, normal.unit(), material);
+    }
+    if disagreeing_normals > 0 {
+        eprintln!("Warning: {disagreeing_normals} facet normals in {path:?} point the other way than their winding, using the winding");
+    }
+    if degenerate > 0 {
+        eprintln!("Warning: skipped {degenerate} facets without an area in {path:?}");
+    }
+
+    let mut world = HittableList::default();
+    options.add_triangles(&mut world, mesh.triangles());
+    Ok(world)
+}
+
+struct Facet {
+    normal: Vec3,
+    vertices: [Point3; 3],
+    color: Option<[u8; 3]>,
+}
+
+/// A binary file has an 80 byte header, a facet count and 50 bytes per facet
+/// Some binary files start with "solid" as well, so the size is checked first
+fn is_binary(contents: &[u8]) -> bool {
+    if contents.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes([contents[80], contents[81], contents[82], contents[83]]) as usize;
+    contents.len() == 84 + 50 * count || !contents.trim_ascii_start().starts_with(b"solid")
+}
+
+fn parse_binary(contents: &[u8]) -> Result<Vec<Facet>, ParseError> {
+    let count = u32::from_le_bytes([contents[80], contents[81], contents[82], contents[83]]) as usize;
+    // Materialise Magics files have "COLOR=" and the default color (RGBA) in the header, and the channels in a different order
+    let magics = contents[..80].windows(10).find(|window| window.starts_with(b"COLOR="))
+        .map(|window| [window[6], window[7], window[8]]);
+
+    (0..count).map(|index| {
+        let facet = contents.get(84 + 50 * index..84 + 50 * (index + 1))
+            .ok_or(ParseElement("facet", index, format!("File ends before facet {index}")))?;
+        let float = |i: usize| f32::from_le_bytes([facet[4 * i], facet[4 * i + 1], facet[4 * i + 2], facet[4 * i + 3]]) as f64;
+        let vector = |i: usize| Vec3::new(float(3 * i), float(3 * i + 1), float(3 * i + 2));
+        let vertices = [vector(1), vector(2), vector(3)];
+        if vertices.iter().any(|v| !(v.x().is_finite() && v.y().is_finite() && v.z().is_finite())) {
+            return Err(ParseElement("facet", index, "Vertex is not a finite number".to_string()));
+        }
+
+        let attribute = u16::from_le_bytes([facet[48], facet[49]]);
+        // 5-bit channels, scaled up to 8 bits
+        let channel = |shift: u16| {
+            let c = ((attribute >> shift) & 0x1f) as u8;
+            (c << 3) | (c >> 2)
+        };
+        let color = match magics {
+            // Bit 15 is cleared when the facet has its own color
+            Some(default) => Some(if attribute & 0x8000 == 0 { [channel(0), channel(5), channel(10)] } else { default }),
+            None => (attribute & 0x8000 != 0).then(|| [channel(10), channel(5), channel(0)]),
+        };
+        Ok(Facet { normal: vector(0), vertices, color })
+    }).collect()
+}
+
+fn parse_ascii(path: &Path, contents: &str) -> Result<Vec<Facet>, ParseError> {
+    let error = |line: usize, message: String| ParseLine(path.to_path_buf(), line + 1, message);
+    let vector = |line: usize, arguments: &[&str]| -> Result<Vec3, ParseError> {
+        let values = arguments.iter()
+            .map(|value| value.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or(error(line, format!("Invalid number {value:?}"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x, y, z] = values[..] else {
+            return Err(error(line, format!("Expected 3 values, found {}", values.len())));
+        };
+        Ok(Vec3::new(x, y, z))
+    };
+
+    let mut facets = vec![];
+    let mut normal = None;
+    let mut vertices = vec![];
+    for (line_number, line) in contents.lines().enumerate() {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        match tokens[..] {
+            ["facet", "normal", ref arguments @ ..] => {
+                if normal.is_some() {
+                    return Err(error(line_number, "Facet inside a facet".to_string()));
+                }
+                normal = Some(vector(line_number, arguments)?);
+            }
+            ["vertex", ref arguments @ ..] => {
+                if normal.is_none() {
+                    return Err(error(line_number, "Vertex outside a facet".to_string()));
+                }
+                vertices.push(vector(line_number, arguments)?);
+            }
+            ["endfacet"] => {
+                let Some(facet_normal) = normal.take() else {
+                    return Err(error(line_number, "endfacet without a facet".to_string()));
+                };
+                let [a, b, c] = vertices[..] else {
+                    return Err(error(line_number, format!("Facet should have 3 vertices, found {}", vertices.len())));
+                };
+                facets.push(Facet { normal: facet_normal, vertices: [a, b, c], color: None });
+                vertices.clear();
+            }
+            ["solid", ..] | ["endsolid", ..] | ["outer", "loop"] | ["endloop"] | [] => {}
+            _ => return Err(error(line_number, format!("Unexpected {:?}", line.trim()))),
+        }
+    }
+    if normal.is_some() {
+        return Err(error(contents.lines().count().saturating_sub(1), "File ends inside a facet".to_string()));
+    }
+    Ok(facets)
+}
+
+/// Triangles with welded vertices, so the normals of the faces around a vertex can be averaged
+#[derive(Default)]
+struct Mesh {
+    positions: Vec<Point3>,
+    /// Index of each position, by the bits of the coordinates
+    welded: HashMap<[u64; 3], usize>,
+    faces: Vec<([usize; 3], Vec3, Rc<dyn Material>)>,
+    /// The faces using each vertex
+    adjacent: Vec<Vec<usize>>,
+}
+
+impl Mesh {
+    fn add(&mut self, vertices: [Point3; 3], normal: Vec3, material: Rc<dyn Material>) {
+        let face = self.faces.len();
+        let indices = vertices.map(|vertex| {
+            // Adding zero turns -0.0 into 0.0, so both are welded
+            let key = [vertex.x(), vertex.y(), vertex.z()].map(|c| (c + 0.0).to_bits());
+            let index = *self.welded.entry(key).or_insert_with(|| {
+                self.positions.push(vertex);
+                self.adjacent.push(vec![]);
+                self.positions.len() - 1
+            });
+            self.adjacent[index].push(face);
+            index
+        });
+        self.faces.push((indices, normal, material));
+    }
+
+    /// Every corner gets the area weighted average of the normals of the faces around it within the crease angle
+    fn triangles(&self) -> Vec<Triangle> {
+        let crease = CREASE_ANGLE.to_radians().cos();
+        self.faces.iter().map(|(indices, normal, material)| {
+            let normals = indices.map(|index| {
+                self.adjacent[index].iter()
+                    .map(|&other| &self.faces[other])
+                    .filter(|(_, other_normal, _)| other_normal.dot(normal) >= crease)
+                    .map(|([a, b, c], _, _)| (self.positions[*b] - self.positions[*a]).cross(&(self.positions[*c] - self.positions[*a])))
+                    .fold(Vec3::default(), |sum, weighted| sum + weighted)
+            });
+            let [a, b, c] = indices.map(|index| self.positions[index]);
+            Triangle::new(a, b, c, material.clone()).with_normals(normals)
+        }).collect()
+    }
+}
@@ -3,4 +3,6 @@ pub mod color;
 pub mod ray;
 pub mod interval;
 pub mod material;
-pub mod data;
\ No newline at end of file
+pub mod data;
+pub mod transform;pub mod texture;
+pub mod light;
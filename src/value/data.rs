@@ -21,6 +21,19 @@ pub struct Data {
     image_height: u32,
     samples_per_pixel: u32,
     max_depth: u32,
+    /// New fields go at the end, `write_to_csv()` appends rows to a file that can have the header of fewer fields
+    load_time: f64,
+    /// Expected cost of a ray through the BVH, see `Bvh::sah_cost()`
+    sah_cost: f64,
+    /// The number of frames after the first, see `HittableList::update()`
+    /// Only animations through the API have frames, a render from the command line does not, so they are not in the csv
+    #[serde(skip)]
+    frames: usize,
+    /// Time spent updating the acceleration structure for those frames
+    #[serde(skip)]
+    frame_time: f64,
+    /// The SAH cost before `-o bvh-optimize`, 0 without it
+    unoptimized_sah_cost: f64,
 }
 
 impl Data {
@@ -47,7 +60,15 @@ impl Data {
         println!("Total intersection checks: {}", self.intersection_checks());
         println!("Total gridbox intersection checks: {}", self.gridbox_intersection_checks());
         println!("Total traversal steps: {}", self.traversal_steps());
+        println!("Total load time: {}", self.load_time());
         println!("Total init time: {}", self.init_time());
+        println!("BVH SAH cost: {}", self.sah_cost());
+        if self.unoptimized_sah_cost() > 0.0 {
+            println!("BVH SAH cost before optimisation: {}", self.unoptimized_sah_cost());
+        }
+        if self.frames() > 0 {
+            println!("Average update time of {} frames: {}", self.frames(), self.frame_time() / self.frames() as f64);
+        }
         println!("Total time: {}", self.seconds());
     }
 
@@ -69,6 +90,10 @@ impl Data {
         writer.flush().expect("Failed to write CSV data");
     }
 
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
     pub fn seconds(&self) -> f64 {
         self.seconds
     }
@@ -77,6 +102,15 @@ impl Data {
         self.seconds = seconds;
     }
 
+    /// Time spent reading the scene and mesh files, or generating the scene
+    pub fn load_time(&self) -> f64 {
+        self.load_time
+    }
+
+    pub fn set_load_time(&mut self, seconds: f64) {
+        self.load_time = seconds;
+    }
+
     pub fn init_time(&self) -> f64 {
         self.init_time
     }
@@ -84,6 +118,38 @@ impl Data {
     pub fn set_init_time(&mut self, seconds: f64) {
         self.init_time = seconds;
     }
+
+    pub fn sah_cost(&self) -> f64 {
+        self.sah_cost
+    }
+
+    pub fn set_sah_cost(&mut self, cost: f64) {
+        self.sah_cost = cost;
+    }
+
+    pub fn unoptimized_sah_cost(&self) -> f64 {
+        self.unoptimized_sah_cost
+    }
+
+    pub fn set_unoptimized_sah_cost(&mut self, cost: f64) {
+        self.unoptimized_sah_cost = cost;
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Total time spent updating the acceleration structure between frames
+    pub fn frame_time(&self) -> f64 {
+        self.frame_time
+    }
+
+    /// Counts a frame that took `seconds` to update the acceleration structure
+    pub fn add_frame(&mut self, seconds: f64) {
+        self.frames += 1;
+        self.frame_time += seconds;
+    }
+
     pub fn primary_rays(&self) -> usize {
         self.primary_rays
     }
@@ -0,0 +1,68 @@
+use crate::value::color::Color;
+use crate::value::transform::Transform;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// A light without a surface, so it can only be found by sampling it directly
+/// The intensity is in candela for point and spot lights, and in lux for directional lights
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Light {
+    Point { position: Point3, color: Color, intensity: f64 },
+    /// `direction` is the direction the light travels in
+    Directional { direction: Vec3, color: Color, intensity: f64 },
+    /// The light fades out between the inner and outer cone angles (in radians)
+    Spot { position: Point3, direction: Vec3, color: Color, intensity: f64, inner_cone_angle: f64, outer_cone_angle: f64 },
+}
+
+impl Light {
+    /// The unit direction from `p` to the light, the distance to the light and the light arriving at `p`
+    pub fn sample(&self, p: Point3) -> Option<(Vec3, f64, Color)> {
+        match self {
+            Light::Point { position, color, intensity } => {
+                let (to_light, distance) = towards(p, *position)?;
+                Some((to_light, distance, *intensity / (distance * distance) * *color))
+            }
+            Light::Directional { direction, color, intensity } => {
+                Some((-direction.unit(), f64::INFINITY, *intensity * *color))
+            }
+            Light::Spot { position, direction, color, intensity, inner_cone_angle, outer_cone_angle } => {
+                let (to_light, distance) = towards(p, *position)?;
+                // Smooth falloff between the cones, as in the glTF KHR_lights_punctual extension
+                let cos_angle = (-to_light).dot(&direction.unit());
+                let (cos_inner, cos_outer) = (inner_cone_angle.cos(), outer_cone_angle.cos());
+                let scale = 1.0 / f64::max(1e-4, cos_inner - cos_outer);
+                let falloff = ((cos_angle - cos_outer) * scale).clamp(0.0, 1.0);
+                if falloff <= 0.0 {
+                    return None;
+                }
+                Some((to_light, distance, falloff * falloff * *intensity / (distance * distance) * *color))
+            }
+        }
+    }
+}
+
+impl Light {
+    /// The light moved by `transform`, point and spot lights are scaled so they light a scaled scene the same
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let area = transform.determinant().abs().powf(2.0 / 3.0);
+        match self.clone() {
+            Light::Point { position, color, intensity } => Light::Point { position: transform.point(position), color, intensity: intensity * area },
+            Light::Directional { direction, color, intensity } => Light::Directional { direction: transform.vector(direction).unit(), color, intensity },
+            Light::Spot { position, direction, color, intensity, inner_cone_angle, outer_cone_angle } => Light::Spot {
+                position: transform.point(position),
+                direction: transform.vector(direction).unit(),
+                color,
+                intensity: intensity * area,
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        }
+    }
+}
+
+fn towards(from: Point3, to: Point3) -> Option<(Vec3, f64)> {
+    let offset = to - from;
+    let distance = offset.length();
+    (distance > 0.0).then(|| (offset / distance, distance))
+}
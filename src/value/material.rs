@@ -1,15 +1,22 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use std::collections::HashMap;
+use std::fmt;
 use crate::value::color::Color;
 use crate::hittable::HitRecord;
 use crate::value::ray::Ray;
 use crate::utils::rtweekend::random_double;
+use crate::value::texture::Texture;
 use crate::value::vec3::Vec3;
+use std::rc::Rc;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum MaterialType {
     Lambertian,
     Metal,
     Dielectric,
+    DiffuseLight,
 }
 
 #[typetag::serde(tag = "type")]
@@ -17,16 +24,199 @@ pub trait Material {
     fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool;
 
     fn get_type(&self) -> MaterialType;
+
+    /// Light given off by the material itself
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::default()
+    }
+}
+
+/// A material in a scene file: the index or name of a material in the `materials` of the scene, or the material itself
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MaterialReference {
+    Index(usize),
+    Named(String),
+    Inline(Rc<dyn Material>),
+}
+
+/// The materials of a scene, so primitives can share them
+/// Written as a map from name to material, the index of a material is its position in the map
+#[derive(Clone, Default)]
+pub struct MaterialTable {
+    entries: Vec<(String, Rc<dyn Material>)>,
+    /// The index of each material, by its address
+    indices: HashMap<*const (), usize>,
+    /// The index of each material, by its json, so equal materials are written once
+    values: HashMap<String, usize>,
+    /// The number of entries in `values`
+    valued: usize,
+}
+
+impl MaterialTable {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Material>> {
+        self.entries.iter().find(|(entry, _)| entry == name).map(|(_, material)| material.clone())
+    }
+
+    pub fn resolve(&self, reference: &MaterialReference) -> Result<Rc<dyn Material>, String> {
+        match reference {
+            MaterialReference::Index(index) => self.entries.get(*index).map(|(_, material)| material.clone())
+                .ok_or(format!("Material {index} is not defined, there are {} materials", self.entries.len())),
+            MaterialReference::Named(name) => self.get(name).ok_or(format!("Material {name:?} is not defined")),
+            MaterialReference::Inline(material) => Ok(material.clone()),
+        }
+    }
+
+    /// Adds a material, unless there is a material with the same name already
+    pub fn insert(&mut self, name: String, material: Rc<dyn Material>) -> bool {
+        if self.get(&name).is_some() {
+            return false;
+        }
+        self.indices.entry(Rc::as_ptr(&material) as *const ()).or_insert(self.entries.len());
+        self.entries.push((name, material));
+        true
+    }
+
+    /// The index of a material, it is added with a new name if there is no equal material in the table yet
+    pub fn index_of(&mut self, material: &Rc<dyn Material>) -> usize {
+        let pointer = Rc::as_ptr(material) as *const ();
+        if let Some(&index) = self.indices.get(&pointer) {
+            return index;
+        }
+        for (index, (_, entry)) in self.entries.iter().enumerate().skip(self.valued) {
+            self.values.entry(serde_json::to_string(entry).unwrap_or_default()).or_insert(index);
+        }
+        self.valued = self.entries.len();
+        let value = serde_json::to_string(material).unwrap_or_default();
+        if let Some(&index) = self.values.get(&value) {
+            self.indices.insert(pointer, index);
+            return index;
+        }
+        let index = self.entries.len();
+        self.values.insert(value, index);
+        self.valued = index + 1;
+        let base = format!("{:?}-{index}", material.get_type()).to_lowercase();
+        let name = (0..).map(|i| if i == 0 { base.clone() } else { format!("{base}-{i}") })
+            .find(|name| self.get(name).is_none())
+            .unwrap();
+        self.insert(name, material.clone());
+        index
+    }
+}
+
+impl IntoIterator for MaterialTable {
+    type Item = (String, Rc<dyn Material>);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl Serialize for MaterialTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.entries.len()))?;
+        for (name, material) in &self.entries {
+            map.serialize_entry(name, material)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MaterialTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TableVisitor;
+
+        impl<'de> Visitor<'de> for TableVisitor {
+            type Value = MaterialTable;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map from names to materials")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                // The order of the file is kept, it gives the indices
+                let mut table = MaterialTable::default();
+                while let Some((name, material)) = map.next_entry::<String, Rc<dyn Material>>()? {
+                    if !table.insert(name.clone(), material) {
+                        return Err(de::Error::custom(format!("Material {name:?} is defined twice")));
+                    }
+                }
+                Ok(table)
+            }
+        }
+
+        deserializer.deserialize_map(TableVisitor)
+    }
+}
+
+/// Serializes the material of a primitive as the index in the material table of the scene that is being written,
+/// and reads an index, a name or an inline material, see `HittableList`
+/// Without a table, for example when a single primitive is written, the material is written inline
+pub mod shared {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use crate::value::material::{Material, MaterialReference, MaterialTable};
+
+    thread_local! {
+        static TABLES: RefCell<Vec<MaterialTable>> = const { RefCell::new(vec![]) };
+    }
+
+    /// Runs `f` with `table` as the table for the materials, returns the table with the materials that were added
+    pub fn with_table<T>(table: MaterialTable, f: impl FnOnce() -> T) -> (T, MaterialTable) {
+        TABLES.with_borrow_mut(|tables| tables.push(table));
+        let result = f();
+        let table = TABLES.with_borrow_mut(|tables| tables.pop()).unwrap();
+        (result, table)
+    }
+
+    /// The table that is in use, if a scene is being read or written
+    pub fn current() -> Option<MaterialTable> {
+        TABLES.with_borrow(|tables| tables.last().cloned())
+    }
+
+    pub fn is_active() -> bool {
+        TABLES.with_borrow(|tables| !tables.is_empty())
+    }
+
+    pub fn serialize<S: Serializer>(material: &Rc<dyn Material>, serializer: S) -> Result<S::Ok, S::Error> {
+        match TABLES.with_borrow_mut(|tables| tables.last_mut().map(|table| table.index_of(material))) {
+            Some(index) => serializer.serialize_u64(index as u64),
+            None => material.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rc<dyn Material>, D::Error> {
+        match MaterialReference::deserialize(deserializer)? {
+            MaterialReference::Inline(material) => Ok(material),
+            reference => TABLES.with_borrow(|tables| match tables.last() {
+                Some(table) => table.resolve(&reference),
+                None => Err("Materials can only be referred to in a scene".to_string()),
+            }).map_err(de::Error::custom),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Lambertian {
     albedo: Color,
+    /// Multiplied with the albedo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture: Option<Rc<dyn Texture>>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
-        Self { albedo }
+        Self { albedo, texture: None }
+    }
+
+    pub fn textured(albedo: Color, texture: Rc<dyn Texture>) -> Self {
+        Self { albedo, texture: Some(texture) }
     }
 }
 
@@ -41,7 +231,14 @@ impl Material for Lambertian {
         }
 
         *scattered = Ray::new(rec.p, scatter_direction);
-        *attenuation = self.albedo;
+        let albedo = match &self.texture {
+            Some(texture) => self.albedo * texture.value(rec.u, rec.v, &rec.p),
+            None => self.albedo,
+        };
+        *attenuation = match rec.color {
+            Some(color) => albedo * color,
+            None => albedo,
+        };
         true
     }
 
@@ -54,11 +251,18 @@ impl Material for Lambertian {
 pub struct Metal {
     albedo: Color,
     fuzz: f64,
+    /// Multiplied with the albedo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture: Option<Rc<dyn Texture>>,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Self {
-        Self { albedo, fuzz: if fuzz < 1.0 { fuzz } else { 1.0 } }
+        Self { albedo, fuzz: if fuzz < 1.0 { fuzz } else { 1.0 }, texture: None }
+    }
+
+    pub fn textured(albedo: Color, fuzz: f64, texture: Rc<dyn Texture>) -> Self {
+        Self { texture: Some(texture), ..Self::new(albedo, fuzz) }
     }
 }
 
@@ -68,7 +272,14 @@ impl Material for Metal {
         let reflected = Vec3::reflect(r_in.direction(), &rec.normal);
         let reflected = reflected.unit() + (self.fuzz * Vec3::random_unit());
         *scattered = Ray::new(rec.p, reflected);
-        *attenuation = self.albedo;
+        let albedo = match &self.texture {
+            Some(texture) => self.albedo * texture.value(rec.u, rec.v, &rec.p),
+            None => self.albedo,
+        };
+        *attenuation = match rec.color {
+            Some(color) => albedo * color,
+            None => albedo,
+        };
 
         scattered.direction().dot(&rec.normal) > 0.0
     }
@@ -117,6 +328,33 @@ impl Material for Dielectric {
     }
 }
 
+/// A material that only gives off light
+#[derive(Serialize, Deserialize)]
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+
+#[typetag::serde]
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _attenuation: &mut Color, _scattered: &mut Ray) -> bool {
+        false
+    }
+
+    fn get_type(&self) -> MaterialType {
+        MaterialType::DiffuseLight
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        self.emit
+    }
+}
+
 fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     let r0 = r0 * r0;
@@ -0,0 +1,96 @@
+use crate::value::color::Color;
+use crate::value::vec3::Point3;
+use serde::{Deserialize, Serialize, Serializer};
+use std::path::PathBuf;
+
+#[typetag::serde(tag = "type")]
+pub trait Texture {
+    /// The color at texture coordinates (u, v) or at point p
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+/// A texture from an image, either loaded from a file (PNG or JPEG) or embedded in a model file
+/// For files only the path is stored in the scene file, the image is loaded again when the scene is read
+#[derive(Deserialize)]
+#[serde(try_from = "ImageSource")]
+pub struct ImageTexture {
+    source: ImageSource,
+    width: usize,
+    height: usize,
+    /// Linear colors, row by row starting at the top
+    pixels: Vec<Color>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ImageSource {
+    File { path: PathBuf },
+    /// 8-bit sRGB values, row by row starting at the top
+    Embedded { width: usize, height: usize, rgb: Vec<u8> },
+}
+
+impl ImageTexture {
+    pub fn load(path: PathBuf) -> Result<Self, image::ImageError> {
+        let image = image::open(&path)?.into_rgb8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = linear_pixels(image.as_raw());
+        Ok(Self { source: ImageSource::File { path }, width, height, pixels })
+    }
+
+    /// An image that is not stored in a separate file, `rgb` has 3 bytes per pixel
+    pub fn embedded(width: usize, height: usize, rgb: Vec<u8>) -> Self {
+        let pixels = linear_pixels(&rgb);
+        Self { source: ImageSource::Embedded { width, height, rgb }, width, height, pixels }
+    }
+}
+
+/// Images are stored with sRGB gamma, the renderer works in linear space (with gamma 2)
+fn linear_pixels(rgb: &[u8]) -> Vec<Color> {
+    rgb.chunks_exact(3)
+        .map(|pixel| {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| (c as f64 / 255.0).powi(2));
+            Color::new(r, g, b)
+        })
+        .collect()
+}
+
+impl TryFrom<ImageSource> for ImageTexture {
+    type Error = String;
+
+    fn try_from(source: ImageSource) -> Result<Self, Self::Error> {
+        match source {
+            ImageSource::File { path } => ImageTexture::load(path.clone())
+                .map_err(|e| format!("Could not load texture {path:?}: {e}")),
+            ImageSource::Embedded { width, height, rgb } => {
+                if rgb.len() != width * height * 3 {
+                    return Err(format!("Embedded texture should have {} bytes, found {}", width * height * 3, rgb.len()));
+                }
+                Ok(ImageTexture::embedded(width, height, rgb))
+            }
+        }
+    }
+}
+
+impl Serialize for ImageTexture {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.source.serialize(serializer)
+    }
+}
+
+#[typetag::serde]
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.pixels.is_empty() {
+            return Color::new(1.0, 0.0, 1.0);
+        }
+        // The texture repeats, v goes from the bottom to the top of the image
+        let u = u.rem_euclid(1.0);
+        let v = 1.0 - v.rem_euclid(1.0);
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+}
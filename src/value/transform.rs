@@ -0,0 +1,234 @@
+use crate::utils::rtweekend::degrees_to_radians;
+use crate::value::ray::Ray;
+use crate::value::vec3::{Point3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::ops::Mul;
+
+pub type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// An affine transformation, stored as a 4x4 matrix together with its inverse
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "TransformDef", into = "TransformDef")]
+pub struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self { matrix: IDENTITY, inverse: IDENTITY }
+    }
+
+    /// Create a transform from a (row-major) matrix, returns None if it can not be inverted
+    pub fn from_matrix(matrix: Matrix) -> Option<Self> {
+        invert(&matrix).map(|inverse| Self { matrix, inverse })
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][3] = offset[axis as u32];
+            inverse[axis][3] = -offset[axis as u32];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Scale along each axis, all factors should be non-zero
+    pub fn scale(factors: Vec3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][axis] = factors[axis as u32];
+            inverse[axis][axis] = 1.0 / factors[axis as u32];
+        }
+        Self { matrix, inverse }
+    }
+
+    /// Rotate counter-clockwise around `axis` (Rodrigues' rotation formula)
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let matrix = [
+            [cos + x * x * (1.0 - cos), x * y * (1.0 - cos) - z * sin, x * z * (1.0 - cos) + y * sin, 0.0],
+            [y * x * (1.0 - cos) + z * sin, cos + y * y * (1.0 - cos), y * z * (1.0 - cos) - x * sin, 0.0],
+            [z * x * (1.0 - cos) - y * sin, z * y * (1.0 - cos) + x * sin, cos + z * z * (1.0 - cos), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        // The inverse of a rotation is its transpose
+        Self { matrix, inverse: transpose(&matrix) }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self { matrix: self.inverse, inverse: self.matrix }
+    }
+
+    pub fn matrix(&self) -> &Matrix {
+        &self.matrix
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.matrix == IDENTITY
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        apply(&self.matrix, p, 1.0)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        apply(&self.matrix, v, 0.0)
+    }
+
+    /// Normals are transformed with the inverse transpose, the result is not normalised
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        apply(&transpose(&self.inverse), n, 0.0)
+    }
+
+    /// Transform a ray, the direction is not normalised so distances along the ray stay the same
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new(self.point(*r.origin()), self.vector(*r.direction()))
+    }
+
+    /// Determinant of the linear (3x3) part, i.e. the change in volume
+    pub fn determinant(&self) -> f64 {
+        let m = &self.matrix;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+/// `a * b` first applies `b` and then `a`
+impl Mul for &Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Transform { matrix: multiply(&self.matrix, &rhs.matrix), inverse: multiply(&rhs.inverse, &self.inverse) }
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        &self * &rhs
+    }
+}
+
+fn apply(m: &Matrix, v: Vec3, w: f64) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z() + m[0][3] * w,
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z() + m[1][3] * w,
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z() + m[2][3] * w,
+    )
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut result = [[0.0; 4]; 4];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    result
+}
+
+/// Gauss-Jordan elimination with partial pivoting
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let mut a = *m;
+    let mut inverse = IDENTITY;
+    for column in 0..4 {
+        let pivot = (column..4)
+            .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+
+        let scale = 1.0 / a[column][column];
+        for j in 0..4 {
+            a[column][j] *= scale;
+            inverse[column][j] *= scale;
+        }
+        for row in 0..4 {
+            if row == column { continue; }
+            let factor = a[row][column];
+            for j in 0..4 {
+                a[row][j] -= factor * a[column][j];
+                inverse[row][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+/// Transforms are written as a matrix, but can also be read as a list of steps, e.g.
+/// `[{"translate": [0, 1, 0]}, {"rotate": {"axis": [0, 1, 0], "degrees": 45}}, {"scale": [2, 2, 2]}]`
+/// Steps are applied in order, so the example translates, then rotates, then scales.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TransformDef {
+    Matrix { matrix: Matrix },
+    Steps(Vec<TransformStep>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformStep {
+    Translate([f64; 3]),
+    Scale([f64; 3]),
+    Rotate { axis: [f64; 3], degrees: f64 },
+}
+
+impl TryFrom<TransformDef> for Transform {
+    type Error = String;
+
+    fn try_from(value: TransformDef) -> Result<Self, Self::Error> {
+        match value {
+            TransformDef::Matrix { matrix } => {
+                Transform::from_matrix(matrix).ok_or("Transform matrix can not be inverted".to_string())
+            }
+            TransformDef::Steps(steps) => {
+                Ok(steps.iter().rev().fold(Transform::identity(), |transform, step| {
+                    let step = match step {
+                        TransformStep::Translate([x, y, z]) => Transform::translate(Vec3::new(*x, *y, *z)),
+                        TransformStep::Scale([x, y, z]) => Transform::scale(Vec3::new(*x, *y, *z)),
+                        TransformStep::Rotate { axis: [x, y, z], degrees } => Transform::rotate(Vec3::new(*x, *y, *z), *degrees),
+                    };
+                    transform * step
+                }))
+            }
+        }
+    }
+}
+
+impl From<Transform> for TransformDef {
+    fn from(value: Transform) -> Self {
+        TransformDef::Matrix { matrix: value.matrix }
+    }
+}
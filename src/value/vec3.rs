@@ -106,6 +106,15 @@ impl Vec3 {
         )
     }
     
+    /// Returns two unit vectors that together with `self` (assumed to have unit length)
+    /// form an orthonormal basis
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let a = if self.x().abs() > 0.9 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let t = self.cross(&a).unit();
+        let b = self.cross(&t);
+        (t, b)
+    }
+
     pub fn red() -> Self { Self::new(1.0, 0.0, 0.0) }
     pub fn green() -> Self { Self::new(0.0, 1.0, 0.0) }
     pub fn blue() -> Self { Self::new(0.0, 0.0, 1.0) }